@@ -0,0 +1,409 @@
+use std::{
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::{context::ConnectionContext, util};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BanKind {
+    // matched against the user@host of a connection as it registers
+    KLine,
+    // matched against the IP address of a connection as it is accepted
+    DLine,
+}
+
+impl BanKind {
+    fn letter(&self) -> &'static str {
+        match self {
+            BanKind::KLine => "K",
+            BanKind::DLine => "D",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub mask: String,
+    pub reason: String,
+    pub set_by: String,
+    pub set_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Ban {
+    // connections that haven't registered yet only have an address to go on
+    pub fn matches(&self, conn_context: &ConnectionContext) -> bool {
+        match self.kind {
+            // usernames without ident get a '~' prefix, so user@host masks
+            // match with or without it
+            BanKind::KLine => conn_context
+                .user_host()
+                .map(|u| {
+                    util::match_mask(u, &self.mask)
+                        || u.strip_prefix('~')
+                            .map(|u| util::match_mask(u, &self.mask))
+                            .unwrap_or(false)
+                })
+                .unwrap_or(false),
+            BanKind::DLine => conn_context
+                .client_host
+                .and_then(|a| ip_matches(&self.mask, a.ip()))
+                .unwrap_or(false),
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map(|e| e <= now).unwrap_or(false)
+    }
+
+    // one ban per line, tab separated as the reason can contain spaces
+    fn serialize(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.kind.letter(),
+            self.mask,
+            self.set_at.timestamp(),
+            self.expires_at.map(|e| e.timestamp()).unwrap_or(0),
+            self.set_by,
+            self.reason
+        )
+    }
+
+    fn deserialize(line: &str) -> Option<Ban> {
+        let fields = line.splitn(6, '\t').collect::<Vec<&str>>();
+
+        let (kind, mask, set_at, expires_at, set_by, reason) = match fields.as_slice() {
+            [k, m, s, e, b, r] => (k, m, s, e, b, r),
+            _ => return None,
+        };
+
+        let kind = match *kind {
+            "K" => BanKind::KLine,
+            "D" => BanKind::DLine,
+            _ => return None,
+        };
+
+        let expires_at = match expires_at.parse::<i64>().ok()? {
+            0 => None,
+            e => Some(Utc.timestamp(e, 0)),
+        };
+
+        Some(Ban {
+            kind,
+            mask: mask.to_string(),
+            reason: reason.to_string(),
+            set_by: set_by.to_string(),
+            set_at: Utc.timestamp(set_at.parse().ok()?, 0),
+            expires_at,
+        })
+    }
+}
+
+// The K-lines and D-lines in force, written back to the ban file after every
+// change so they survive a restart. Without a path nothing is persisted.
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Vec<Ban>,
+    // every save is numbered, and the last one written is kept here so a
+    // write that has been overtaken by a later one is skipped
+    saves: u64,
+    written: Arc<Mutex<u64>>,
+}
+
+impl BanList {
+    // a missing file just means nobody has been banned yet
+    pub fn load(path: &Path) -> BanList {
+        let mut list = BanList {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return list,
+        };
+
+        for line in contents.lines().filter(|l| !l.is_empty()) {
+            match Ban::deserialize(line) {
+                Some(ban) => list.bans.push(ban),
+                None => println!("Ignoring malformed ban {} in {:?}", line, path),
+            }
+        }
+
+        list.expire();
+        list
+    }
+
+    // The list is locked by the message handler and the accept loop, so
+    // only the contents are taken here and the file is written off the
+    // runtime once the lock has gone
+    fn save(&mut self) {
+        let path = match &self.path {
+            Some(p) => p.clone(),
+            None => return,
+        };
+
+        let contents = self
+            .bans
+            .iter()
+            .map(|b| b.serialize() + "\n")
+            .collect::<String>();
+
+        self.saves += 1;
+        let save = self.saves;
+        let written = self.written.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut written = written.lock().unwrap();
+
+            if *written > save {
+                return;
+            }
+
+            // written alongside then renamed so a crash can't leave half a file
+            let temp_path = path.with_extension("tmp");
+
+            match fs::write(&temp_path, contents).and_then(|_| fs::rename(&temp_path, &path)) {
+                Ok(_) => *written = save,
+                Err(e) => println!("Error saving bans to {:?} {:?}", path, e),
+            }
+        });
+    }
+
+    fn expire(&mut self) {
+        let now = Utc::now();
+        self.bans.retain(|b| !b.is_expired(now));
+    }
+
+    // replaces any existing ban of the same kind on the same mask
+    pub fn add(
+        &mut self,
+        kind: BanKind,
+        mask: &str,
+        reason: &str,
+        set_by: &str,
+        duration_mins: Option<u64>,
+    ) -> Ban {
+        let now = Utc::now();
+
+        let ban = Ban {
+            kind,
+            mask: mask.to_string(),
+            reason: reason.to_string(),
+            set_by: set_by.to_string(),
+            set_at: now,
+            expires_at: duration_mins
+                .filter(|d| *d > 0)
+                .map(|d| now + Duration::minutes(d as i64)),
+        };
+
+        self.expire();
+        self.bans
+            .retain(|b| !(b.kind == kind && b.mask.eq_ignore_ascii_case(mask)));
+        self.bans.push(ban.clone());
+        self.save();
+
+        ban
+    }
+
+    // whether there was such a ban to remove
+    pub fn remove(&mut self, kind: BanKind, mask: &str) -> bool {
+        self.expire();
+
+        let before = self.bans.len();
+        self.bans
+            .retain(|b| !(b.kind == kind && b.mask.eq_ignore_ascii_case(mask)));
+
+        let removed = self.bans.len() != before;
+
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    pub fn list(&self, kind: BanKind) -> impl Iterator<Item = &Ban> {
+        let now = Utc::now();
+
+        self.bans
+            .iter()
+            .filter(move |b| b.kind == kind && !b.is_expired(now))
+    }
+
+    pub fn find(&self, conn_context: &ConnectionContext) -> Option<&Ban> {
+        let now = Utc::now();
+
+        self.bans
+            .iter()
+            .find(|b| !b.is_expired(now) && b.matches(conn_context))
+    }
+
+    pub fn find_dline(&self, ip: IpAddr) -> Option<&Ban> {
+        self.list(BanKind::DLine)
+            .find(|b| ip_matches(&b.mask, ip).unwrap_or(false))
+    }
+}
+
+// A D-line is either a single address or a CIDR range like 10.0.0.0/8,
+// None when the mask is neither
+pub fn ip_matches(mask: &str, ip: IpAddr) -> Option<bool> {
+    let (address, prefix_len) = match mask.split_once('/') {
+        Some((a, p)) => (a.parse::<IpAddr>().ok()?, Some(p.parse::<u32>().ok()?)),
+        None => (mask.parse::<IpAddr>().ok()?, None),
+    };
+
+    // an IPv4 client on a dual stack socket shows up as a mapped IPv6 address
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+
+    let matches = match (address, ip) {
+        (IpAddr::V4(a), IpAddr::V4(i)) => {
+            let prefix_len = prefix_len.unwrap_or(32);
+
+            if prefix_len > 32 {
+                return None;
+            }
+
+            let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+            u32::from(a) & mask == u32::from(i) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(i)) => {
+            let prefix_len = prefix_len.unwrap_or(128);
+
+            if prefix_len > 128 {
+                return None;
+            }
+
+            let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+            u128::from(a) & mask == u128::from(i) & mask
+        }
+        _ => false,
+    };
+
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("127.0.0.1", "127.0.0.1", Some(true) ; "exact_v4")]
+    #[test_case("127.0.0.1", "127.0.0.2", Some(false) ; "different_v4")]
+    #[test_case("10.0.0.0/8", "10.20.30.40", Some(true) ; "inside_v4_range")]
+    #[test_case("10.0.0.0/8", "11.0.0.1", Some(false) ; "outside_v4_range")]
+    #[test_case("0.0.0.0/0", "1.2.3.4", Some(true) ; "everything_v4")]
+    #[test_case("2001:db8::/32", "2001:db8::1", Some(true) ; "inside_v6_range")]
+    #[test_case("2001:db8::/32", "2001:db9::1", Some(false) ; "outside_v6_range")]
+    #[test_case("127.0.0.1", "::ffff:127.0.0.1", Some(true) ; "mapped_v4")]
+    #[test_case("10.0.0.0/33", "10.0.0.1", None ; "prefix_too_long")]
+    #[test_case("*@host", "10.0.0.1", None ; "not_an_address")]
+    fn ip_matches_correctly(mask: &str, ip: &str, expected: Option<bool>) {
+        assert_eq!(expected, ip_matches(mask, ip.parse().unwrap()));
+    }
+
+    #[test]
+    fn ban_list_finds_and_expires_bans() {
+        let mut bans = BanList::default();
+
+        bans.add(BanKind::KLine, "*@10.0.0.*", "spam", "admin", None);
+        bans.add(BanKind::DLine, "192.168.0.0/16", "flood", "admin", Some(60));
+
+        let connection = |client: &str| ConnectionContext {
+            client: Some(client.to_string()),
+            ..Default::default()
+        };
+
+        assert!(bans.find(&connection("JIM!~jim@10.0.0.5")).is_some());
+        assert!(bans.find(&connection("JIM!~jim@10.0.1.5")).is_none());
+        assert!(bans.find_dline("192.168.1.1".parse().unwrap()).is_some());
+        assert!(bans.find_dline("10.0.0.5".parse().unwrap()).is_none());
+
+        bans.bans[1].expires_at = Some(Utc::now() - Duration::minutes(1));
+        assert!(bans.find_dline("192.168.1.1".parse().unwrap()).is_none());
+
+        assert!(bans.remove(BanKind::KLine, "*@10.0.0.*"));
+        assert!(!bans.remove(BanKind::KLine, "*@10.0.0.*"));
+        assert!(bans.find(&connection("JIM!~jim@10.0.0.5")).is_none());
+    }
+
+    #[test_case("bob@10.0.0.5", true ; "without_tilde")]
+    #[test_case("~bob@10.0.0.5", true ; "with_tilde")]
+    #[test_case("bob@10.0.0.*", true ; "wildcard_host")]
+    #[test_case("*@10.0.0.5", true ; "wildcard_user")]
+    #[test_case("jim@10.0.0.5", false ; "different_user")]
+    fn kline_matches_user_host_correctly(mask: &str, expected: bool) {
+        let mut bans = BanList::default();
+        bans.add(BanKind::KLine, mask, "spam", "admin", None);
+
+        let connection = ConnectionContext {
+            client: Some("BOB!~bob@10.0.0.5".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(expected, bans.find(&connection).is_some());
+    }
+
+    #[test]
+    fn ban_list_masks_are_case_insensitive() {
+        let mut bans = BanList::default();
+
+        bans.add(BanKind::KLine, "*@Example.com", "spam", "admin", None);
+        bans.add(BanKind::KLine, "*@example.COM", "flood", "admin", None);
+
+        let kline = bans.list(BanKind::KLine).collect::<Vec<&Ban>>();
+        assert_eq!(1, kline.len());
+        assert_eq!("flood", kline[0].reason);
+
+        assert!(bans.remove(BanKind::KLine, "*@EXAMPLE.com"));
+        assert_eq!(0, bans.list(BanKind::KLine).count());
+    }
+
+    #[tokio::test]
+    async fn ban_list_is_saved_to_its_file() {
+        let path = std::env::temp_dir().join(format!("{}.bans", uuid::Uuid::new_v4()));
+
+        let mut bans = BanList::load(&path);
+        bans.add(BanKind::KLine, "*@10.0.0.*", "spam", "admin", None);
+        bans.add(BanKind::DLine, "192.168.0.0/16", "flood", "admin", None);
+
+        // the file is written in the background
+        for _ in 0..100 {
+            if BanList::load(&path).bans.len() == 2 {
+                break;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let masks = |list: &BanList| list.bans.iter().map(|b| b.mask.clone()).collect::<Vec<_>>();
+        assert_eq!(masks(&bans), masks(&BanList::load(&path)));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn ban_round_trips_through_file_format() {
+        let ban = Ban {
+            kind: BanKind::KLine,
+            mask: "*@example.com".to_string(),
+            reason: "go away now".to_string(),
+            set_by: "admin".to_string(),
+            set_at: Utc.timestamp(1_600_000_000, 0),
+            expires_at: Some(Utc.timestamp(1_600_003_600, 0)),
+        };
+
+        assert_eq!(Some(ban.clone()), Ban::deserialize(&ban.serialize()));
+        assert_eq!(None, Ban::deserialize("X\tmask"));
+    }
+}
@@ -0,0 +1,46 @@
+// IRCv3 capabilities this server is able to negotiate with clients
+// https://ircv3.net/specs/extensions/capability-negotiation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    AwayNotify,
+    EchoMessage,
+    InviteNotify,
+    MessageTags,
+    ServerTime,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::AwayNotify,
+        Capability::EchoMessage,
+        Capability::InviteNotify,
+        Capability::MessageTags,
+        Capability::ServerTime,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::AwayNotify => "away-notify",
+            Capability::EchoMessage => "echo-message",
+            Capability::InviteNotify => "invite-notify",
+            Capability::MessageTags => "message-tags",
+            Capability::ServerTime => "server-time",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Capability::ALL.iter().find(|c| c.name() == name).copied()
+    }
+}
+
+#[test]
+fn capability_from_name_roundtrips() {
+    for capability in &Capability::ALL {
+        assert_eq!(Some(*capability), Capability::from_name(capability.name()));
+    }
+}
+
+#[test]
+fn capability_from_name_unknown_is_none() {
+    assert_eq!(None, Capability::from_name("not-a-real-cap"));
+}
@@ -1,4 +1,6 @@
+#[cfg(test)]
 use std::collections::VecDeque;
+#[cfg(test)]
 use std::ops::AddAssign;
 
 use async_trait::async_trait;
@@ -8,11 +10,12 @@ pub trait ReceiverWrapper<T> {
     async fn receive(&mut self) -> Option<T>;
 }
 
+#[cfg(test)]
 pub struct FakeChannelReceiver<T>
 where
     T: Send + Sync,
 {
-    pub faked_messages: Box<VecDeque<T>>,
+    pub faked_messages: VecDeque<T>,
     pub receive_count: i32,
 }
 
@@ -26,6 +29,7 @@ where
     }
 }
 
+#[cfg(test)]
 #[async_trait]
 impl<T> ReceiverWrapper<T> for FakeChannelReceiver<T>
 where
@@ -1,6 +1,8 @@
+use crate::codec::LineCodec;
 use crate::error::Error::*;
 use crate::replies::Reply;
 use crate::result::Result;
+use crate::tags::Tags;
 use crate::{
     context::ServerContext,
     message_parsing::{Command, Message},
@@ -18,12 +20,13 @@ use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::net::tcp::OwnedReadHalf;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
 use uuid::Uuid;
 
 use pin_project_lite::pin_project;
 
 pub async fn run(
-    context: ServerContext,
+    context: watch::Receiver<ServerContext>,
     connection_id: &Uuid,
     stream: &mut OwnedReadHalf,
     message_sender: &Sender<Message>,
@@ -35,13 +38,16 @@ pub async fn run(
     // there also needs to be a ping loop going on that can stop this loop too
 
     let mut reader = BufReader::with_capacity(512, stream);
+    let mut codec = LineCodec::new(context.borrow().fallback_encoding);
     let mut last_pong = Instant::now();
     let mut waiting_for_pong = false;
-    let server_host = &context.server_host;
+    let server_host = context.borrow().server_host.clone();
 
     loop {
-        if waiting_for_pong && last_pong.elapsed().as_secs() > context.ping_frequency.as_secs() + 5
-        {
+        // read each time around as it can change when the settings are reloaded
+        let ping_frequency = context.borrow().ping_frequency;
+
+        if waiting_for_pong && last_pong.elapsed().as_secs() > ping_frequency.as_secs() + 5 {
             println!(
                 "No pong received, last pong received {} secs ago. Closing down listener",
                 last_pong.elapsed().as_secs()
@@ -49,7 +55,7 @@ pub async fn run(
             return Ok(());
         }
 
-        if last_pong.elapsed().as_secs() > context.ping_frequency.as_secs() {
+        if last_pong.elapsed().as_secs() > ping_frequency.as_secs() {
             println!("Sending ping");
 
             waiting_for_pong = true;
@@ -65,21 +71,48 @@ pub async fn run(
         }
 
         let raw_messages = tokio::select! {
-            raw_messages = get_messages(&mut reader) => match raw_messages {
+            raw_messages = get_messages(&mut reader, &mut codec) => match raw_messages {
                 Ok(m) => m,
-                Err(e) => match e {
-                    MessageReadingErrorStreamClosed => return Ok(()),
-                    _ => {
-                        continue;
-                    }
-                },
+                // retrying a failed read would only fail again, so treat
+                // it the same as the client hanging up
+                Err(e) => {
+                    println!("{}", e);
+                    return Ok(());
+                }
             },
             _ = shutdown_receiver.recv() => {
                 return Ok(());
             }
+            // the client sender stops once the connection has been closed by
+            // the server, as with a KILL, so there is nobody left to read for
+            _ = reply_sender.closed() => {
+                return Ok(());
+            }
         };
 
         for raw_message in &raw_messages {
+            let raw_message = match raw_message {
+                Ok(r) => r,
+                Err(MessageReadingErrorLineTooLong) => {
+                    let message = Message {
+                        tags: Tags::new(),
+                        source: None,
+                        command: Command::InputTooLong,
+                        connection_id: *connection_id,
+                    };
+
+                    if let Err(e) = message_sender.send(message).await {
+                        println!("Error forwarding message to server {:?}", e);
+                    }
+
+                    continue;
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+
             let message = match Message::from_str(raw_message, *connection_id) {
                 Ok(m) => m,
                 Err(e) => {
@@ -116,7 +149,12 @@ pub async fn run(
     }
 }
 
-async fn get_messages<T: AsyncBufRead + Unpin>(reader: &mut T) -> Result<Vec<String>> {
+// returns every line completed by this read, which may be none at all if
+// only part of a line has arrived so far
+async fn get_messages<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    codec: &mut LineCodec,
+) -> Result<Vec<Result<String>>> {
     let bytes = match reader.fill_buf().await {
         Ok(s) => Ok(s),
         Err(e) => {
@@ -137,26 +175,10 @@ async fn get_messages<T: AsyncBufRead + Unpin>(reader: &mut T) -> Result<Vec<Str
         return Err(MessageReadingErrorStreamClosed);
     }
 
-    match std::str::from_utf8(bytes) {
-        Ok(s) => {
-            let raw_payload = s.to_owned();
-
-            // map to owned String so the ownership can be moved out of this function scope
-            let mut split_messages: Vec<String> =
-                raw_payload.split("\r\n").map(|s| s.to_string()).collect();
-
-            if split_messages.len() <= 1 {
-                Err(MessageReadingErrorNoMessageSeparatorProvided)
-            } else if split_messages.last().unwrap_or(&"BLAH".to_string()) != &format!("") {
-                Err(MessageReadingErrorLastMessageMissingSeparator)
-            } else {
-                split_messages.truncate(split_messages.len() - 1);
-                reader.consume(bytes_read);
-                Ok(split_messages)
-            }
-        }
-        Err(_) => Err(MessageReadingErrorNotUtf8),
-    }
+    let lines = codec.decode(bytes);
+    reader.consume(bytes_read);
+
+    Ok(lines)
 }
 
 #[tokio::test]
@@ -169,9 +191,11 @@ async fn get_messages_reads_from_buffer() {
         faked_responses,
     };
 
-    let result = get_messages(&mut faked_bufreader).await.unwrap();
+    let result = get_messages(&mut faked_bufreader, &mut LineCodec::default())
+        .await
+        .unwrap();
     assert_eq!(1, result.len());
-    assert_eq!("Hello world", result.first().unwrap());
+    assert_eq!(&Ok("Hello world".to_string()), result.first().unwrap());
     assert_eq!(0, faked_bufreader.fake_buffer.len());
 }
 
@@ -185,34 +209,41 @@ async fn get_messages_multiplemessages_reads_from_buffer() {
         faked_responses,
     };
 
-    let result = get_messages(&mut faked_bufreader).await.unwrap();
+    let result = get_messages(&mut faked_bufreader, &mut LineCodec::default())
+        .await
+        .unwrap();
     assert_eq!(2, result.len());
-    assert_eq!("Hello world", result.first().unwrap());
-    assert_eq!("Foobar", result[1]);
+    assert_eq!(&Ok("Hello world".to_string()), result.first().unwrap());
+    assert_eq!(Ok("Foobar".to_string()), result[1]);
     assert_eq!(0, faked_bufreader.fake_buffer.len());
 }
 
 #[tokio::test]
-async fn get_messages_multiplemessages_noterminator_errors() {
-    let fake_buffer = b"Hello world\r\nFoobar".to_vec();
+async fn get_messages_partialmessage_is_buffered_until_terminated() {
+    let fake_buffer = b"Hello world\r\nFoobar\n".to_vec();
     let mut faked_responses = VecDeque::new();
-    faked_responses.push_back(19);
+    faked_responses.push_back(16);
+    faked_responses.push_back(4);
     let mut faked_bufreader = FakeBufReader {
         fake_buffer,
         faked_responses,
     };
+    let mut codec = LineCodec::default();
 
-    let result = get_messages(&mut faked_bufreader)
+    let result = get_messages(&mut faked_bufreader, &mut codec)
         .await
-        .expect_err("Testing expect an error to be returned here");
-    assert_eq!(
-        "Error reading message(s), last message is missing separator",
-        result.to_string()
-    );
+        .unwrap();
+    assert_eq!(vec![Ok("Hello world".to_string())], result);
+
+    let result = get_messages(&mut faked_bufreader, &mut codec)
+        .await
+        .unwrap();
+    assert_eq!(vec![Ok("Foobar".to_string())], result);
+    assert_eq!(0, faked_bufreader.fake_buffer.len());
 }
 
 #[tokio::test]
-async fn get_messages_nolineterminator_errors() {
+async fn get_messages_nolineterminator_returns_nothing_yet() {
     let fake_buffer = b"Hello world".to_vec();
     let mut faked_responses = VecDeque::new();
     faked_responses.push_back(11);
@@ -221,13 +252,11 @@ async fn get_messages_nolineterminator_errors() {
         faked_responses,
     };
 
-    let result = get_messages(&mut faked_bufreader)
+    let result = get_messages(&mut faked_bufreader, &mut LineCodec::default())
         .await
-        .expect_err("Testing expect an error to be returned here");
-    assert_eq!(
-        "Error reading message(s), no message separator provided",
-        result.to_string()
-    );
+        .unwrap();
+    assert!(result.is_empty());
+    assert_eq!(0, faked_bufreader.fake_buffer.len());
 }
 
 #[tokio::test]
@@ -240,7 +269,7 @@ async fn get_messages_emptybuffer_errors() {
         faked_responses,
     };
 
-    get_messages(&mut faked_bufreader)
+    get_messages(&mut faked_bufreader, &mut LineCodec::default())
         .await
         .expect_err("Testing expect an error to be returned here");
 }
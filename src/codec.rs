@@ -0,0 +1,231 @@
+use serde_derive::Deserialize;
+
+use crate::error::Error::*;
+use crate::result::Result;
+
+// 512 bytes for the message itself including the CRLF (RFC 1459 2.3)
+pub const MAX_LINE_LEN: usize = 512;
+// plus up to 8191 bytes of tags including the leading "@" and the trailing space
+// https://ircv3.net/specs/extensions/message-tags#size-limit
+pub const MAX_TAGS_LEN: usize = 8191;
+
+// How to decode a line that is not valid UTF-8, lines that are valid UTF-8
+// are always taken as they are
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FallbackEncoding {
+    // reject the line, this is advertised to clients as UTF8ONLY
+    Strict,
+    Latin1,
+    #[default]
+    Cp1252,
+    // swap anything invalid for U+FFFD
+    Lossy,
+}
+
+// Splits a stream of bytes into lines, buffering partial lines across reads.
+// Lines may be terminated by either CRLF or a bare LF.
+#[derive(Default)]
+pub struct LineCodec {
+    buffer: Vec<u8>,
+    discarding: bool,
+    fallback: FallbackEncoding,
+}
+
+impl LineCodec {
+    pub fn new(fallback: FallbackEncoding) -> Self {
+        LineCodec {
+            fallback,
+            ..Default::default()
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Result<String>> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut lines = vec![];
+
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=pos).collect();
+
+            // the start of this line was already thrown away, see below
+            if self.discarding {
+                self.discarding = false;
+                lines.push(Err(MessageReadingErrorLineTooLong));
+                continue;
+            }
+
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            // empty messages are silently ignored
+            if line.is_empty() {
+                continue;
+            }
+
+            if is_too_long(&line) {
+                lines.push(Err(MessageReadingErrorLineTooLong));
+                continue;
+            }
+
+            lines.push(decode_line(line, self.fallback));
+        }
+
+        // once a partial line can no longer be valid there is no point holding
+        // on to it, drop everything up until the next line starts instead
+        if self.buffer.len() > MAX_LINE_LEN + MAX_TAGS_LEN {
+            self.buffer.clear();
+            self.discarding = true;
+        }
+
+        lines
+    }
+}
+
+fn decode_line(line: Vec<u8>, fallback: FallbackEncoding) -> Result<String> {
+    let bytes = match String::from_utf8(line) {
+        Ok(s) => return Ok(s),
+        Err(e) => e.into_bytes(),
+    };
+
+    match fallback {
+        FallbackEncoding::Strict => Err(MessageReadingErrorNotUtf8),
+        FallbackEncoding::Latin1 => Ok(bytes.iter().map(|b| *b as char).collect()),
+        FallbackEncoding::Cp1252 => Ok(bytes.iter().map(|b| cp1252_char(*b)).collect()),
+        FallbackEncoding::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+    }
+}
+
+// CP1252 is Latin-1 apart from 0x80-0x9F, the five bytes it leaves
+// undefined there are passed through as their C1 controls
+fn cp1252_char(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}',
+        '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}',
+        '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}',
+        '\u{178}',
+    ];
+
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+// the line passed in has already had its CRLF removed
+fn is_too_long(line: &[u8]) -> bool {
+    let (tags, message) = match line.first() {
+        Some(b'@') => match line.iter().position(|b| *b == b' ') {
+            Some(pos) => line.split_at(pos + 1),
+            None => (line, &[][..]),
+        },
+        _ => (&[][..], line),
+    };
+
+    tags.len() > MAX_TAGS_LEN || message.len() + 2 > MAX_LINE_LEN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn decode_partial_lines_are_buffered_across_reads() {
+        let mut codec = LineCodec::default();
+
+        assert_eq!(
+            vec![Ok("NICK joe".to_string())],
+            codec.decode(b"NICK joe\r\nUSER joe 0 * :J")
+        );
+        assert_eq!(
+            vec![Ok("USER joe 0 * :Joe".to_string())],
+            codec.decode(b"oe\r\n")
+        );
+    }
+
+    #[test]
+    fn decode_accepts_bare_lf_and_ignores_empty_lines() {
+        let mut codec = LineCodec::default();
+
+        assert_eq!(
+            vec![Ok("PING a".to_string()), Ok("PING b".to_string())],
+            codec.decode(b"PING a\n\r\n\nPING b\r\n")
+        );
+    }
+
+    #[test]
+    fn decode_invalid_utf8_only_rejects_that_line() {
+        let mut codec = LineCodec::new(FallbackEncoding::Strict);
+
+        assert_eq!(
+            vec![
+                Err(MessageReadingErrorNotUtf8),
+                Ok("QUIT :caf\u{e9}".to_string())
+            ],
+            codec.decode(b"NICK Ren\xe9\r\nQUIT :caf\xc3\xa9\r\n")
+        );
+    }
+
+    #[test_case(FallbackEncoding::Latin1, "QUIT :Ren\u{e9} \u{80}" ; "latin1")]
+    #[test_case(FallbackEncoding::Cp1252, "QUIT :Ren\u{e9} \u{20AC}" ; "cp1252")]
+    #[test_case(FallbackEncoding::Lossy, "QUIT :Ren\u{FFFD} \u{FFFD}" ; "lossy")]
+    fn decode_invalid_utf8_uses_fallback(fallback: FallbackEncoding, expected: &str) {
+        let mut codec = LineCodec::new(fallback);
+
+        assert_eq!(
+            vec![Ok(expected.to_string())],
+            codec.decode(b"QUIT :Ren\xe9 \x80\r\n")
+        );
+    }
+
+    #[test]
+    fn decode_line_over_limit_errors() {
+        let mut codec = LineCodec::default();
+        let line = format!("PRIVMSG #foo :{}\r\nPING a\r\n", "a".repeat(MAX_LINE_LEN));
+
+        assert_eq!(
+            vec![
+                Err(MessageReadingErrorLineTooLong),
+                Ok("PING a".to_string())
+            ],
+            codec.decode(line.as_bytes())
+        );
+    }
+
+    #[test]
+    fn decode_tags_have_their_own_limit() {
+        let mut codec = LineCodec::default();
+        let line = format!("@+foo={} PRIVMSG #foo :hi\r\n", "a".repeat(1000));
+
+        assert_eq!(
+            1,
+            codec
+                .decode(line.as_bytes())
+                .iter()
+                .filter(|l| l.is_ok())
+                .count()
+        );
+    }
+
+    #[test]
+    fn decode_unterminated_line_over_limit_is_discarded() {
+        let mut codec = LineCodec::default();
+        let junk = "a".repeat(MAX_LINE_LEN + MAX_TAGS_LEN + 1);
+
+        assert!(codec.decode(junk.as_bytes()).is_empty());
+        assert!(codec.buffer.is_empty());
+        assert!(codec.decode(junk.as_bytes()).is_empty());
+
+        assert_eq!(
+            vec![
+                Err(MessageReadingErrorLineTooLong),
+                Ok("PING a".to_string())
+            ],
+            codec.decode(b"aaa\r\nPING a\r\n")
+        );
+    }
+}
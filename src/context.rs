@@ -1,18 +1,110 @@
-use std::{collections::HashSet, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::{
+    bans::BanList,
+    capabilities::Capability,
+    codec::FallbackEncoding,
+    oper::{OperBlock, Privilege},
+    settings::Settings,
+    util,
+};
+
 #[derive(Clone)]
 pub struct ServerContext {
     pub start_time: DateTime<Utc>,
     pub server_host: String,
+    pub port: u32,
+    pub network_name: String,
     pub version: String,
     pub ping_frequency: Duration,
     pub motd_lines: Vec<String>,
+    pub nick_len: usize,
+    pub channel_len: usize,
+    pub topic_len: usize,
+    pub kick_len: usize,
+    pub away_len: usize,
+    pub max_targets: usize,
+    pub fallback_encoding: FallbackEncoding,
+    pub max_list_entries: usize,
+    pub invite_timeout: Duration,
+    pub opers: Vec<OperBlock>,
+    // shared with the accept loop, which turns away D-lined addresses
+    pub bans: Arc<Mutex<BanList>>,
+}
+
+impl ServerContext {
+    pub fn new(settings: &Settings) -> ServerContext {
+        let mut context = ServerContext {
+            start_time: Utc::now(),
+            server_host: settings.host.clone(),
+            port: settings.port,
+            network_name: String::new(),
+            version: "0.0.1".to_string(),
+            ping_frequency: Duration::default(),
+            motd_lines: vec![],
+            nick_len: 0,
+            channel_len: 0,
+            topic_len: 0,
+            kick_len: 0,
+            away_len: 0,
+            max_targets: 0,
+            fallback_encoding: settings.fallback_encoding,
+            max_list_entries: 0,
+            invite_timeout: Duration::default(),
+            opers: vec![],
+            bans: Arc::default(),
+        };
+
+        context.reload(settings);
+        context
+    }
+
+    // Takes on everything that can be changed while running. The host and
+    // port were bound at startup so they are left alone, what was asked of
+    // them is returned instead.
+    pub fn reload(&mut self, settings: &Settings) -> Vec<String> {
+        let mut not_reloaded = vec![];
+
+        if settings.host != self.server_host {
+            not_reloaded.push(format!("host (still {})", self.server_host));
+        }
+
+        if settings.port != self.port {
+            not_reloaded.push(format!("port (still {})", self.port));
+        }
+
+        self.network_name = settings.network_name.clone();
+        self.ping_frequency = Duration::from_secs(settings.ping_frequency_secs);
+        self.motd_lines = settings.motd_lines.clone();
+        self.nick_len = settings.nick_len;
+        self.channel_len = settings.channel_len;
+        self.topic_len = settings.topic_len;
+        self.kick_len = settings.kick_len;
+        self.away_len = settings.away_len;
+        self.max_targets = settings.max_targets;
+        // only connections made from now on pick up a new encoding
+        self.fallback_encoding = settings.fallback_encoding;
+        self.max_list_entries = settings.max_list_entries;
+        self.invite_timeout = Duration::from_secs(settings.invite_timeout_secs);
+        self.opers = settings.opers.clone();
+
+        // the bans are saved as they change, so rereading the file loses
+        // nothing and picks up any edits made to it by hand
+        *self.bans.lock().unwrap() = BanList::load(Path::new(&settings.ban_file));
+
+        not_reloaded
+    }
 }
 
-#[derive(Default)]
 pub struct ConnectionContext {
     pub connection_id: Uuid,
     pub client: Option<String>,
@@ -20,8 +112,364 @@ pub struct ConnectionContext {
     pub user: Option<String>,
     pub real_name: Option<String>,
     pub client_host: Option<SocketAddr>,
+    // what client_host reverse resolved to, if it did
+    pub hostname: Option<String>,
+    pub registration: RegistrationState,
+    pub capabilities: HashSet<Capability>,
+    pub cap_negotiating: bool,
+    pub signon_time: DateTime<Utc>,
+    // the last time a message was sent, for the idle time in WHOIS
+    pub last_active: DateTime<Utc>,
+    pub operator: bool,
+    // what the OPER block used gave access to
+    pub privileges: HashSet<Privilege>,
+    // an OPER password is being checked, only one is at a time
+    pub oper_pending: bool,
+    pub away: Option<String>,
+}
+
+impl Default for ConnectionContext {
+    fn default() -> Self {
+        ConnectionContext {
+            connection_id: Uuid::default(),
+            client: None,
+            nick: None,
+            user: None,
+            real_name: None,
+            client_host: None,
+            hostname: None,
+            registration: RegistrationState::default(),
+            capabilities: HashSet::new(),
+            cap_negotiating: false,
+            signon_time: Utc::now(),
+            last_active: Utc::now(),
+            operator: false,
+            privileges: HashSet::new(),
+            away: None,
+            oper_pending: false,
+        }
+    }
+}
+
+impl ConnectionContext {
+    // the address is only shown when it didn't resolve to a hostname
+    pub fn host(&self) -> String {
+        match (&self.hostname, self.client_host) {
+            (Some(hostname), _) => hostname.clone(),
+            (None, Some(addr)) => addr.ip().to_string(),
+            (None, None) => "localhost".to_string(),
+        }
+    }
+
+    // the user@host part of the hostmask, which is what K-lines match
+    pub fn user_host(&self) -> Option<&str> {
+        self.client
+            .as_ref()
+            .and_then(|c| c.split_once('!'))
+            .map(|(_, u)| u)
+    }
+}
+
+// the high water marks reported by LUSERS, kept since the server started
+#[derive(Default)]
+pub struct ServerStats {
+    pub connections_received: u32,
+    pub max_connections: u32,
+    pub max_users: u32,
+}
+
+// how many departed users WHOWAS remembers before the oldest are forgotten
+pub const WHOWAS_HISTORY_LEN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhowasEntry {
+    pub nick: String,
+    pub user: String,
+    pub host: String,
+    pub real_name: String,
+    pub departed_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct WhowasHistory {
+    entries: VecDeque<WhowasEntry>,
+}
+
+impl WhowasHistory {
+    // users that never got as far as a nick aren't worth remembering
+    pub fn record(&mut self, conn_context: &ConnectionContext) {
+        let nick = match &conn_context.nick {
+            Some(n) => n.clone(),
+            None => return,
+        };
+
+        if self.entries.len() == WHOWAS_HISTORY_LEN {
+            self.entries.pop_back();
+        }
+
+        self.entries.push_front(WhowasEntry {
+            nick,
+            user: conn_context.user.clone().unwrap_or_default(),
+            host: conn_context.host(),
+            real_name: conn_context.real_name.clone().unwrap_or_default(),
+            departed_at: Utc::now(),
+        });
+    }
+
+    // most recent first
+    pub fn find(&self, nick: &str) -> impl Iterator<Item = &WhowasEntry> {
+        let nick = nick.to_lowercase();
+
+        self.entries
+            .iter()
+            .filter(move |e| e.nick.to_lowercase() == nick)
+    }
+}
+
+// a connection only becomes a fully fledged user once both NICK and USER
+// have been received, in whichever order the client chooses to send them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RegistrationState {
+    #[default]
+    Unregistered,
+    NickOnly,
+    UserOnly,
+    Registered,
+}
+
+impl RegistrationState {
+    pub fn with_nick(self) -> Self {
+        match self {
+            RegistrationState::Unregistered | RegistrationState::NickOnly => {
+                RegistrationState::NickOnly
+            }
+            RegistrationState::UserOnly | RegistrationState::Registered => {
+                RegistrationState::Registered
+            }
+        }
+    }
+
+    pub fn with_user(self) -> Self {
+        match self {
+            RegistrationState::Unregistered | RegistrationState::UserOnly => {
+                RegistrationState::UserOnly
+            }
+            RegistrationState::NickOnly | RegistrationState::Registered => {
+                RegistrationState::Registered
+            }
+        }
+    }
 }
 
 pub struct ChannelContext {
-    pub members: HashSet<Uuid>,
+    // as it was first joined, the map it is kept in is keyed by the
+    // casefolded name so this is what gets shown
+    pub name: String,
+    pub members: HashMap<Uuid, MemberStatus>,
+    pub topic: Option<ChannelTopic>,
+    pub modes: ChannelModes,
+    pub created_at: DateTime<Utc>,
+    // who has been invited in, and when
+    pub invites: HashMap<Uuid, DateTime<Utc>>,
+}
+
+impl ChannelContext {
+    pub fn new(name: &str) -> Self {
+        ChannelContext {
+            name: name.to_string(),
+            members: HashMap::new(),
+            topic: None,
+            modes: ChannelModes::default(),
+            created_at: Utc::now(),
+            invites: HashMap::new(),
+        }
+    }
+
+    pub fn invite(&mut self, connection_id: Uuid, timeout: Duration) {
+        self.invites
+            .retain(|_, invited_at| !has_expired(invited_at, timeout));
+        self.invites.insert(connection_id, Utc::now());
+    }
+
+    pub fn is_invited(&self, connection_id: &Uuid, timeout: Duration) -> bool {
+        match self.invites.get(connection_id) {
+            Some(invited_at) => !has_expired(invited_at, timeout),
+            None => false,
+        }
+    }
+
+    pub fn status_of(&self, connection_id: &Uuid) -> Option<MemberStatus> {
+        self.members.get(connection_id).copied()
+    }
+}
+
+fn has_expired(invited_at: &DateTime<Utc>, timeout: Duration) -> bool {
+    match chrono::Duration::from_std(timeout) {
+        Ok(timeout) => Utc::now() - *invited_at > timeout,
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChannelModes {
+    pub invite_only: bool,
+    pub moderated: bool,
+    pub no_external: bool,
+    pub private: bool,
+    pub secret: bool,
+    pub topic_lock: bool,
+    pub key: Option<String>,
+    pub limit: Option<usize>,
+    pub bans: Vec<ListEntry>,
+    pub ban_exceptions: Vec<ListEntry>,
+    pub invite_exceptions: Vec<ListEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListEntry {
+    pub mask: String,
+    pub set_by: String,
+    pub set_at: DateTime<Utc>,
+}
+
+impl ChannelModes {
+    // the modes that are simply on or off
+    pub fn flag_mut(&mut self, mode: char) -> Option<&mut bool> {
+        match mode {
+            'i' => Some(&mut self.invite_only),
+            'm' => Some(&mut self.moderated),
+            'n' => Some(&mut self.no_external),
+            'p' => Some(&mut self.private),
+            's' => Some(&mut self.secret),
+            't' => Some(&mut self.topic_lock),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self, mode: char) -> Option<&Vec<ListEntry>> {
+        match mode {
+            'b' => Some(&self.bans),
+            'e' => Some(&self.ban_exceptions),
+            'I' => Some(&self.invite_exceptions),
+            _ => None,
+        }
+    }
+
+    pub fn list_mut(&mut self, mode: char) -> Option<&mut Vec<ListEntry>> {
+        match mode {
+            'b' => Some(&mut self.bans),
+            'e' => Some(&mut self.ban_exceptions),
+            'I' => Some(&mut self.invite_exceptions),
+            _ => None,
+        }
+    }
+
+    // MAXLIST is shared between all of the lists
+    pub fn list_entries(&self) -> usize {
+        self.bans.len() + self.ban_exceptions.len() + self.invite_exceptions.len()
+    }
+
+    pub fn is_banned(&self, client: &str) -> bool {
+        let matches =
+            |list: &Vec<ListEntry>| list.iter().any(|e| util::match_mask(client, &e.mask));
+
+        matches(&self.bans) && !matches(&self.ban_exceptions)
+    }
+
+    pub fn is_invite_excepted(&self, client: &str) -> bool {
+        self.invite_exceptions
+            .iter()
+            .any(|e| util::match_mask(client, &e.mask))
+    }
+
+    // the channel type shown in RPL_NAMREPLY
+    pub fn names_symbol(&self) -> char {
+        match (self.secret, self.private) {
+            (true, _) => '@',
+            (false, true) => '*',
+            (false, false) => '=',
+        }
+    }
+
+    // the key is only given out to those who could already join
+    pub fn mode_string(&self, show_key: bool) -> (String, Vec<String>) {
+        let flags = [
+            ('i', self.invite_only),
+            ('m', self.moderated),
+            ('n', self.no_external),
+            ('p', self.private),
+            ('s', self.secret),
+            ('t', self.topic_lock),
+        ];
+
+        let mut mode_string = String::from("+");
+        let mut arguments = vec![];
+
+        for (mode, set) in flags {
+            if set {
+                mode_string.push(mode);
+            }
+        }
+
+        if let Some(key) = &self.key {
+            mode_string.push('k');
+            arguments.push(match show_key {
+                true => key.clone(),
+                false => "*".to_string(),
+            });
+        }
+
+        if let Some(limit) = self.limit {
+            mode_string.push('l');
+            arguments.push(limit.to_string());
+        }
+
+        (mode_string, arguments)
+    }
+}
+
+// a member can be both an operator and voiced at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MemberStatus {
+    pub operator: bool,
+    pub voice: bool,
+}
+
+impl MemberStatus {
+    // only the highest prefix is shown, as multi-prefix isn't supported
+    pub fn prefix(&self) -> &'static str {
+        match (self.operator, self.voice) {
+            (true, _) => "@",
+            (false, true) => "+",
+            (false, false) => "",
+        }
+    }
+}
+
+pub struct ChannelTopic {
+    pub text: String,
+    pub set_by: String,
+    pub set_at: DateTime<Utc>,
+}
+
+#[test]
+fn whowas_history_is_bounded_and_most_recent_first() {
+    let mut history = WhowasHistory::default();
+
+    for i in 0..WHOWAS_HISTORY_LEN + 5 {
+        history.record(&ConnectionContext {
+            nick: Some("JIM".to_string()),
+            user: Some(i.to_string()),
+            ..Default::default()
+        });
+    }
+
+    let users = history
+        .find("jim")
+        .map(|e| e.user.clone())
+        .collect::<Vec<String>>();
+
+    assert_eq!(WHOWAS_HISTORY_LEN, users.len());
+    assert_eq!((WHOWAS_HISTORY_LEN + 4).to_string(), users[0]);
+    assert_eq!(0, history.find("BOB").count());
 }
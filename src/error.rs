@@ -1,38 +1,16 @@
-use std::fmt::Debug;
 use std::fmt::Display;
-use std::io;
 
 // should these be separate enums? what is idiomatic way to manage a large
 // error enum?
 #[derive(Debug, PartialEq)]
 pub enum Error {
     MessageReadingErrorNotUtf8,
-    MessageReadingErrorNoMessageSeparatorProvided,
-    MessageReadingErrorLastMessageMissingSeparator,
+    MessageReadingErrorLineTooLong,
     MessageReadingErrorStreamClosed,
     MessageReadingErrorIoFailure,
     MessageParsingErrorMissingCommand,
     UnableToBindToPort(u32),
-}
-
-// there isn't an impl for PartialEq for io::Error (probably for good reason)
-// but we can wrap around it using "new type" pattern and impl the traits
-// we need to fit with the rest of the enum above
-// the type its wrapping over needs to be declared as pub to be used "publicly"
-pub struct IoError(pub io::Error);
-
-// this is sufficient we just want the Error enum to impl PartialEq for unit testing
-// so we can just use assert_eq! against an expected struct
-impl PartialEq for IoError {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.kind() == other.0.kind()
-    }
-}
-
-impl Debug for IoError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("IoError").field(&self.0).finish()
-    }
+    InvalidSettings(String),
 }
 
 impl Display for Error {
@@ -41,14 +19,8 @@ impl Display for Error {
             Error::MessageReadingErrorNotUtf8 => {
                 write!(f, "Error reading message(s), must be valid UTF-8")
             }
-            Error::MessageReadingErrorNoMessageSeparatorProvided => {
-                write!(f, "Error reading message(s), no message separator provided")
-            }
-            Error::MessageReadingErrorLastMessageMissingSeparator => {
-                write!(
-                    f,
-                    "Error reading message(s), last message is missing separator"
-                )
+            Error::MessageReadingErrorLineTooLong => {
+                write!(f, "Error reading message(s), line is too long")
             }
             Error::MessageReadingErrorStreamClosed => {
                 write!(f, "Error reading message(s), stream is closed")
@@ -62,6 +34,9 @@ impl Display for Error {
             Error::UnableToBindToPort(port) => {
                 write!(f, "Unable to bind server to port {}", port)
             }
+            Error::InvalidSettings(problem) => {
+                write!(f, "Invalid settings, {}", problem)
+            }
         }
     }
 }
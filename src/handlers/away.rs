@@ -0,0 +1,156 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{
+    capabilities::Capability,
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    tags::server_tags,
+    util,
+};
+
+pub fn handle_away(
+    server_host: &str,
+    connection_id: Uuid,
+    connections: &mut HashMap<Uuid, ConnectionContext>,
+    channels: &HashMap<String, ChannelContext>,
+    message: &Option<String>,
+    away_len: usize,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    // an empty message is the same as none, both mean no longer away
+    let message = message
+        .as_ref()
+        .filter(|m| !m.is_empty())
+        .map(|m| util::truncate(m, away_len));
+
+    let conn_context = connections.get_mut(&connection_id)?;
+    conn_context.away = message.clone();
+
+    let nick = conn_context.nick.clone().unwrap_or_default();
+    let client = conn_context.client.clone().unwrap_or_default();
+
+    let reply = match message {
+        Some(_) => Reply::NowAway {
+            server_host: server_host.to_owned(),
+            nick,
+        },
+        None => Reply::UnAway {
+            server_host: server_host.to_owned(),
+            nick,
+        },
+    };
+
+    let mut map = HashMap::new();
+    map.insert(connection_id, vec![reply]);
+
+    let away_reply = Reply::AwayNotify { client, message }.with_tags(server_tags());
+
+    // everyone sharing a channel who asked to be told about it
+    let mut recipients = HashSet::new();
+
+    for channel in channels.values() {
+        if channel.members.contains_key(&connection_id) {
+            recipients.extend(channel.members.keys());
+        }
+    }
+
+    for recipient in recipients {
+        if recipient == &connection_id {
+            continue;
+        }
+
+        let wants_notify = connections
+            .get(recipient)
+            .map(|c| c.capabilities.contains(&Capability::AwayNotify))
+            .unwrap_or(false);
+
+        if wants_notify {
+            map.insert(*recipient, vec![away_reply.clone()]);
+        }
+    }
+
+    Some(map)
+}
+
+#[test]
+fn handle_away_notifies_channel_peers_with_capability() {
+    use crate::context::MemberStatus;
+
+    let away_id = Uuid::new_v4();
+    let notify_id = Uuid::new_v4();
+    let plain_id = Uuid::new_v4();
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        away_id,
+        ConnectionContext {
+            connection_id: away_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        notify_id,
+        ConnectionContext {
+            connection_id: notify_id,
+            capabilities: vec![Capability::AwayNotify].into_iter().collect(),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        plain_id,
+        ConnectionContext {
+            connection_id: plain_id,
+            ..Default::default()
+        },
+    );
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    for id in &[away_id, notify_id, plain_id] {
+        chan_ctx.members.insert(*id, MemberStatus::default());
+    }
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let replies = handle_away(
+        "localhost",
+        away_id,
+        &mut connections,
+        &channels,
+        &Some("gone fishing".to_string()),
+        200,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::NowAway {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+        }]),
+        replies.get(&away_id)
+    );
+    assert!(replies.contains_key(&notify_id));
+    assert!(!replies.contains_key(&plain_id));
+    assert_eq!(Some("gone fishing".to_string()), connections[&away_id].away);
+
+    let replies = handle_away(
+        "localhost",
+        away_id,
+        &mut connections,
+        &channels,
+        &Some("".to_string()),
+        200,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::UnAway {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+        }]),
+        replies.get(&away_id)
+    );
+    assert_eq!(None, connections[&away_id].away);
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{
+    capabilities::Capability,
+    context::{ConnectionContext, RegistrationState, ServerContext},
+    handlers::welcome::advance_registration,
+    replies::Reply,
+};
+
+pub fn handle_cap(
+    server_context: &ServerContext,
+    server_host: &str,
+    subcommand: &Option<String>,
+    arguments: &Option<String>,
+    conn_context: &mut ConnectionContext,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let nick = conn_context.nick.clone().unwrap_or_else(|| "*".to_string());
+
+    let subcommand = match subcommand {
+        Some(s) => s,
+        None => {
+            let mut map = HashMap::new();
+            map.insert(
+                conn_context.connection_id,
+                vec![Reply::ErrNeedMoreParams {
+                    server_host: server_host.to_string(),
+                    nick,
+                    command: "CAP".to_string(),
+                }],
+            );
+            return Some(map);
+        }
+    };
+
+    let is_registered = conn_context.registration == RegistrationState::Registered;
+
+    let reply = match subcommand.as_str() {
+        "LS" => {
+            // negotiation started before registration holds it open until CAP END
+            if !is_registered {
+                conn_context.cap_negotiating = true;
+            }
+
+            let capabilities = Capability::ALL
+                .iter()
+                .map(|c| c.name())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            Reply::Cap {
+                server_host: server_host.to_string(),
+                nick,
+                subcommand: "LS".to_string(),
+                capabilities,
+            }
+        }
+        "LIST" => {
+            let mut capabilities = conn_context
+                .capabilities
+                .iter()
+                .map(|c| c.name())
+                .collect::<Vec<&str>>();
+            capabilities.sort_unstable();
+
+            Reply::Cap {
+                server_host: server_host.to_string(),
+                nick,
+                subcommand: "LIST".to_string(),
+                capabilities: capabilities.join(" "),
+            }
+        }
+        "REQ" => {
+            if !is_registered {
+                conn_context.cap_negotiating = true;
+            }
+
+            let requested = arguments.clone().unwrap_or_default();
+
+            // a request is all or nothing, if any of the capabilities are
+            // unknown then none of them are applied
+            let changes = requested
+                .split_whitespace()
+                .map(|c| match c.strip_prefix('-') {
+                    Some(name) => Capability::from_name(name).map(|c| (c, false)),
+                    None => Capability::from_name(c).map(|c| (c, true)),
+                })
+                .collect::<Option<Vec<(Capability, bool)>>>();
+
+            let subcommand = match changes {
+                Some(changes) => {
+                    for (capability, enable) in changes {
+                        match enable {
+                            true => conn_context.capabilities.insert(capability),
+                            false => conn_context.capabilities.remove(&capability),
+                        };
+                    }
+
+                    "ACK"
+                }
+                None => "NAK",
+            };
+
+            Reply::Cap {
+                server_host: server_host.to_string(),
+                nick,
+                subcommand: subcommand.to_string(),
+                capabilities: requested,
+            }
+        }
+        "END" => {
+            conn_context.cap_negotiating = false;
+
+            if is_registered {
+                return None;
+            }
+
+            return advance_registration(
+                server_context,
+                conn_context,
+                RegistrationState::Registered,
+            );
+        }
+        _ => Reply::ErrInvalidCapCmd {
+            server_host: server_host.to_string(),
+            nick,
+            subcommand: subcommand.to_string(),
+        },
+    };
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, vec![reply]);
+
+    Some(map)
+}
+
+#[test]
+fn handle_cap_registration_is_held_until_end() {
+    let context = ServerContext::new(&crate::settings::test_settings());
+
+    let mut conn_ctx = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        user: Some("jim".to_string()),
+        registration: RegistrationState::NickOnly,
+        ..Default::default()
+    };
+
+    handle_cap(
+        &context,
+        "localhost",
+        &Some("LS".to_string()),
+        &Some("302".to_string()),
+        &mut conn_ctx,
+    );
+
+    let replies = handle_cap(
+        &context,
+        "localhost",
+        &Some("REQ".to_string()),
+        &Some("echo-message".to_string()),
+        &mut conn_ctx,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::Cap {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            subcommand: "ACK".to_string(),
+            capabilities: "echo-message".to_string(),
+        }]),
+        replies.get(&conn_ctx.connection_id)
+    );
+
+    let next = conn_ctx.registration.with_user();
+    assert_eq!(None, advance_registration(&context, &mut conn_ctx, next));
+    assert_ne!(RegistrationState::Registered, conn_ctx.registration);
+
+    handle_cap(
+        &context,
+        "localhost",
+        &Some("END".to_string()),
+        &None,
+        &mut conn_ctx,
+    )
+    .expect("Expected welcome burst");
+
+    assert_eq!(RegistrationState::Registered, conn_ctx.registration);
+    assert!(conn_ctx.capabilities.contains(&Capability::EchoMessage));
+}
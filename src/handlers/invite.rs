@@ -0,0 +1,227 @@
+use std::{collections::HashMap, iter::FromIterator, time::Duration};
+
+use uuid::Uuid;
+
+use crate::{
+    capabilities::Capability,
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    tags::server_tags,
+    util,
+};
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_invite(
+    server_host: &str,
+    nick: &str,
+    client: &str,
+    conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    target: &Option<String>,
+    channel: &Option<String>,
+    invite_timeout: Duration,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let error = |reply: Reply| {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]))
+    };
+
+    let (target, channel) = match (target, channel) {
+        (Some(t), Some(c)) => (t, c),
+        _ => {
+            return error(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: "INVITE".to_string(),
+            })
+        }
+    };
+
+    let target_conn = match util::find_connection_by_nick(connections, target) {
+        Some(t) => t,
+        None => {
+            return error(Reply::ErrNoSuchNick {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                target: target.to_owned(),
+            })
+        }
+    };
+
+    let chan_ctx = match channels.get_mut(&util::casefold(channel)) {
+        Some(c) => c,
+        None => {
+            return error(Reply::ErrNoSuchChannel {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                channel: channel.to_owned(),
+            })
+        }
+    };
+
+    let status = match chan_ctx.status_of(&conn_context.connection_id) {
+        Some(s) => s,
+        None => {
+            return error(Reply::ErrNotOnChannel {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                channel: channel.to_owned(),
+            })
+        }
+    };
+
+    // anyone in the channel can invite, unless it's invite only
+    if chan_ctx.modes.invite_only && !status.operator {
+        return error(Reply::ErrChanOPrivsNeeded {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            channel: channel.to_owned(),
+        });
+    }
+
+    let target_nick = target_conn.nick.clone().unwrap_or_default();
+
+    if chan_ctx.members.contains_key(&target_conn.connection_id) {
+        return error(Reply::ErrUserOnChannel {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target_nick,
+            channel: channel.to_owned(),
+        });
+    }
+
+    chan_ctx.invite(target_conn.connection_id, invite_timeout);
+
+    let invite_reply = Reply::Invite {
+        client: client.to_owned(),
+        target: target_nick.clone(),
+        channel: channel.to_owned(),
+    }
+    .with_tags(server_tags());
+
+    let mut map = HashMap::new();
+
+    map.insert(
+        conn_context.connection_id,
+        vec![Reply::Inviting {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target_nick,
+            channel: channel.to_owned(),
+        }],
+    );
+
+    map.insert(target_conn.connection_id, vec![invite_reply.clone()]);
+
+    // the other operators in the channel can be told about it too
+    for (member, member_status) in &chan_ctx.members {
+        if member == &conn_context.connection_id || !member_status.operator {
+            continue;
+        }
+
+        let wants_notify = connections
+            .get(member)
+            .map(|c| c.capabilities.contains(&Capability::InviteNotify))
+            .unwrap_or(false);
+
+        if wants_notify {
+            map.insert(*member, vec![invite_reply.clone()]);
+        }
+    }
+
+    Some(map)
+}
+
+#[test]
+fn handle_invite_target_is_invited_and_ops_notified() {
+    use crate::context::MemberStatus;
+
+    let inviter_id = Uuid::new_v4();
+    let other_op_id = Uuid::new_v4();
+    let target_id = Uuid::new_v4();
+    let client = "JIM!~jim@localhost";
+
+    let operator = MemberStatus {
+        operator: true,
+        voice: false,
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.invite_only = true;
+    chan_ctx.members.insert(inviter_id, operator);
+    chan_ctx.members.insert(other_op_id, operator);
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        inviter_id,
+        ConnectionContext {
+            connection_id: inviter_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        other_op_id,
+        ConnectionContext {
+            connection_id: other_op_id,
+            nick: Some("TOM".to_string()),
+            capabilities: vec![Capability::InviteNotify].into_iter().collect(),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        target_id,
+        ConnectionContext {
+            connection_id: target_id,
+            nick: Some("BOB".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let timeout = Duration::from_secs(3600);
+
+    let replies = handle_invite(
+        "localhost",
+        "JIM",
+        client,
+        &connections[&inviter_id],
+        &mut channels,
+        &connections,
+        &Some("bob".to_string()),
+        &Some("#foo".to_string()),
+        timeout,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::Inviting {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            target: "BOB".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        replies.get(&inviter_id)
+    );
+
+    for id in &[target_id, other_op_id] {
+        match replies.get(id).map(|r| r.as_slice()) {
+            Some([Reply::Tagged { reply, .. }]) => assert_eq!(
+                &Reply::Invite {
+                    client: client.to_string(),
+                    target: "BOB".to_string(),
+                    channel: "#foo".to_string(),
+                },
+                reply.as_ref()
+            ),
+            r => panic!("Unexpected replies {:?}", r),
+        }
+    }
+
+    assert!(channels["#foo"].is_invited(&target_id, timeout));
+}
@@ -1,16 +1,17 @@
-use std::{
-    collections::{HashMap, HashSet},
-    iter::FromIterator,
-};
+use std::{collections::HashMap, iter::FromIterator, time::Duration};
 
-use chrono::Utc;
 use uuid::Uuid;
 
 use crate::{
-    context::{ChannelContext, ConnectionContext},
+    capabilities::Capability,
+    context::{ChannelContext, ConnectionContext, MemberStatus},
+    handlers::names::names_replies,
     replies::Reply,
+    tags::server_tags,
+    util,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_join(
     server_host: &str,
     nick: &str,
@@ -19,6 +20,9 @@ pub fn handle_join(
     channels: &mut HashMap<String, ChannelContext>,
     connections: &HashMap<Uuid, ConnectionContext>,
     channels_to_join: &Option<Vec<String>>,
+    keys: &Option<Vec<String>>,
+    invite_timeout: Duration,
+    channel_len: usize,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
     let channels_to_join = match channels_to_join {
         Some(c) => c,
@@ -34,48 +38,77 @@ pub fn handle_join(
         }
     };
 
-    let now = Utc::now();
     let mut map = HashMap::new();
 
-    for channel in channels_to_join {
-        match channels.get_mut(channel) {
-            Some(c) => {
-                c.members.insert(conn_context.connection_id);
-            }
-            None => {
-                let mut members = HashSet::new();
-                members.insert(conn_context.connection_id);
-
-                channels.insert(
-                    channel.clone(),
-                    // TODO this probably won't be right eventually
-                    // if there needs to be persisted channel ownership?
-                    ChannelContext { members },
-                );
+    for (i, channel) in channels_to_join.iter().enumerate() {
+        // keys line up with the channels they are for
+        let key = keys.as_ref().and_then(|k| k.get(i));
+
+        if !is_valid_channel(channel, channel_len) {
+            map.entry(conn_context.connection_id)
+                .or_insert_with(Vec::new)
+                .push(Reply::ErrBadChanMask {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                });
+            continue;
+        }
+
+        let folded = util::casefold(channel);
+
+        if let Some(chan_ctx) = channels.get(&folded) {
+            if !chan_ctx.members.contains_key(&conn_context.connection_id) {
+                let is_invited = chan_ctx.is_invited(&conn_context.connection_id, invite_timeout);
+
+                if let Some(error) = check_can_join(
+                    server_host,
+                    nick,
+                    client,
+                    channel,
+                    chan_ctx,
+                    key,
+                    is_invited,
+                ) {
+                    map.entry(conn_context.connection_id)
+                        .or_insert_with(Vec::new)
+                        .push(error);
+                    continue;
+                }
             }
         }
 
-        let mut replies = vec![
-            Reply::Join {
-                client: client.to_string(),
-                channel: channel.clone(),
-            },
-            // TODO persist the channel metadata
-            Reply::Topic {
-                server_host: server_host.to_string(),
-                nick: nick.to_string(),
-                channel: channel.clone(),
-                topic: "foobar topic".to_string(),
-            },
-            Reply::TopicWhoTime {
-                server_host: server_host.to_string(),
-                channel: channel.clone(),
-                nick: nick.to_string(),
-                set_at: now,
-            },
-        ];
+        let chan_ctx = channels.entry(folded.clone()).or_insert_with(|| {
+            let mut chan_ctx = ChannelContext::new(channel);
+            chan_ctx.modes.no_external = true;
+            chan_ctx.modes.topic_lock = true;
+            chan_ctx
+        });
 
-        let chan_ctx = match channels.get(channel) {
+        // whoever creates the channel looks after it
+        let status = MemberStatus {
+            operator: chan_ctx.members.is_empty(),
+            voice: false,
+        };
+
+        chan_ctx
+            .members
+            .entry(conn_context.connection_id)
+            .or_insert(status);
+
+        // an invite is only good for one join
+        chan_ctx.invites.remove(&conn_context.connection_id);
+
+        // every member sees the same JOIN, including its msgid
+        let join_reply = Reply::Join {
+            client: client.to_string(),
+            channel: channel.clone(),
+        }
+        .with_tags(server_tags());
+
+        let mut replies = vec![join_reply.clone()];
+
+        let chan_ctx = match channels.get(&folded) {
             Some(c) => c,
             None => {
                 println!(
@@ -86,9 +119,39 @@ pub fn handle_join(
             }
         };
 
-        let mut channel_users = vec![];
+        if let Some(topic) = &chan_ctx.topic {
+            replies.push(Reply::Topic {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+                channel: channel.clone(),
+                topic: topic.text.clone(),
+            });
+            replies.push(Reply::TopicWhoTime {
+                server_host: server_host.to_string(),
+                channel: channel.clone(),
+                nick: nick.to_string(),
+                set_by: topic.set_by.clone(),
+                set_at: topic.set_at,
+            });
+        }
+
+        replies.extend(names_replies(
+            server_host,
+            nick,
+            channel,
+            chan_ctx,
+            connections,
+        ));
+
+        map.entry(conn_context.connection_id)
+            .or_insert_with(Vec::new)
+            .extend(replies);
+
+        for member in chan_ctx.members.keys() {
+            if member == &conn_context.connection_id {
+                continue;
+            }
 
-        for member in &chan_ctx.members {
             let other_user = match connections.get(member) {
                 Some(c) => c,
                 None => {
@@ -100,51 +163,377 @@ pub fn handle_join(
                 }
             };
 
-            if let Some(e) = &other_user.nick {
-                channel_users.push(e.clone())
+            let member_replies = map.entry(other_user.connection_id).or_insert_with(Vec::new);
+            member_replies.push(join_reply.clone());
+
+            // away-notify clients learn straight away that the new member is away
+            if let Some(away) = &conn_context.away {
+                if other_user.capabilities.contains(&Capability::AwayNotify) {
+                    member_replies.push(
+                        Reply::AwayNotify {
+                            client: client.to_string(),
+                            message: Some(away.clone()),
+                        }
+                        .with_tags(server_tags()),
+                    );
+                }
             }
         }
+    }
 
-        replies.push(Reply::Nam {
-            server_host: server_host.to_string(),
-            nick: nick.to_string(),
-            channel: channel.clone(),
-            channel_users,
+    Some(map)
+}
+
+// channel    =  ( "#" / "+" / ( "!" channelid ) / "&" ) chanstring
+// chanstring =  any octet except NUL, BELL, CR, LF, " ", "," and ":"
+// only # channels exist here, as advertised in CHANTYPES
+fn is_valid_channel(channel: &str, max_len: usize) -> bool {
+    channel.starts_with('#')
+        && channel.len() > 1
+        && channel.len() <= max_len
+        && !channel.contains(['\0', '\x07', '\r', '\n', ' ', ',', ':'])
+}
+
+fn check_can_join(
+    server_host: &str,
+    nick: &str,
+    client: &str,
+    channel: &str,
+    chan_ctx: &ChannelContext,
+    key: Option<&String>,
+    is_invited: bool,
+) -> Option<Reply> {
+    let server_host = server_host.to_string();
+    let nick = nick.to_string();
+    let channel = channel.to_string();
+
+    if chan_ctx.modes.is_banned(client) {
+        return Some(Reply::ErrBannedFromChan {
+            server_host,
+            nick,
+            channel,
         });
+    }
 
-        replies.push(Reply::EndOfNames {
-            server_host: server_host.to_string(),
-            nick: nick.to_string(),
-            channel: channel.clone(),
+    if chan_ctx.modes.invite_only && !is_invited && !chan_ctx.modes.is_invite_excepted(client) {
+        return Some(Reply::ErrInviteOnlyChan {
+            server_host,
+            nick,
+            channel,
         });
+    }
 
-        map.insert(conn_context.connection_id, replies);
+    if chan_ctx.modes.key.is_some() && chan_ctx.modes.key.as_ref() != key {
+        return Some(Reply::ErrBadChannelKey {
+            server_host,
+            nick,
+            channel,
+        });
+    }
 
-        for member in &chan_ctx.members {
-            if member == &conn_context.connection_id {
-                continue;
-            }
+    if let Some(limit) = chan_ctx.modes.limit {
+        if chan_ctx.members.len() >= limit {
+            return Some(Reply::ErrChannelIsFull {
+                server_host,
+                nick,
+                channel,
+            });
+        }
+    }
 
-            let other_user = match connections.get(member) {
-                Some(c) => c,
-                None => {
-                    println!(
-                        "Connection context not found for matched channel user {}",
-                        member
-                    );
-                    continue;
-                }
-            };
+    None
+}
 
-            map.insert(
-                other_user.connection_id,
-                vec![Reply::Join {
-                    client: client.to_string(),
-                    channel: channel.clone(),
-                }],
-            );
-        }
+#[test]
+fn handle_join_channel_modes_are_enforced() {
+    let conn_ctx = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.key = Some("secret".to_string());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let replies = handle_join(
+        "localhost",
+        "JIM",
+        "JIM!~jim@localhost",
+        &conn_ctx,
+        &mut channels,
+        &HashMap::new(),
+        &Some(vec!["#foo".to_string()]),
+        &Some(vec!["wrong".to_string()]),
+        Duration::from_secs(3600),
+        50,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::ErrBadChannelKey {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        replies.get(&conn_ctx.connection_id)
+    );
+    assert!(channels["#foo"].members.is_empty());
+
+    handle_join(
+        "localhost",
+        "JIM",
+        "JIM!~jim@localhost",
+        &conn_ctx,
+        &mut channels,
+        &HashMap::new(),
+        &Some(vec!["#foo".to_string()]),
+        &Some(vec!["secret".to_string()]),
+        Duration::from_secs(3600),
+        50,
+    );
+
+    assert!(channels["#foo"]
+        .members
+        .contains_key(&conn_ctx.connection_id));
+}
+
+#[test]
+fn handle_join_banned_unless_excepted() {
+    use chrono::Utc;
+
+    use crate::context::ListEntry;
+
+    let conn_ctx = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let entry = |mask: &str| ListEntry {
+        mask: mask.to_string(),
+        set_by: "BOB".to_string(),
+        set_at: Utc::now(),
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.bans.push(entry("*!*@localhost"));
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let join = |channels: &mut HashMap<String, ChannelContext>| {
+        handle_join(
+            "localhost",
+            "JIM",
+            "JIM!~jim@localhost",
+            &conn_ctx,
+            channels,
+            &HashMap::new(),
+            &Some(vec!["#foo".to_string()]),
+            &None,
+            Duration::from_secs(3600),
+            50,
+        )
+        .expect("Expected replies")
+    };
+
+    assert_eq!(
+        Some(&vec![Reply::ErrBannedFromChan {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        join(&mut channels).get(&conn_ctx.connection_id)
+    );
+
+    channels
+        .get_mut("#foo")
+        .unwrap()
+        .modes
+        .ban_exceptions
+        .push(entry("jim!*@*"));
+
+    join(&mut channels);
+
+    assert!(channels["#foo"]
+        .members
+        .contains_key(&conn_ctx.connection_id));
+}
+
+#[test]
+fn handle_join_invite_only_needs_invite() {
+    let conn_ctx = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.invite_only = true;
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let join = |channels: &mut HashMap<String, ChannelContext>| {
+        handle_join(
+            "localhost",
+            "JIM",
+            "JIM!~jim@localhost",
+            &conn_ctx,
+            channels,
+            &HashMap::new(),
+            &Some(vec!["#foo".to_string()]),
+            &None,
+            Duration::from_secs(3600),
+            50,
+        )
+        .expect("Expected replies")
+    };
+
+    assert_eq!(
+        Some(&vec![Reply::ErrInviteOnlyChan {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        join(&mut channels).get(&conn_ctx.connection_id)
+    );
+
+    channels
+        .get_mut("#foo")
+        .unwrap()
+        .invite(conn_ctx.connection_id, Duration::from_secs(3600));
+
+    join(&mut channels);
+
+    let chan_ctx = &channels["#foo"];
+    assert!(chan_ctx.members.contains_key(&conn_ctx.connection_id));
+    assert!(chan_ctx.invites.is_empty());
+}
+
+#[test]
+fn is_valid_channel_validates() {
+    assert!(is_valid_channel("#foo", 50));
+    assert!(!is_valid_channel("foo", 50));
+    assert!(!is_valid_channel("#", 50));
+    assert!(!is_valid_channel("#foo bar", 50));
+    assert!(!is_valid_channel("#foo:bar", 50));
+    assert!(!is_valid_channel(&format!("#{}", "a".repeat(50)), 50));
+}
+
+#[test]
+fn handle_join_channel_names_are_case_insensitive() {
+    let jim = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+    let bob = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("BOB".to_string()),
+        ..Default::default()
+    };
+
+    let mut channels = HashMap::new();
+
+    for (conn_ctx, channel) in [(&jim, "#Foo"), (&bob, "#foo")] {
+        handle_join(
+            "localhost",
+            conn_ctx.nick.as_deref().unwrap(),
+            "client",
+            conn_ctx,
+            &mut channels,
+            &HashMap::new(),
+            &Some(vec![channel.to_string()]),
+            &None,
+            Duration::from_secs(3600),
+            50,
+        );
     }
 
-    Some(map)
+    assert_eq!(1, channels.len());
+
+    let chan_ctx = channels.values().next().unwrap();
+    assert!(chan_ctx.members[&jim.connection_id].operator);
+    assert!(!chan_ctx.members[&bob.connection_id].operator);
+}
+
+#[test]
+fn handle_join_channel_keeps_the_name_it_was_created_with() {
+    use crate::handlers::{list::handle_list, names::handle_names, whois::handle_whois};
+
+    let connection_id = Uuid::new_v4();
+    let mut connections = HashMap::new();
+    connections.insert(
+        connection_id,
+        ConnectionContext {
+            connection_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let mut channels = HashMap::new();
+
+    for channel in ["#Foo", "#foo"] {
+        handle_join(
+            "localhost",
+            "JIM",
+            "JIM!~jim@localhost",
+            &connections[&connection_id],
+            &mut channels,
+            &connections,
+            &Some(vec![channel.to_string()]),
+            &None,
+            Duration::from_secs(3600),
+            50,
+        );
+    }
+
+    let conn_ctx = &connections[&connection_id];
+    let shown = |replies: Option<HashMap<Uuid, Vec<Reply>>>| {
+        replies.expect("Expected replies")[&connection_id]
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<String>>()
+    };
+
+    let list = shown(handle_list("localhost", "JIM", conn_ctx, &channels, &None));
+    assert!(
+        list.iter().any(|r| r.contains(" 322 JIM #Foo ")),
+        "{:?}",
+        list
+    );
+
+    let names = shown(handle_names(
+        "localhost",
+        "JIM",
+        conn_ctx,
+        &channels,
+        &connections,
+        &None,
+    ));
+    assert!(
+        names.iter().any(|r| r.contains(" 353 JIM = #Foo ")),
+        "{:?}",
+        names
+    );
+
+    let whois = shown(handle_whois(
+        "localhost",
+        "0.0.1",
+        "JIM",
+        conn_ctx,
+        &channels,
+        &connections,
+        &Some("JIM".to_string()),
+    ));
+    assert!(
+        whois.iter().any(|r| r.contains(" 319 JIM JIM :@#Foo")),
+        "{:?}",
+        whois
+    );
 }
@@ -0,0 +1,240 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    tags::server_tags,
+    util,
+};
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_kick(
+    server_host: &str,
+    nick: &str,
+    client: &str,
+    conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    channels_to_kick_from: &Option<Vec<String>>,
+    users: &Option<Vec<String>>,
+    reason: &Option<String>,
+    kick_len: usize,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let need_more_params = || {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: "KICK".to_string(),
+            }],
+        )]))
+    };
+
+    let (channels_to_kick_from, users) = match (channels_to_kick_from, users) {
+        (Some(c), Some(u)) => (c, u),
+        _ => return need_more_params(),
+    };
+
+    // either a single channel to kick every user from, or a channel per user
+    let pairs: Vec<(&String, &String)> = match channels_to_kick_from.as_slice() {
+        [channel] => users.iter().map(|u| (channel, u)).collect(),
+        _ if channels_to_kick_from.len() == users.len() => {
+            channels_to_kick_from.iter().zip(users.iter()).collect()
+        }
+        _ => return need_more_params(),
+    };
+
+    // without a reason the kicker's nick is used, as most servers do
+    let reason = match reason {
+        Some(r) if !r.is_empty() => util::truncate(r, kick_len),
+        _ => nick.to_owned(),
+    };
+
+    let mut map: HashMap<Uuid, Vec<Reply>> = HashMap::new();
+    let mut replies_to_user = vec![];
+
+    for (channel, user) in pairs {
+        let folded = util::casefold(channel);
+
+        let chan_ctx = match channels.get_mut(&folded) {
+            Some(c) => c,
+            None => {
+                replies_to_user.push(Reply::ErrNoSuchChannel {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                });
+                continue;
+            }
+        };
+
+        let status = match chan_ctx.status_of(&conn_context.connection_id) {
+            Some(s) => s,
+            None => {
+                replies_to_user.push(Reply::ErrNotOnChannel {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                });
+                continue;
+            }
+        };
+
+        if !status.operator {
+            replies_to_user.push(Reply::ErrChanOPrivsNeeded {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                channel: channel.to_owned(),
+            });
+            continue;
+        }
+
+        let target = match util::find_connection_by_nick(connections, user) {
+            Some(t) if chan_ctx.members.contains_key(&t.connection_id) => t,
+            Some(_) => {
+                replies_to_user.push(Reply::ErrUserNotInChannel {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    target: user.to_owned(),
+                    channel: channel.to_owned(),
+                });
+                continue;
+            }
+            None => {
+                replies_to_user.push(Reply::ErrNoSuchNick {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    target: user.to_owned(),
+                });
+                continue;
+            }
+        };
+
+        let kick_reply = Reply::Kick {
+            client: client.to_owned(),
+            channel: channel.to_owned(),
+            target: target.nick.clone().unwrap_or_default(),
+            reason: reason.clone(),
+        }
+        .with_tags(server_tags());
+
+        // the user being kicked is told along with everyone else
+        for member in chan_ctx.members.keys() {
+            map.entry(*member).or_default().push(kick_reply.clone());
+        }
+
+        chan_ctx.members.remove(&target.connection_id);
+
+        if chan_ctx.members.is_empty() {
+            channels.remove(&folded);
+        }
+    }
+
+    if !replies_to_user.is_empty() {
+        map.entry(conn_context.connection_id)
+            .or_default()
+            .extend(replies_to_user);
+    }
+
+    Some(map)
+}
+
+#[test]
+fn handle_kick_operator_removes_member() {
+    use crate::context::MemberStatus;
+
+    let op_id = Uuid::new_v4();
+    let member_id = Uuid::new_v4();
+    let client = "JIM!~jim@localhost";
+
+    let op = ConnectionContext {
+        connection_id: op_id,
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let member = ConnectionContext {
+        connection_id: member_id,
+        nick: Some("BOB".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.members.insert(
+        op_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+    chan_ctx.members.insert(member_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let mut connections = HashMap::new();
+    connections.insert(op_id, op);
+    connections.insert(member_id, member);
+
+    let replies = handle_kick(
+        "localhost",
+        "BOB",
+        "BOB!~bob@localhost",
+        &connections[&member_id],
+        &mut channels,
+        &connections,
+        &Some(vec!["#foo".to_string()]),
+        &Some(vec!["JIM".to_string()]),
+        &None,
+        255,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::ErrChanOPrivsNeeded {
+            server_host: "localhost".to_string(),
+            nick: "BOB".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        replies.get(&member_id)
+    );
+
+    let replies = handle_kick(
+        "localhost",
+        "JIM",
+        client,
+        &connections[&op_id],
+        &mut channels,
+        &connections,
+        &Some(vec!["#foo".to_string()]),
+        &Some(vec!["bob".to_string(), "NOBODY".to_string()]),
+        &Some("be nice".to_string()),
+        255,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&member_id).map(|r| r.as_slice()) {
+        Some([Reply::Tagged { reply, .. }]) => assert_eq!(
+            &Reply::Kick {
+                client: client.to_string(),
+                channel: "#foo".to_string(),
+                target: "BOB".to_string(),
+                reason: "be nice".to_string(),
+            },
+            reply.as_ref()
+        ),
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    match replies.get(&op_id).map(|r| r.as_slice()) {
+        Some([Reply::Tagged { .. }, Reply::ErrNoSuchNick { target, .. }]) => {
+            assert_eq!("NOBODY", target)
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    assert!(!channels["#foo"].members.contains_key(&member_id));
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext, WhowasHistory},
+    handlers::quit::disconnect,
+    oper::{check_privilege, Privilege},
+    replies::Reply,
+    util,
+};
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_kill(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    whowas: &mut WhowasHistory,
+    target: &Option<String>,
+    reason: &Option<String>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let error = |reply: Reply| {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]))
+    };
+
+    if let Some(reply) = check_privilege(server_host, nick, conn_context, Privilege::Kill) {
+        return error(reply);
+    }
+
+    let target = match target {
+        Some(t) => t,
+        None => {
+            return error(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: "KILL".to_string(),
+            })
+        }
+    };
+
+    let killed = match util::find_connection_by_nick(connections, target) {
+        Some(c) => c,
+        None => {
+            return error(Reply::ErrNoSuchNick {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                target: target.to_owned(),
+            })
+        }
+    };
+
+    let reason = reason
+        .as_ref()
+        .filter(|r| !r.is_empty())
+        .map(|r| r.as_str())
+        .unwrap_or("No reason given");
+    let message = format!("Killed ({} ({}))", nick, reason);
+
+    println!(
+        "{} killed {} ({})",
+        nick,
+        killed.client.as_ref().unwrap_or(target),
+        reason
+    );
+
+    disconnect(
+        killed.connection_id,
+        &message,
+        &message,
+        channels,
+        connections,
+        whowas,
+    )
+}
+
+#[test]
+fn handle_kill_disconnects_target() {
+    use crate::context::MemberStatus;
+
+    let oper_id = Uuid::new_v4();
+    let target_id = Uuid::new_v4();
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        oper_id,
+        ConnectionContext {
+            connection_id: oper_id,
+            nick: Some("JIM".to_string()),
+            operator: true,
+            privileges: vec![Privilege::Kill].into_iter().collect(),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        target_id,
+        ConnectionContext {
+            connection_id: target_id,
+            nick: Some("BOB".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.members.insert(oper_id, MemberStatus::default());
+    chan_ctx.members.insert(target_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let mut whowas = WhowasHistory::default();
+
+    let replies = handle_kill(
+        "localhost",
+        "JIM",
+        &connections[&oper_id],
+        &mut channels,
+        &connections,
+        &mut whowas,
+        &Some("bob".to_string()),
+        &Some("spamming".to_string()),
+    )
+    .expect("Expected replies");
+
+    match replies[&target_id].as_slice() {
+        [Reply::Error { message }, Reply::Quit { connection_id, .. }] => {
+            assert_eq!("Closing Link: localhost (Killed (JIM (spamming)))", message);
+            assert_eq!(&target_id, connection_id);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    assert!(matches!(
+        replies[&oper_id].as_slice(),
+        [Reply::Tagged { .. }]
+    ));
+    assert!(!channels["#foo"].members.contains_key(&target_id));
+}
+
+#[test]
+fn handle_kill_requires_privilege() {
+    let connection_id = Uuid::new_v4();
+    let mut connections = HashMap::new();
+    connections.insert(
+        connection_id,
+        ConnectionContext {
+            connection_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let replies = handle_kill(
+        "localhost",
+        "JIM",
+        &connections[&connection_id],
+        &mut HashMap::new(),
+        &connections,
+        &mut WhowasHistory::default(),
+        &Some("JIM".to_string()),
+        &None,
+    )
+    .expect("Expected replies");
+
+    assert!(matches!(
+        replies[&connection_id].as_slice(),
+        [Reply::ErrNoPrivileges { .. }]
+    ));
+}
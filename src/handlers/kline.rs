@@ -0,0 +1,449 @@
+use std::{collections::HashMap, iter::FromIterator, net::Ipv4Addr, sync::Mutex};
+
+use uuid::Uuid;
+
+use crate::{
+    bans::{ip_matches, Ban, BanKind, BanList},
+    context::{ChannelContext, ConnectionContext, WhowasHistory},
+    handlers::quit::disconnect,
+    oper::{check_privilege, Privilege},
+    replies::Reply,
+};
+
+// a bare host is taken to mean every user on it
+fn normalize_kline_mask(mask: &str) -> String {
+    match mask.contains('@') {
+        true => mask.to_string(),
+        false => format!("*@{}", mask),
+    }
+}
+
+fn kind_name(kind: BanKind) -> &'static str {
+    match kind {
+        BanKind::KLine => "K-Line",
+        BanKind::DLine => "D-Line",
+    }
+}
+
+// Sets a K-line or D-line, anyone already connected who it matches is
+// disconnected straight away
+#[allow(clippy::too_many_arguments)]
+pub fn handle_kline(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    whowas: &mut WhowasHistory,
+    bans: &Mutex<BanList>,
+    kind: BanKind,
+    duration: &Option<u64>,
+    mask: &Option<String>,
+    reason: &Option<String>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let error = |reply: Reply| {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]))
+    };
+
+    if let Some(reply) = check_privilege(server_host, nick, conn_context, Privilege::Kline) {
+        return error(reply);
+    }
+
+    let mask = match (mask, kind) {
+        (Some(m), BanKind::KLine) => normalize_kline_mask(m),
+        (Some(m), BanKind::DLine) => m.to_string(),
+        (None, _) => {
+            return error(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: kind_name(kind).replace('-', "").to_uppercase(),
+            })
+        }
+    };
+
+    // any address will do, this is only checking the mask can be understood
+    if kind == BanKind::DLine && ip_matches(&mask, Ipv4Addr::LOCALHOST.into()).is_none() {
+        return error(Reply::ServerNotice {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            message: format!("Invalid D-Line [{}]", mask),
+        });
+    }
+
+    let reason = reason
+        .as_ref()
+        .filter(|r| !r.is_empty())
+        .map(|r| r.as_str())
+        .unwrap_or("No reason given");
+
+    // the ban file has one tab separated ban a line, so neither can hold those
+    if [&mask, reason]
+        .iter()
+        .any(|s| s.contains(['\t', '\r', '\n']))
+    {
+        return error(Reply::ServerNotice {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            message: format!("Invalid {} [{}]", kind_name(kind), mask),
+        });
+    }
+
+    let ban = bans
+        .lock()
+        .unwrap()
+        .add(kind, &mask, reason, nick, *duration);
+
+    let added = match ban.expires_at {
+        Some(_) => format!(
+            "Added temporary {} min. {} [{}]",
+            duration.unwrap_or_default(),
+            kind_name(kind),
+            mask
+        ),
+        None => format!("Added {} [{}]", kind_name(kind), mask),
+    };
+
+    println!("{} {}", nick, added);
+
+    let mut notices = vec![Reply::ServerNotice {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        message: added,
+    }];
+
+    // the oper setting the ban is left connected, but told it covers them
+    if ban.matches(conn_context) {
+        notices.push(Reply::ServerNotice {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            message: format!("{} [{}] also matches you", kind_name(kind), mask),
+        });
+    }
+
+    let mut map = HashMap::<Uuid, Vec<Reply>>::new();
+    map.insert(conn_context.connection_id, notices);
+
+    let banned = connections
+        .values()
+        .filter(|c| c.connection_id != conn_context.connection_id && ban.matches(c))
+        .map(|c| c.connection_id)
+        .collect::<Vec<Uuid>>();
+
+    for connection_id in banned {
+        let replies = disconnect_banned(
+            server_host,
+            connection_id,
+            &ban,
+            channels,
+            connections,
+            whowas,
+        );
+
+        for (id, replies) in replies.into_iter().flatten() {
+            map.entry(id).or_default().extend(replies);
+        }
+    }
+
+    Some(map)
+}
+
+pub fn handle_unkline(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    bans: &Mutex<BanList>,
+    kind: BanKind,
+    mask: &Option<String>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let reply = |reply: Reply| {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]))
+    };
+
+    if let Some(error) = check_privilege(server_host, nick, conn_context, Privilege::Kline) {
+        return reply(error);
+    }
+
+    let mask = match (mask, kind) {
+        (Some(m), BanKind::KLine) => normalize_kline_mask(m),
+        (Some(m), BanKind::DLine) => m.to_string(),
+        (None, _) => {
+            return reply(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: format!("UN{}", kind_name(kind).replace('-', "").to_uppercase()),
+            })
+        }
+    };
+
+    let message = match bans.lock().unwrap().remove(kind, &mask) {
+        true => format!("Removed {} [{}]", kind_name(kind), mask),
+        false => format!("No {} for [{}]", kind_name(kind), mask),
+    };
+
+    println!("{} {}", nick, message);
+
+    reply(Reply::ServerNotice {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        message,
+    })
+}
+
+// Closes the link of someone caught by a ban, whether it has just been set
+// or they have only now finished registering
+pub fn disconnect_banned(
+    server_host: &str,
+    connection_id: Uuid,
+    ban: &Ban,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    whowas: &mut WhowasHistory,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let nick = connections
+        .get(&connection_id)?
+        .nick
+        .clone()
+        .unwrap_or_else(|| "*".to_string());
+
+    let message = match ban.kind {
+        BanKind::KLine => "K-Lined",
+        BanKind::DLine => "D-Lined",
+    };
+    let reason = format!("{}: {}", message, ban.reason);
+
+    let mut map = disconnect(
+        connection_id,
+        message,
+        &reason,
+        channels,
+        connections,
+        whowas,
+    )?;
+
+    map.entry(connection_id).or_default().insert(
+        0,
+        Reply::ErrYoureBannedCreep {
+            server_host: server_host.to_owned(),
+            nick,
+            reason: ban.reason.clone(),
+        },
+    );
+
+    Some(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+    use test_case::test_case;
+
+    fn oper(connection_id: Uuid) -> ConnectionContext {
+        ConnectionContext {
+            connection_id,
+            nick: Some("JIM".to_string()),
+            client: Some("JIM!~jim@127.0.0.1".to_string()),
+            operator: true,
+            privileges: vec![Privilege::Kline].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn user(connection_id: Uuid, ip: [u8; 4]) -> ConnectionContext {
+        let [a, b, c, d] = ip;
+
+        ConnectionContext {
+            connection_id,
+            nick: Some("BOB".to_string()),
+            client: Some(format!("BOB!~bob@{}.{}.{}.{}", a, b, c, d)),
+            client_host: Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(a, b, c, d),
+                1234,
+            ))),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn handle_kline_bans_and_disconnects_matching_users() {
+        let oper_id = Uuid::new_v4();
+        let banned_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper(oper_id));
+        connections.insert(banned_id, user(banned_id, [10, 0, 0, 5]));
+        connections.insert(other_id, user(other_id, [10, 0, 1, 5]));
+
+        let bans = Mutex::new(BanList::default());
+
+        let replies = handle_kline(
+            "localhost",
+            "JIM",
+            &connections[&oper_id],
+            &mut HashMap::new(),
+            &connections,
+            &mut WhowasHistory::default(),
+            &bans,
+            BanKind::KLine,
+            &Some(60),
+            &Some("10.0.0.*".to_string()),
+            &Some("spamming".to_string()),
+        )
+        .expect("Expected replies");
+
+        assert_eq!(
+            Some(&vec![Reply::ServerNotice {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                message: "Added temporary 60 min. K-Line [*@10.0.0.*]".to_string(),
+            }]),
+            replies.get(&oper_id)
+        );
+
+        match replies[&banned_id].as_slice() {
+            [Reply::ErrYoureBannedCreep { .. }, Reply::Error { message }, Reply::Quit { .. }] => {
+                assert_eq!("Closing Link: 10.0.0.5 (K-Lined: spamming)", message)
+            }
+            r => panic!("Unexpected replies {:?}", r),
+        }
+
+        assert!(!replies.contains_key(&other_id));
+        assert!(bans
+            .lock()
+            .unwrap()
+            .find(&connections[&banned_id])
+            .is_some());
+    }
+
+    #[test]
+    fn handle_kline_rejects_bad_dline_mask() {
+        let oper_id = Uuid::new_v4();
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper(oper_id));
+
+        let bans = Mutex::new(BanList::default());
+
+        let replies = handle_kline(
+            "localhost",
+            "JIM",
+            &connections[&oper_id],
+            &mut HashMap::new(),
+            &connections,
+            &mut WhowasHistory::default(),
+            &bans,
+            BanKind::DLine,
+            &None,
+            &Some("*@example.com".to_string()),
+            &None,
+        )
+        .expect("Expected replies");
+
+        assert!(matches!(
+            replies[&oper_id].as_slice(),
+            [Reply::ServerNotice { .. }]
+        ));
+        assert_eq!(0, bans.lock().unwrap().list(BanKind::DLine).count());
+    }
+
+    #[test]
+    fn handle_kline_leaves_the_oper_connected() {
+        let oper_id = Uuid::new_v4();
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper(oper_id));
+
+        let bans = Mutex::new(BanList::default());
+
+        let replies = handle_kline(
+            "localhost",
+            "JIM",
+            &connections[&oper_id],
+            &mut HashMap::new(),
+            &connections,
+            &mut WhowasHistory::default(),
+            &bans,
+            BanKind::KLine,
+            &None,
+            &Some("127.0.0.1".to_string()),
+            &None,
+        )
+        .expect("Expected replies");
+
+        match replies[&oper_id].as_slice() {
+            [Reply::ServerNotice { .. }, Reply::ServerNotice { message, .. }] => {
+                assert_eq!("K-Line [*@127.0.0.1] also matches you", message)
+            }
+            r => panic!("Unexpected replies {:?}", r),
+        }
+    }
+
+    #[test_case("*@example.com", "tab\tin reason" ; "tab_in_reason")]
+    #[test_case("*@exam\nple.com", "spamming" ; "newline_in_mask")]
+    fn handle_kline_rejects_unstorable_ban(mask: &str, reason: &str) {
+        let oper_id = Uuid::new_v4();
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper(oper_id));
+
+        let bans = Mutex::new(BanList::default());
+
+        let replies = handle_kline(
+            "localhost",
+            "JIM",
+            &connections[&oper_id],
+            &mut HashMap::new(),
+            &connections,
+            &mut WhowasHistory::default(),
+            &bans,
+            BanKind::KLine,
+            &None,
+            &Some(mask.to_string()),
+            &Some(reason.to_string()),
+        )
+        .expect("Expected replies");
+
+        assert!(matches!(
+            replies[&oper_id].as_slice(),
+            [Reply::ServerNotice { message, .. }] if message.starts_with("Invalid K-Line")
+        ));
+        assert_eq!(0, bans.lock().unwrap().list(BanKind::KLine).count());
+    }
+
+    #[test]
+    fn handle_unkline_removes_ban() {
+        let oper_id = Uuid::new_v4();
+        let conn_context = oper(oper_id);
+
+        let bans = Mutex::new(BanList::default());
+        bans.lock()
+            .unwrap()
+            .add(BanKind::KLine, "*@example.com", "spamming", "JIM", None);
+
+        let unkline = || match handle_unkline(
+            "localhost",
+            "JIM",
+            &conn_context,
+            &bans,
+            BanKind::KLine,
+            &Some("example.com".to_string()),
+        )
+        .expect("Expected replies")
+        .remove(&oper_id)
+        .expect("Expected replies")
+        .as_slice()
+        {
+            [Reply::ServerNotice { message, .. }] => message.clone(),
+            r => panic!("Unexpected replies {:?}", r),
+        };
+
+        assert_eq!("Removed K-Line [*@example.com]", unkline());
+        assert_eq!("No K-Line for [*@example.com]", unkline());
+    }
+}
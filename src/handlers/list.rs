@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    util,
+};
+
+// The ELIST extensions, times are given in minutes
+// https://modern.ircdocs.horse/#elist-parameter
+pub const ELIST: &str = "CMNTU";
+
+#[derive(Debug, PartialEq)]
+enum ListFilter {
+    MoreUsersThan(usize),
+    FewerUsersThan(usize),
+    CreatedWithin(i64),
+    CreatedBefore(i64),
+    TopicWithin(i64),
+    TopicBefore(i64),
+    Mask(String),
+    NotMask(String),
+}
+
+impl ListFilter {
+    // anything that doesn't look like one of the others is a channel mask
+    fn parse(s: &str) -> Option<Self> {
+        let number = |n: &str| n.parse::<i64>().ok();
+
+        let filter = if let Some(n) = s.strip_prefix('>') {
+            ListFilter::MoreUsersThan(n.parse().ok()?)
+        } else if let Some(n) = s.strip_prefix('<') {
+            ListFilter::FewerUsersThan(n.parse().ok()?)
+        } else if let Some(n) = s.strip_prefix("C<") {
+            ListFilter::CreatedWithin(number(n)?)
+        } else if let Some(n) = s.strip_prefix("C>") {
+            ListFilter::CreatedBefore(number(n)?)
+        } else if let Some(n) = s.strip_prefix("T<") {
+            ListFilter::TopicWithin(number(n)?)
+        } else if let Some(n) = s.strip_prefix("T>") {
+            ListFilter::TopicBefore(number(n)?)
+        } else if let Some(mask) = s.strip_prefix('!') {
+            ListFilter::NotMask(mask.to_string())
+        } else {
+            ListFilter::Mask(s.to_string())
+        };
+
+        Some(filter)
+    }
+
+    fn matches(&self, name: &str, chan_ctx: &ChannelContext, now: DateTime<Utc>) -> bool {
+        let within = |time: DateTime<Utc>, minutes: i64| now - time < Duration::minutes(minutes);
+        let topic_set_at = chan_ctx.topic.as_ref().map(|t| t.set_at);
+
+        match self {
+            ListFilter::MoreUsersThan(n) => chan_ctx.members.len() > *n,
+            ListFilter::FewerUsersThan(n) => chan_ctx.members.len() < *n,
+            ListFilter::CreatedWithin(m) => within(chan_ctx.created_at, *m),
+            ListFilter::CreatedBefore(m) => !within(chan_ctx.created_at, *m),
+            ListFilter::TopicWithin(m) => topic_set_at.map(|t| within(t, *m)).unwrap_or(false),
+            ListFilter::TopicBefore(m) => topic_set_at.map(|t| !within(t, *m)).unwrap_or(false),
+            ListFilter::Mask(mask) => util::match_mask(name, mask),
+            ListFilter::NotMask(mask) => !util::match_mask(name, mask),
+        }
+    }
+}
+
+pub fn handle_list(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    filters: &Option<Vec<String>>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let filters = filters
+        .iter()
+        .flatten()
+        .filter_map(|f| ListFilter::parse(f))
+        .collect::<Vec<ListFilter>>();
+
+    // a channel has to match one of the masks given, if there are any, and
+    // every other filter
+    let (masks, filters): (Vec<ListFilter>, Vec<ListFilter>) = filters
+        .into_iter()
+        .partition(|f| matches!(f, ListFilter::Mask(_)));
+
+    let now = Utc::now();
+
+    let mut listed = channels
+        .iter()
+        .filter(|(_, c)| !c.modes.secret || c.members.contains_key(&conn_context.connection_id))
+        .filter(|(name, c)| masks.is_empty() || masks.iter().any(|m| m.matches(name, c, now)))
+        .filter(|(name, c)| filters.iter().all(|f| f.matches(name, c, now)))
+        .collect::<Vec<(&String, &ChannelContext)>>();
+    listed.sort_unstable_by_key(|(name, _)| name.to_owned());
+
+    let mut replies = vec![Reply::ListStart {
+        server_host: server_host.to_string(),
+        nick: nick.to_string(),
+    }];
+
+    for (_, chan_ctx) in listed {
+        replies.push(Reply::List {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+            channel: chan_ctx.name.clone(),
+            visible_users: chan_ctx.members.len(),
+            topic: chan_ctx
+                .topic
+                .as_ref()
+                .map(|t| t.text.clone())
+                .unwrap_or_default(),
+        });
+    }
+
+    replies.push(Reply::ListEnd {
+        server_host: server_host.to_string(),
+        nick: nick.to_string(),
+    });
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, replies);
+
+    Some(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case(">5", Some(ListFilter::MoreUsersThan(5)) ; "more_users")]
+    #[test_case("<5", Some(ListFilter::FewerUsersThan(5)) ; "fewer_users")]
+    #[test_case("C<10", Some(ListFilter::CreatedWithin(10)) ; "created_within")]
+    #[test_case("C>10", Some(ListFilter::CreatedBefore(10)) ; "created_before")]
+    #[test_case("T<10", Some(ListFilter::TopicWithin(10)) ; "topic_within")]
+    #[test_case("T>10", Some(ListFilter::TopicBefore(10)) ; "topic_before")]
+    #[test_case("#foo*", Some(ListFilter::Mask("#foo*".to_string())) ; "mask")]
+    #[test_case("!#foo*", Some(ListFilter::NotMask("#foo*".to_string())) ; "not_mask")]
+    #[test_case(">lots", None ; "bad_number")]
+    fn list_filter_parses_correctly(raw: &str, expected: Option<ListFilter>) {
+        assert_eq!(expected, ListFilter::parse(raw));
+    }
+
+    #[test]
+    fn handle_list_filters_channels() {
+        use crate::context::MemberStatus;
+
+        let conn_ctx = ConnectionContext {
+            connection_id: Uuid::new_v4(),
+            ..Default::default()
+        };
+
+        let with_members = |name: &str, count: usize| {
+            let mut chan_ctx = ChannelContext::new(name);
+
+            for _ in 0..count {
+                chan_ctx
+                    .members
+                    .insert(Uuid::new_v4(), MemberStatus::default());
+            }
+
+            chan_ctx
+        };
+
+        let mut secret = with_members("#secret", 5);
+        secret.modes.secret = true;
+
+        let mut old = with_members("#old", 1);
+        old.created_at = Utc::now() - Duration::minutes(60);
+
+        let mut channels = HashMap::new();
+        channels.insert("#busy".to_string(), with_members("#busy", 5));
+        channels.insert("#quiet".to_string(), with_members("#quiet", 1));
+        channels.insert("#old".to_string(), old);
+        channels.insert("#secret".to_string(), secret);
+
+        let list = |filters: &[&str]| {
+            let filters = Some(filters.iter().map(|f| f.to_string()).collect());
+
+            handle_list("localhost", "JIM", &conn_ctx, &channels, &filters)
+                .expect("Expected replies")
+                .remove(&conn_ctx.connection_id)
+                .expect("Expected replies")
+                .into_iter()
+                .filter_map(|r| match r {
+                    Reply::List { channel, .. } => Some(channel),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(vec!["#busy", "#old", "#quiet"], list(&[]));
+        assert_eq!(vec!["#busy"], list(&[">2"]));
+        assert_eq!(vec!["#old", "#quiet"], list(&["<2"]));
+        assert_eq!(vec!["#old"], list(&["C>30"]));
+        assert_eq!(vec!["#busy", "#quiet"], list(&["C<30"]));
+        assert_eq!(vec!["#old", "#quiet"], list(&["#q*", "#o*"]));
+        assert_eq!(vec!["#quiet"], list(&["#q*", "#o*", "C<30"]));
+        assert_eq!(vec!["#busy", "#old"], list(&["!#q*"]));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext, RegistrationState, ServerStats},
+    replies::Reply,
+};
+
+pub fn handle_lusers(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    channels: &HashMap<String, ChannelContext>,
+    stats: &ServerStats,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let mut map = HashMap::new();
+    map.insert(
+        conn_context.connection_id,
+        lusers_replies(server_host, nick, connections, channels, stats),
+    );
+
+    Some(map)
+}
+
+// there is only ever this one server, so the local and global counts are the same
+pub fn lusers_replies(
+    server_host: &str,
+    nick: &str,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    channels: &HashMap<String, ChannelContext>,
+    stats: &ServerStats,
+) -> Vec<Reply> {
+    let users = connections
+        .values()
+        .filter(|c| c.registration == RegistrationState::Registered)
+        .collect::<Vec<&ConnectionContext>>();
+
+    let user_count = users.len() as u32;
+    let operators = users.iter().filter(|c| c.operator).count() as u32;
+    let unknown = connections.len() as u32 - user_count;
+
+    vec![
+        Reply::LuserClient {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            // TODO there is no user mode +i yet, so nobody is invisible
+            visible_users: user_count,
+            invisible_users: 0,
+            servers: 1,
+        },
+        Reply::LuserOp {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            operators,
+        },
+        Reply::LuserUnknown {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            unknown,
+        },
+        Reply::LuserChannels {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            channels: channels.len() as u32,
+        },
+        Reply::LuserMe {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            clients: user_count,
+            servers: 0,
+        },
+        Reply::LocalUsers {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            current: user_count,
+            max: stats.max_users,
+        },
+        Reply::GlobalUsers {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            current: user_count,
+            max: stats.max_users,
+        },
+        Reply::StatsConn {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            connections: stats.max_connections,
+            clients: stats.max_users,
+            received: stats.connections_received,
+        },
+    ]
+}
+
+#[test]
+fn lusers_replies_counts_live_connections() {
+    let mut connections = HashMap::new();
+
+    for (registration, operator) in &[
+        (RegistrationState::Registered, true),
+        (RegistrationState::Registered, false),
+        (RegistrationState::NickOnly, false),
+    ] {
+        let id = Uuid::new_v4();
+        connections.insert(
+            id,
+            ConnectionContext {
+                connection_id: id,
+                registration: *registration,
+                operator: *operator,
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), ChannelContext::new("#foo"));
+
+    let stats = ServerStats {
+        connections_received: 10,
+        max_connections: 5,
+        max_users: 4,
+    };
+
+    let replies = lusers_replies("localhost", "JIM", &connections, &channels, &stats);
+
+    match replies.as_slice() {
+        [Reply::LuserClient { visible_users, .. }, Reply::LuserOp { operators, .. }, Reply::LuserUnknown { unknown, .. }, Reply::LuserChannels { channels, .. }, Reply::LuserMe { clients, .. }, Reply::LocalUsers { current, max, .. }, Reply::GlobalUsers { .. }, Reply::StatsConn { received, .. }] =>
+        {
+            assert_eq!(2, *visible_users);
+            assert_eq!(1, *operators);
+            assert_eq!(1, *unknown);
+            assert_eq!(1, *channels);
+            assert_eq!(2, *clients);
+            assert_eq!((2, 4), (*current, *max));
+            assert_eq!(10, *received);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
@@ -1,9 +1,25 @@
+pub mod away;
+pub mod cap;
+pub mod invite;
 pub mod join;
+pub mod kick;
+pub mod kill;
+pub mod kline;
+pub mod list;
+pub mod lusers;
 pub mod mode;
+pub mod names;
 pub mod nick;
+pub mod oper;
 pub mod part;
 pub mod ping;
 pub mod privmsg;
 pub mod quit;
+pub mod rehash;
+pub mod stats;
+pub mod topic;
 pub mod user;
+pub mod welcome;
 pub mod who;
+pub mod whois;
+pub mod whowas;
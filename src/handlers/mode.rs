@@ -1,46 +1,745 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, iter::FromIterator};
 
 use chrono::Utc;
 use uuid::Uuid;
 
-use crate::{context::ConnectionContext, replies::Reply};
+use crate::{
+    context::{ChannelContext, ChannelModes, ConnectionContext, ListEntry},
+    modes::{
+        format_mode_changes, mode_class, parse_mode_changes, ModeChange, ModeClass, MAX_MODES,
+        USER_MODES,
+    },
+    replies::Reply,
+    util::{self, normalize_mask},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_mode(
     server_host: &str,
     nick: &str,
-    channel: &Option<String>,
+    client: &str,
     conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    channel: &Option<String>,
+    mode_string: &Option<String>,
+    mode_arguments: &[String],
+    max_list_entries: usize,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
-    let mut map = HashMap::new();
-
-    let replies = match channel {
-        Some(channel) => {
-            vec![
-                Reply::ChannelModeIs {
+    let channel = match channel {
+        Some(c) => c,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNeedMoreParams {
                     server_host: server_host.to_string(),
                     nick: nick.to_string(),
-                    channel: channel.to_string(),
-                    mode_string: "+mtn1".to_string(),
-                    mode_arguments: "100".to_string(),
-                },
-                Reply::CreationTime {
+                    command: "MODE".to_string(),
+                }],
+            )]));
+        }
+    };
+
+    if !channel.starts_with('#') {
+        return Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![user_mode(
+                server_host,
+                nick,
+                conn_context,
+                channel,
+                mode_string,
+            )],
+        )]));
+    }
+
+    let chan_ctx = match channels.get_mut(&util::casefold(channel)) {
+        Some(c) => c,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNoSuchChannel {
                     server_host: server_host.to_string(),
                     nick: nick.to_string(),
                     channel: channel.to_string(),
-                    created_at: Utc::now(),
-                },
-            ]
+                }],
+            )]));
         }
+    };
+
+    let status = chan_ctx.status_of(&conn_context.connection_id);
+
+    let mode_string = match mode_string {
+        Some(m) => m,
         None => {
-            vec![Reply::ErrNeedMoreParams {
+            let (mode_string, mode_arguments) = chan_ctx.modes.mode_string(status.is_some());
+
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![
+                    Reply::ChannelModeIs {
+                        server_host: server_host.to_string(),
+                        nick: nick.to_string(),
+                        channel: channel.to_string(),
+                        mode_string,
+                        mode_arguments,
+                    },
+                    Reply::CreationTime {
+                        server_host: server_host.to_string(),
+                        nick: nick.to_string(),
+                        channel: channel.to_string(),
+                        created_at: chan_ctx.created_at,
+                    },
+                ],
+            )]));
+        }
+    };
+
+    let is_operator = status.map(|s| s.operator).unwrap_or(false);
+
+    let (changes, unknown) = parse_mode_changes(mode_string, mode_arguments);
+
+    // asking to see a list is the only thing anyone can do, and
+    // even then only the operators get to see the exceptions
+    let (queries, changes): (Vec<ModeChange>, Vec<ModeChange>) = changes
+        .into_iter()
+        .partition(|c| mode_class(c.mode) == Some(ModeClass::List) && c.argument.is_none());
+
+    let mut replies = vec![];
+    let mut needs_operator = !is_operator && !changes.is_empty();
+
+    for query in &queries {
+        if query.mode != 'b' && !is_operator {
+            needs_operator = true;
+            continue;
+        }
+
+        replies.extend(list_replies(
+            server_host,
+            nick,
+            channel,
+            query.mode,
+            &chan_ctx.modes,
+        ));
+    }
+
+    let mut errors = unknown
+        .into_iter()
+        .map(|mode| Reply::ErrUnknownMode {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+            mode,
+        })
+        .collect::<Vec<Reply>>();
+
+    if needs_operator {
+        errors.push(Reply::ErrChanOPrivsNeeded {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+            channel: channel.to_string(),
+        });
+    }
+
+    let changes = match is_operator {
+        true => changes,
+        false => vec![],
+    };
+
+    // only so many modes with an argument are taken from one MODE, as
+    // advertised in MODES, and any more are ignored
+    let mut with_argument = 0;
+    let changes = changes
+        .into_iter()
+        .filter(|c| {
+            with_argument += c.argument.is_some() as usize;
+            c.argument.is_none() || with_argument <= MAX_MODES
+        })
+        .collect::<Vec<ModeChange>>();
+
+    // only the changes that actually took effect are told to the channel
+    let mut applied = vec![];
+
+    for change in changes {
+        let argument = match change.mode {
+            'b' | 'e' | 'I' => {
+                let mask = normalize_mask(&change.argument.unwrap_or_default());
+                let is_full = chan_ctx.modes.list_entries() >= max_list_entries;
+
+                let list = match chan_ctx.modes.list_mut(change.mode) {
+                    Some(l) => l,
+                    None => continue,
+                };
+
+                let existing = list.iter().position(|e| e.mask.eq_ignore_ascii_case(&mask));
+
+                match (change.adding, existing) {
+                    (true, None) if is_full => {
+                        errors.push(Reply::ErrBanListFull {
+                            server_host: server_host.to_string(),
+                            nick: nick.to_string(),
+                            channel: channel.to_string(),
+                            mode: change.mode,
+                        });
+                        continue;
+                    }
+                    (true, None) => list.push(ListEntry {
+                        mask: mask.clone(),
+                        set_by: client.to_string(),
+                        set_at: Utc::now(),
+                    }),
+                    (false, Some(i)) => {
+                        list.remove(i);
+                    }
+                    _ => continue,
+                }
+
+                Some(mask)
+            }
+            'o' | 'v' => {
+                let target = change.argument.unwrap_or_default();
+
+                let target_conn = match util::find_connection_by_nick(connections, &target) {
+                    Some(c) => c,
+                    None => {
+                        errors.push(Reply::ErrNoSuchNick {
+                            server_host: server_host.to_string(),
+                            nick: nick.to_string(),
+                            target,
+                        });
+                        continue;
+                    }
+                };
+
+                let status = match chan_ctx.members.get_mut(&target_conn.connection_id) {
+                    Some(s) => s,
+                    None => {
+                        errors.push(Reply::ErrUserNotInChannel {
+                            server_host: server_host.to_string(),
+                            nick: nick.to_string(),
+                            target,
+                            channel: channel.to_string(),
+                        });
+                        continue;
+                    }
+                };
+
+                let flag = match change.mode {
+                    'o' => &mut status.operator,
+                    _ => &mut status.voice,
+                };
+
+                if *flag == change.adding {
+                    continue;
+                }
+
+                *flag = change.adding;
+
+                target_conn.nick.clone()
+            }
+            'k' => {
+                let key = change.argument.unwrap_or_default();
+
+                // keys are sent as a comma separated list on JOIN
+                if change.adding && (key.is_empty() || key.contains(',')) {
+                    continue;
+                }
+
+                if !change.adding && chan_ctx.modes.key.is_none() {
+                    continue;
+                }
+
+                chan_ctx.modes.key = match change.adding {
+                    true => Some(key.clone()),
+                    false => None,
+                };
+
+                Some(key)
+            }
+            'l' => match change.adding {
+                true => {
+                    let limit = match change.argument.as_deref().map(str::parse::<usize>) {
+                        Some(Ok(l)) if l > 0 => l,
+                        _ => continue,
+                    };
+
+                    chan_ctx.modes.limit = Some(limit);
+
+                    Some(limit.to_string())
+                }
+                false => match chan_ctx.modes.limit.take() {
+                    Some(_) => None,
+                    None => continue,
+                },
+            },
+            mode => {
+                let flag = match chan_ctx.modes.flag_mut(mode) {
+                    Some(f) => f,
+                    None => continue,
+                };
+
+                if *flag == change.adding {
+                    continue;
+                }
+
+                *flag = change.adding;
+
+                None
+            }
+        };
+
+        applied.push(ModeChange { argument, ..change });
+    }
+
+    let mut map: HashMap<Uuid, Vec<Reply>> = HashMap::new();
+
+    if !applied.is_empty() {
+        let (mode_string, mode_arguments) = format_mode_changes(&applied);
+
+        let mode_reply = Reply::Mode {
+            client: client.to_string(),
+            target: channel.to_string(),
+            mode_string,
+            mode_arguments,
+        };
+
+        for member in chan_ctx.members.keys() {
+            map.entry(*member).or_default().push(mode_reply.clone());
+        }
+    }
+
+    replies.extend(errors);
+
+    if !replies.is_empty() {
+        map.entry(conn_context.connection_id)
+            .or_default()
+            .extend(replies);
+    }
+
+    Some(map)
+}
+
+fn list_replies(
+    server_host: &str,
+    nick: &str,
+    channel: &str,
+    mode: char,
+    modes: &ChannelModes,
+) -> Vec<Reply> {
+    let entries = match modes.list(mode) {
+        Some(l) => l,
+        None => return vec![],
+    };
+
+    let mut replies = entries
+        .iter()
+        .map(|e| {
+            let server_host = server_host.to_string();
+            let nick = nick.to_string();
+            let channel = channel.to_string();
+            let mask = e.mask.clone();
+            let set_by = e.set_by.clone();
+            let set_at = e.set_at;
+
+            match mode {
+                'e' => Reply::ExceptList {
+                    server_host,
+                    nick,
+                    channel,
+                    mask,
+                    set_by,
+                    set_at,
+                },
+                'I' => Reply::InviteList {
+                    server_host,
+                    nick,
+                    channel,
+                    mask,
+                    set_by,
+                    set_at,
+                },
+                _ => Reply::BanList {
+                    server_host,
+                    nick,
+                    channel,
+                    mask,
+                    set_by,
+                    set_at,
+                },
+            }
+        })
+        .collect::<Vec<Reply>>();
+
+    let server_host = server_host.to_string();
+    let nick = nick.to_string();
+    let channel = channel.to_string();
+
+    replies.push(match mode {
+        'e' => Reply::EndOfExceptList {
+            server_host,
+            nick,
+            channel,
+        },
+        'I' => Reply::EndOfInviteList {
+            server_host,
+            nick,
+            channel,
+        },
+        _ => Reply::EndOfBanList {
+            server_host,
+            nick,
+            channel,
+        },
+    });
+
+    replies
+}
+
+// o is only ever given by OPER, and is the only user mode there is, so a
+// user can look at their modes but there is nothing for them to change
+fn user_mode(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    target: &str,
+    mode_string: &Option<String>,
+) -> Reply {
+    if !target.eq_ignore_ascii_case(nick) {
+        return Reply::ErrUsersDontMatch {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+        };
+    }
+
+    let is_known = |c: char| c == '+' || c == '-' || USER_MODES.contains(c);
+
+    if let Some(m) = mode_string {
+        if !m.chars().all(is_known) {
+            return Reply::ErrUModeUnknownFlag {
                 server_host: server_host.to_string(),
                 nick: nick.to_string(),
-                command: "MODE".to_string(),
-            }]
+            };
         }
+    }
+
+    Reply::UModeIs {
+        server_host: server_host.to_string(),
+        nick: nick.to_string(),
+        mode_string: match conn_context.operator {
+            true => "+o".to_string(),
+            false => "+".to_string(),
+        },
+    }
+}
+
+#[test]
+fn handle_mode_operator_can_op_and_voice_members() {
+    use crate::context::MemberStatus;
+
+    let op_id = Uuid::new_v4();
+    let member_id = Uuid::new_v4();
+    let client = "JIM!~jim@localhost";
+
+    let op = ConnectionContext {
+        connection_id: op_id,
+        nick: Some("JIM".to_string()),
+        ..Default::default()
     };
 
-    map.insert(conn_context.connection_id, replies);
+    let member = ConnectionContext {
+        connection_id: member_id,
+        nick: Some("BOB".to_string()),
+        ..Default::default()
+    };
 
-    Some(map)
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.members.insert(
+        op_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+    chan_ctx.members.insert(member_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let mut connections = HashMap::new();
+    connections.insert(op_id, op);
+    connections.insert(member_id, member);
+
+    let replies = handle_mode(
+        "localhost",
+        "BOB",
+        "BOB!~bob@localhost",
+        &connections[&member_id],
+        &mut channels,
+        &connections,
+        &Some("#foo".to_string()),
+        &Some("+o".to_string()),
+        &["BOB".to_string()],
+        100,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::ErrChanOPrivsNeeded {
+            server_host: "localhost".to_string(),
+            nick: "BOB".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        replies.get(&member_id)
+    );
+
+    let replies = handle_mode(
+        "localhost",
+        "JIM",
+        client,
+        &connections[&op_id],
+        &mut channels,
+        &connections,
+        &Some("#foo".to_string()),
+        &Some("+ov".to_string()),
+        &["bob".to_string(), "bob".to_string()],
+        100,
+    )
+    .expect("Expected replies");
+
+    let expected = Reply::Mode {
+        client: client.to_string(),
+        target: "#foo".to_string(),
+        mode_string: "+ov".to_string(),
+        mode_arguments: vec!["BOB".to_string(), "BOB".to_string()],
+    };
+
+    assert_eq!(Some(&vec![expected.clone()]), replies.get(&op_id));
+    assert_eq!(Some(&vec![expected]), replies.get(&member_id));
+    assert_eq!("@", channels["#foo"].members[&member_id].prefix());
+}
+
+#[test]
+fn handle_mode_channel_modes_are_stored_and_reported() {
+    use crate::context::MemberStatus;
+
+    let op_id = Uuid::new_v4();
+    let client = "JIM!~jim@localhost";
+
+    let op = ConnectionContext {
+        connection_id: op_id,
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.members.insert(
+        op_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let connections = HashMap::new();
+    let channel = Some("#foo".to_string());
+
+    let replies = handle_mode(
+        "localhost",
+        "JIM",
+        client,
+        &op,
+        &mut channels,
+        &connections,
+        &channel,
+        &Some("+ntXlk-m".to_string()),
+        &["5".to_string(), "secret".to_string()],
+        100,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![
+            Reply::Mode {
+                client: client.to_string(),
+                target: "#foo".to_string(),
+                mode_string: "+ntlk".to_string(),
+                mode_arguments: vec!["5".to_string(), "secret".to_string()],
+            },
+            Reply::ErrUnknownMode {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                mode: 'X',
+            }
+        ]),
+        replies.get(&op_id)
+    );
+
+    let replies = handle_mode(
+        "localhost",
+        "JIM",
+        client,
+        &op,
+        &mut channels,
+        &connections,
+        &channel,
+        &None,
+        &[],
+        100,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&op_id).map(|r| r.as_slice()) {
+        Some(
+            [Reply::ChannelModeIs {
+                mode_string,
+                mode_arguments,
+                ..
+            }, Reply::CreationTime { created_at, .. }],
+        ) => {
+            assert_eq!("+ntkl", mode_string);
+            assert_eq!(&vec!["secret".to_string(), "5".to_string()], mode_arguments);
+            assert_eq!(&channels["#foo"].created_at, created_at);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
+
+#[test]
+fn handle_mode_list_modes_are_stored_and_listed() {
+    use crate::context::MemberStatus;
+
+    let op_id = Uuid::new_v4();
+    let member_id = Uuid::new_v4();
+    let client = "JIM!~jim@localhost";
+
+    let op = ConnectionContext {
+        connection_id: op_id,
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let member = ConnectionContext {
+        connection_id: member_id,
+        nick: Some("BOB".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.members.insert(
+        op_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+    chan_ctx.members.insert(member_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let connections = HashMap::new();
+    let channel = Some("#foo".to_string());
+
+    let replies = handle_mode(
+        "localhost",
+        "JIM",
+        client,
+        &op,
+        &mut channels,
+        &connections,
+        &channel,
+        &Some("+bb".to_string()),
+        &["tom".to_string(), "*@host".to_string()],
+        1,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![
+            Reply::Mode {
+                client: client.to_string(),
+                target: "#foo".to_string(),
+                mode_string: "+b".to_string(),
+                mode_arguments: vec!["tom!*@*".to_string()],
+            },
+            Reply::ErrBanListFull {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                channel: "#foo".to_string(),
+                mode: 'b',
+            }
+        ]),
+        replies.get(&op_id)
+    );
+
+    let replies = handle_mode(
+        "localhost",
+        "BOB",
+        "BOB!~bob@localhost",
+        &member,
+        &mut channels,
+        &connections,
+        &channel,
+        &Some("be".to_string()),
+        &[],
+        1,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&member_id).map(|r| r.as_slice()) {
+        Some(
+            [Reply::BanList { mask, set_by, .. }, Reply::EndOfBanList { .. }, Reply::ErrChanOPrivsNeeded { .. }],
+        ) => {
+            assert_eq!("tom!*@*", mask);
+            assert_eq!(client, set_by);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
+
+#[test]
+fn handle_mode_user_modes_are_only_shown_to_their_user() {
+    let conn_ctx = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        operator: true,
+        ..Default::default()
+    };
+
+    let mode = |target: &str, mode_string: Option<&str>| {
+        handle_mode(
+            "localhost",
+            "JIM",
+            "JIM!~jim@localhost",
+            &conn_ctx,
+            &mut HashMap::new(),
+            &HashMap::new(),
+            &Some(target.to_string()),
+            &mode_string.map(|m| m.to_string()),
+            &[],
+            100,
+        )
+        .expect("Expected replies")
+        .remove(&conn_ctx.connection_id)
+        .expect("Expected replies")
+    };
+
+    assert_eq!(
+        vec![Reply::UModeIs {
+            server_host: "localhost".to_string(),
+            nick: "JIM".to_string(),
+            mode_string: "+o".to_string(),
+        }],
+        mode("jim", None)
+    );
+    assert!(matches!(
+        mode("JIM", Some("+x")).as_slice(),
+        [Reply::ErrUModeUnknownFlag { .. }]
+    ));
+    assert!(matches!(
+        mode("BOB", None).as_slice(),
+        [Reply::ErrUsersDontMatch { .. }]
+    ));
 }
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    util,
+};
+
+pub fn handle_names(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    channels_to_list: &Option<Vec<String>>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let mut replies = vec![];
+
+    match channels_to_list {
+        Some(channels_to_list) => {
+            for channel in channels_to_list {
+                match channels.get(&util::casefold(channel)) {
+                    Some(c) if is_visible(c, conn_context) => {
+                        replies.extend(names_replies(server_host, nick, &c.name, c, connections))
+                    }
+                    // a channel that can't be seen looks the same as one that
+                    // doesn't exist
+                    _ => replies.push(Reply::EndOfNames {
+                        server_host: server_host.to_string(),
+                        nick: nick.to_string(),
+                        channel: channel.to_string(),
+                    }),
+                }
+            }
+        }
+        None => {
+            let mut visible = channels
+                .iter()
+                .filter(|(_, c)| is_visible(c, conn_context))
+                .collect::<Vec<(&String, &ChannelContext)>>();
+            visible.sort_unstable_by_key(|(name, _)| name.to_owned());
+
+            let mut listed: HashSet<&Uuid> = HashSet::new();
+
+            for (_, chan_ctx) in visible {
+                listed.extend(chan_ctx.members.keys());
+
+                let mut channel_replies =
+                    names_replies(server_host, nick, &chan_ctx.name, chan_ctx, connections);
+                // there is a single end of names for the whole listing
+                channel_replies.pop();
+
+                replies.extend(channel_replies);
+            }
+
+            // everyone not in a channel that could be listed goes under "*"
+            let mut others = connections
+                .values()
+                .filter(|c| !listed.contains(&c.connection_id))
+                .filter_map(|c| c.nick.clone())
+                .collect::<Vec<String>>();
+            others.sort_unstable();
+
+            replies.extend(Reply::names(server_host, nick, '*', "*", others));
+
+            replies.push(Reply::EndOfNames {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+                channel: "*".to_string(),
+            });
+        }
+    }
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, replies);
+
+    Some(map)
+}
+
+// RPL_NAMREPLY for everyone in the channel followed by RPL_ENDOFNAMES
+pub fn names_replies(
+    server_host: &str,
+    nick: &str,
+    channel: &str,
+    chan_ctx: &ChannelContext,
+    connections: &HashMap<Uuid, ConnectionContext>,
+) -> Vec<Reply> {
+    let mut channel_users = vec![];
+
+    for (member, status) in &chan_ctx.members {
+        let other_user = match connections.get(member) {
+            Some(c) => c,
+            None => {
+                println!(
+                    "Connection context not found for matched channel user {}",
+                    member
+                );
+                continue;
+            }
+        };
+
+        if let Some(e) = &other_user.nick {
+            channel_users.push(format!("{}{}", status.prefix(), e))
+        }
+    }
+
+    let mut replies = Reply::names(
+        server_host,
+        nick,
+        chan_ctx.modes.names_symbol(),
+        channel,
+        channel_users,
+    );
+
+    replies.push(Reply::EndOfNames {
+        server_host: server_host.to_string(),
+        nick: nick.to_string(),
+        channel: channel.to_string(),
+    });
+
+    replies
+}
+
+// secret and private channels can only be seen into from the inside
+fn is_visible(chan_ctx: &ChannelContext, conn_context: &ConnectionContext) -> bool {
+    let hidden = chan_ctx.modes.secret || chan_ctx.modes.private;
+
+    !hidden || chan_ctx.members.contains_key(&conn_context.connection_id)
+}
+
+#[test]
+fn handle_names_secret_channels_are_hidden() {
+    use crate::context::MemberStatus;
+
+    let member_id = Uuid::new_v4();
+    let outsider_id = Uuid::new_v4();
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        member_id,
+        ConnectionContext {
+            connection_id: member_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        outsider_id,
+        ConnectionContext {
+            connection_id: outsider_id,
+            nick: Some("BOB".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.secret = true;
+    chan_ctx.members.insert(
+        member_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let names = |id: &Uuid, channels_to_list: Option<Vec<String>>| {
+        handle_names(
+            "localhost",
+            "NICK",
+            &connections[id],
+            &channels,
+            &connections,
+            &channels_to_list,
+        )
+        .expect("Expected replies")
+        .remove(id)
+        .expect("Expected replies")
+    };
+
+    assert_eq!(
+        vec![
+            Reply::Nam {
+                server_host: "localhost".to_string(),
+                nick: "NICK".to_string(),
+                symbol: '@',
+                channel: "#foo".to_string(),
+                channel_users: vec!["@JIM".to_string()],
+            },
+            Reply::EndOfNames {
+                server_host: "localhost".to_string(),
+                nick: "NICK".to_string(),
+                channel: "#foo".to_string(),
+            }
+        ],
+        names(&member_id, Some(vec!["#foo".to_string()]))
+    );
+
+    assert_eq!(
+        vec![Reply::EndOfNames {
+            server_host: "localhost".to_string(),
+            nick: "NICK".to_string(),
+            channel: "#foo".to_string(),
+        }],
+        names(&outsider_id, Some(vec!["#foo".to_string()]))
+    );
+
+    assert_eq!(
+        vec![
+            Reply::Nam {
+                server_host: "localhost".to_string(),
+                nick: "NICK".to_string(),
+                symbol: '*',
+                channel: "*".to_string(),
+                channel_users: vec!["BOB".to_string(), "JIM".to_string()],
+            },
+            Reply::EndOfNames {
+                server_host: "localhost".to_string(),
+                nick: "NICK".to_string(),
+                channel: "*".to_string(),
+            }
+        ],
+        names(&outsider_id, None)
+    );
+}
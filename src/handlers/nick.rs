@@ -1,27 +1,34 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 use crate::{
-    context::{ConnectionContext, ServerContext},
+    context::{ChannelContext, ConnectionContext, RegistrationState, ServerContext, WhowasHistory},
+    handlers::welcome::advance_registration,
     replies::Reply,
+    util,
 };
 
 pub fn handle_nick(
     server_context: &ServerContext,
     server_host: &str,
     nick: &Option<String>,
-    ctx_version: &str,
-    &ctx_created_at: &DateTime<Utc>,
-    conn_context: &mut ConnectionContext,
+    connection_id: Uuid,
+    connections: &mut HashMap<Uuid, ConnectionContext>,
+    channels: &HashMap<String, ChannelContext>,
+    whowas: &mut WhowasHistory,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let current_nick = match connections.get(&connection_id) {
+        Some(c) => c.nick.clone().unwrap_or_else(|| "*".to_string()),
+        None => return None,
+    };
+
     let nick = match nick {
         Some(n) => n,
         None => {
             let mut map = HashMap::new();
             map.insert(
-                conn_context.connection_id,
+                connection_id,
                 vec![Reply::ErrNoNickGiven {
                     server_host: server_host.to_owned(),
                 }],
@@ -30,104 +37,120 @@ pub fn handle_nick(
         }
     };
 
-    let mut map = HashMap::new();
-    let mut replies: Vec<Reply> = vec![];
+    if !is_valid_nick(nick, server_context.nick_len) {
+        let mut map = HashMap::new();
+        map.insert(
+            connection_id,
+            vec![Reply::ErrErroneusNickname {
+                server_host: server_host.to_owned(),
+                nick: current_nick,
+                attempted_nick: nick.to_owned(),
+            }],
+        );
+        return Some(map);
+    }
+
+    // a user is allowed to change the case of their own nick
+    if let Some(other_user) = util::find_connection_by_nick(connections, nick) {
+        if other_user.connection_id != connection_id {
+            let mut map = HashMap::new();
+            map.insert(
+                connection_id,
+                vec![Reply::ErrNicknameInUse {
+                    server_host: server_host.to_owned(),
+                    nick: current_nick,
+                    attempted_nick: nick.to_owned(),
+                }],
+            );
+            return Some(map);
+        }
+    }
+
+    let conn_context = connections.get_mut(&connection_id)?;
+
+    let old_client = conn_context.client.clone();
+
+    // the old nick is remembered once it has been seen by others
+    if conn_context.registration == RegistrationState::Registered {
+        whowas.record(conn_context);
+    }
 
     conn_context.nick = Some(nick.to_string());
-    conn_context.client = Some(format!("{}!~{}@localhost", nick, nick));
-
-    replies.push(Reply::Welcome {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-    });
-    replies.push(Reply::YourHost {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        version: ctx_version.to_owned(),
-    });
-    replies.push(Reply::Created {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        created_at: ctx_created_at,
-    });
-    replies.push(Reply::MyInfo {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        version: ctx_version.to_owned(),
-        user_modes: "r".to_string(),
-        channel_modes: "i".to_string(),
-    });
-    replies.push(Reply::Support {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        channel_len: 32,
-    });
-    replies.push(Reply::LuserClient {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        visible_users: 100,
-        invisible_users: 20,
-        servers: 1,
-    });
-    replies.push(Reply::LuserOp {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        operators: 1337,
-    });
-    replies.push(Reply::LuserUnknown {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        unknown: 7,
-    });
-    replies.push(Reply::LuserChannels {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        channels: 9999,
-    });
-    replies.push(Reply::LuserMe {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        clients: 900,
-        servers: 1,
-    });
-    replies.push(Reply::LocalUsers {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        current: 845,
-        max: 1000,
-    });
-    replies.push(Reply::GlobalUsers {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        current: 9832,
-        max: 23455,
-    });
-    replies.push(Reply::StatsDLine {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-        connections: 9998,
-        clients: 9000,
-        received: 99999,
-    });
-    replies.push(Reply::MotdStart {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-    });
-
-    for line in &server_context.motd_lines {
-        replies.push(Reply::Motd {
-            server_host: server_host.to_owned(),
-            nick: nick.clone(),
-            line: line.to_string(),
-        });
+    conn_context.client = Some(format!(
+        "{}!~{}@{}",
+        nick,
+        conn_context.user.as_ref().unwrap_or(nick),
+        conn_context.host()
+    ));
+
+    // once registered this is just a change of nick, which needs
+    // to be told to anyone who can see this user in a channel
+    if conn_context.registration == RegistrationState::Registered {
+        let old_client = old_client.unwrap_or_default();
+
+        let mut recipients = HashSet::new();
+        recipients.insert(connection_id);
+
+        for channel in channels.values() {
+            if channel.members.contains_key(&connection_id) {
+                recipients.extend(channel.members.keys());
+            }
+        }
+
+        let mut map = HashMap::new();
+
+        for recipient in recipients {
+            map.insert(
+                recipient,
+                vec![Reply::Nick {
+                    client: old_client.clone(),
+                    nick: nick.to_owned(),
+                }],
+            );
+        }
+
+        return Some(map);
     }
 
-    replies.push(Reply::EndOfMotd {
-        server_host: server_host.to_owned(),
-        nick: nick.clone(),
-    });
+    // until registration completes nobody else can see this user, the welcome
+    // burst is only sent once both NICK and USER have been received
+    let next = conn_context.registration.with_nick();
 
-    map.insert(conn_context.connection_id, replies);
+    advance_registration(server_context, conn_context, next)
+}
+
+// nickname   =  ( letter / special ) *8( letter / digit / special / "-" )
+// special    =  %x5B-60 / %x7B-7D
+// RFC 2812 caps nicks at 9 characters, the limit here is configurable instead
+fn is_valid_nick(nick: &str, max_len: usize) -> bool {
+    let is_special = |c: char| matches!(c, '[' | ']' | '\\' | '`' | '_' | '^' | '{' | '|' | '}');
+
+    let mut chars = nick.chars();
+
+    let first_valid = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || is_special(c),
+        None => false,
+    };
 
-    Some(map)
+    first_valid
+        && nick.len() <= max_len
+        && chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("JOE", true ; "letters")]
+    #[test_case("[joe]-2", true ; "specials_digits_and_dash")]
+    #[test_case("2joe", false ; "leading_digit")]
+    #[test_case("-joe", false ; "leading_dash")]
+    #[test_case("jo e", false ; "space")]
+    #[test_case("joe!", false ; "invalid_char")]
+    #[test_case("", false ; "empty")]
+    #[test_case("abcdefghijk", false ; "too_long")]
+    fn is_valid_nick_validates(nick: &str, expected: bool) {
+        assert_eq!(expected, is_valid_nick(nick, 10));
+    }
 }
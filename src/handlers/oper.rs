@@ -0,0 +1,225 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+
+use crate::{
+    context::ConnectionContext,
+    message_parsing::{Command, Message},
+    oper::OperBlock,
+    replies::Reply,
+    tags::Tags,
+};
+
+// Checking the password is slow by design, so it is done on a blocking
+// thread and the result is sent back to be picked up by handle_oper_verified.
+// Each connection only gets one check at a time, any OPER sent while one is
+// going is dropped, so nobody can tie up every blocking thread.
+pub fn handle_oper(
+    server_host: &str,
+    connection_id: Uuid,
+    connections: &mut HashMap<Uuid, ConnectionContext>,
+    opers: &[OperBlock],
+    name: &Option<String>,
+    password: &Option<String>,
+    verified_sender: &Sender<Message>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let conn_context = connections.get_mut(&connection_id)?;
+
+    if conn_context.oper_pending {
+        return None;
+    }
+
+    let nick = conn_context.nick.clone().unwrap_or_default();
+    let client = conn_context.client.as_deref().unwrap_or_default();
+
+    let error = |reply: Reply| Some(HashMap::<_, _>::from_iter([(connection_id, vec![reply])]));
+
+    let (name, password) = match (name, password) {
+        (Some(n), Some(p)) => (n, p),
+        _ => {
+            return error(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick,
+                command: "OPER".to_string(),
+            })
+        }
+    };
+
+    // an unknown name looks the same as a known one from the wrong host
+    let block = match opers
+        .iter()
+        .find(|o| &o.name == name && o.matches_host(client))
+    {
+        Some(b) => b.clone(),
+        None => {
+            return error(Reply::ErrNoOperHost {
+                server_host: server_host.to_owned(),
+                nick,
+            })
+        }
+    };
+
+    conn_context.oper_pending = true;
+
+    let password = password.clone();
+    let verified_sender = verified_sender.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let message = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::OperVerified {
+                verified: block.verify_password(&password),
+                name: block.name,
+            },
+            connection_id,
+        };
+
+        if let Err(e) = verified_sender.blocking_send(message) {
+            println!("Error sending OPER result for {} {:?}", connection_id, e);
+        }
+    });
+
+    None
+}
+
+pub fn handle_oper_verified(
+    server_host: &str,
+    connection_id: Uuid,
+    connections: &mut HashMap<Uuid, ConnectionContext>,
+    opers: &[OperBlock],
+    name: &str,
+    verified: bool,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let conn_context = connections.get_mut(&connection_id)?;
+    let nick = conn_context.nick.clone().unwrap_or_default();
+    let client = conn_context.client.clone().unwrap_or_default();
+
+    conn_context.oper_pending = false;
+
+    let error = |reply: Reply| Some(HashMap::<_, _>::from_iter([(connection_id, vec![reply])]));
+
+    if !verified {
+        return error(Reply::ErrPasswdMismatch {
+            server_host: server_host.to_owned(),
+            nick,
+        });
+    }
+
+    // the block is looked up again as it may have gone in a rehash since
+    let block = match opers
+        .iter()
+        .find(|o| o.name == name && o.matches_host(&client))
+    {
+        Some(b) => b,
+        None => {
+            return error(Reply::ErrNoOperHost {
+                server_host: server_host.to_owned(),
+                nick,
+            })
+        }
+    };
+
+    println!("{} is now an operator as {}", client, block.name);
+
+    conn_context.operator = true;
+    conn_context.privileges = block.privileges.iter().copied().collect();
+
+    let mut map = HashMap::new();
+    map.insert(
+        connection_id,
+        vec![
+            Reply::YoureOper {
+                server_host: server_host.to_owned(),
+                nick: nick.clone(),
+            },
+            Reply::Mode {
+                client: nick.clone(),
+                target: nick,
+                mode_string: "+o".to_string(),
+                mode_arguments: vec![],
+            },
+        ],
+    );
+
+    Some(map)
+}
+
+#[tokio::test]
+async fn handle_oper_checks_host_and_password() {
+    use tokio::sync::mpsc;
+
+    use crate::oper::test_oper_block;
+
+    let connection_id = Uuid::new_v4();
+    let mut connections = HashMap::new();
+    connections.insert(
+        connection_id,
+        ConnectionContext {
+            connection_id,
+            nick: Some("JIM".to_string()),
+            client: Some("JIM!~jim@localhost".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let opers = vec![test_oper_block("hunter2")];
+    let (verified_sender, mut verified_receiver) = mpsc::channel(1);
+
+    let oper = |connections: &mut HashMap<Uuid, ConnectionContext>, name: &str, password: &str| {
+        handle_oper(
+            "localhost",
+            connection_id,
+            connections,
+            &opers,
+            &Some(name.to_string()),
+            &Some(password.to_string()),
+            &verified_sender,
+        )
+    };
+
+    assert!(matches!(
+        oper(&mut connections, "nobody", "hunter2").expect("Expected replies")[&connection_id]
+            .as_slice(),
+        [Reply::ErrNoOperHost { .. }]
+    ));
+
+    // a known oper goes round through the blocking check, as in the handler
+    for (password, operator) in [("wrong", false), ("hunter2", true)] {
+        assert_eq!(None, oper(&mut connections, "admin", password));
+        assert!(connections[&connection_id].oper_pending);
+
+        // nothing more is checked until the first one is done
+        assert_eq!(None, oper(&mut connections, "admin", "hunter2"));
+
+        let replies = match verified_receiver.recv().await.map(|m| m.command) {
+            Some(Command::OperVerified { name, verified }) => handle_oper_verified(
+                "localhost",
+                connection_id,
+                &mut connections,
+                &opers,
+                &name,
+                verified,
+            )
+            .expect("Expected replies"),
+            r => panic!("Unexpected message {:?}", r),
+        };
+
+        match operator {
+            true => assert!(matches!(
+                replies[&connection_id].as_slice(),
+                [Reply::YoureOper { .. }, Reply::Mode { .. }]
+            )),
+            false => assert!(matches!(
+                replies[&connection_id].as_slice(),
+                [Reply::ErrPasswdMismatch { .. }]
+            )),
+        }
+
+        assert_eq!(operator, connections[&connection_id].operator);
+        assert!(!connections[&connection_id].oper_pending);
+    }
+
+    assert!(verified_receiver.try_recv().is_err());
+}
@@ -5,6 +5,8 @@ use uuid::Uuid;
 use crate::{
     context::{ChannelContext, ConnectionContext},
     replies::Reply,
+    tags::server_tags,
+    util,
 };
 
 pub fn handle_part(
@@ -44,41 +46,58 @@ pub fn handle_part(
     let mut replies_to_user = vec![];
 
     for channel in channels_to_leave {
-        match channels.get_mut(channel) {
+        let folded = util::casefold(channel);
+
+        match channels.get_mut(&folded) {
             Some(ctx) => {
-                if !ctx.members.contains(&conn_context.connection_id) {
+                if !ctx.members.contains_key(&conn_context.connection_id) {
                     replies_to_user.push(Reply::ErrNotOnChannel {
                         server_host: server_host.to_owned(),
+                        nick: nick.to_owned(),
                         channel: channel.to_owned(),
                     });
                     continue;
                 }
 
-                for member in &ctx.members {
+                let part_reply = Reply::Part {
+                    client: client.to_owned(),
+                    channel: channel.to_owned(),
+                }
+                .with_tags(server_tags());
+
+                // the parting user gets the PART too, so they know it went through
+                for member in ctx.members.keys() {
                     if member == &conn_context.connection_id {
+                        replies_to_user.push(part_reply.clone());
                         continue;
                     }
 
-                    map.insert(
-                        *member,
-                        vec![Reply::Part {
-                            client: client.to_owned(),
-                            channel: channel.to_owned(),
-                        }],
-                    );
+                    map.entry(*member)
+                        .or_insert_with(Vec::new)
+                        .push(part_reply.clone());
                 }
 
                 ctx.members.remove(&conn_context.connection_id);
+
+                // an empty channel is gone, along with its modes
+                if ctx.members.is_empty() {
+                    channels.remove(&folded);
+                }
             }
             None => {
                 replies_to_user.push(Reply::ErrNoSuchChannel {
                     server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
                     channel: channel.to_owned(),
                 });
             }
         }
     }
 
+    if !replies_to_user.is_empty() {
+        map.insert(conn_context.connection_id, replies_to_user);
+    }
+
     Some(map)
 }
 
@@ -87,8 +106,10 @@ fn handle_part_no_channels_returns_error() {
     let connection_id = Uuid::new_v4();
     let server_host = "FOOBAR";
 
-    let mut conn_ctx = ConnectionContext::default();
-    conn_ctx.connection_id = connection_id;
+    let conn_ctx = ConnectionContext {
+        connection_id,
+        ..Default::default()
+    };
 
     let mut channels = HashMap::new();
     let expected_reply = Reply::ErrNeedMoreParams {
@@ -106,9 +127,9 @@ fn handle_part_no_channels_returns_error() {
                     let first_reply = r.first().unwrap();
                     assert_eq!(&expected_reply, first_reply);
                 }
-                None => assert!(false),
+                None => panic!("Expected replies"),
             }
         }
-        None => assert!(false),
+        None => panic!("Expected replies"),
     }
 }
@@ -1,86 +1,524 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, iter::FromIterator};
 
 use uuid::Uuid;
 
 use crate::{
+    capabilities::Capability,
     context::{ChannelContext, ConnectionContext},
     replies::Reply,
+    tags::{client_only_tags, server_tags, Tags},
+    util,
 };
 
+#[derive(Clone, Copy, PartialEq)]
+enum RelayKind {
+    PrivMsg,
+    Notice,
+    TagMsg,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_privmsg(
     server_host: &str,
     nick: &str,
-    channel: &Option<String>,
+    tags: &Tags,
+    targets: &Option<Vec<String>>,
     message: &Option<String>,
     conn_context: &ConnectionContext,
     channels: &HashMap<String, ChannelContext>,
     connections: &HashMap<Uuid, ConnectionContext>,
+    max_targets: usize,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
-    let channel = match channel {
-        Some(c) => c,
-        None => {
-            let mut map = HashMap::new();
-            map.insert(
+    relay_message(
+        server_host,
+        nick,
+        tags,
+        targets,
+        message.as_deref(),
+        conn_context,
+        channels,
+        connections,
+        max_targets,
+        RelayKind::PrivMsg,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_notice(
+    server_host: &str,
+    nick: &str,
+    tags: &Tags,
+    targets: &Option<Vec<String>>,
+    message: &Option<String>,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    max_targets: usize,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    relay_message(
+        server_host,
+        nick,
+        tags,
+        targets,
+        message.as_deref(),
+        conn_context,
+        channels,
+        connections,
+        max_targets,
+        RelayKind::Notice,
+    )
+}
+
+// TAGMSG carries nothing but tags, so it is only ever relayed to
+// clients that have negotiated message-tags
+#[allow(clippy::too_many_arguments)]
+pub fn handle_tagmsg(
+    server_host: &str,
+    nick: &str,
+    tags: &Tags,
+    targets: &Option<Vec<String>>,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    max_targets: usize,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    relay_message(
+        server_host,
+        nick,
+        tags,
+        targets,
+        None,
+        conn_context,
+        channels,
+        connections,
+        max_targets,
+        RelayKind::TagMsg,
+    )
+}
+
+// NOTICE must never trigger an automatic reply (RFC 2812 3.3.2), so when
+// relaying a notice any error that would normally go back to the sender
+// is silently dropped instead, and a TAGMSG has no message at all
+#[allow(clippy::too_many_arguments)]
+fn relay_message(
+    server_host: &str,
+    nick: &str,
+    tags: &Tags,
+    targets: &Option<Vec<String>>,
+    message: Option<&str>,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    max_targets: usize,
+    kind: RelayKind,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let is_notice = kind == RelayKind::Notice;
+
+    let command = match kind {
+        RelayKind::PrivMsg => "PRIVMSG",
+        RelayKind::Notice => "NOTICE",
+        RelayKind::TagMsg => "TAGMSG",
+    };
+
+    let targets = match targets {
+        Some(t) if !t.is_empty() => t,
+        _ if is_notice => return None,
+        _ => {
+            return Some(HashMap::<_, _>::from_iter([(
                 conn_context.connection_id,
-                vec![Reply::ErrNeedMoreParams {
+                vec![Reply::ErrNoRecipient {
                     server_host: server_host.to_string(),
                     nick: nick.to_string(),
-                    command: "PRIVMSG".to_string(),
+                    command: command.to_string(),
                 }],
-            );
-            return Some(map);
+            )]));
         }
     };
 
     let message = match message {
-        Some(m) => m,
+        _ if kind == RelayKind::TagMsg => None,
+        Some(m) => Some(m),
+        None if is_notice => return None,
         None => {
-            let mut map = HashMap::new();
-            map.insert(
+            return Some(HashMap::<_, _>::from_iter([(
                 conn_context.connection_id,
-                vec![Reply::ErrNeedMoreParams {
+                vec![Reply::ErrNoTextToSend {
                     server_host: server_host.to_string(),
                     nick: nick.to_string(),
-                    command: "PRIVMSG".to_string(),
                 }],
-            );
-            return Some(map);
+            )]));
         }
     };
 
-    let mut map = HashMap::new();
-    let channel_ctx = match channels.get(channel) {
-        Some(c) => c,
-        None => {
-            println!("Unable to send message to channel {}, not found", channel);
-            return None;
-        }
+    // only client-only tags are relayed, anything else is for the server,
+    // and every recipient gets the same time and msgid
+    let mut relayed_tags = client_only_tags(tags);
+    relayed_tags.extend(server_tags());
+
+    let client = conn_context.client.as_deref().unwrap_or_default();
+
+    let build_reply = |target: &str| {
+        let reply = match (kind, message) {
+            (RelayKind::PrivMsg, Some(message)) => Reply::PrivMsg {
+                client: client.to_string(),
+                target: target.to_string(),
+                message: message.to_string(),
+            },
+            (RelayKind::Notice, Some(message)) => Reply::Notice {
+                client: client.to_string(),
+                target: target.to_string(),
+                message: message.to_string(),
+            },
+            (RelayKind::TagMsg, _) | (_, None) => Reply::TagMsg {
+                client: client.to_string(),
+                target: target.to_string(),
+            },
+        };
+
+        reply.with_tags(relayed_tags.clone())
     };
 
-    for member in &channel_ctx.members {
-        if member == &conn_context.connection_id {
+    let can_receive = |c: &ConnectionContext| {
+        kind != RelayKind::TagMsg || c.capabilities.contains(&Capability::MessageTags)
+    };
+
+    let mut map: HashMap<Uuid, Vec<Reply>> = HashMap::new();
+    let mut errors = vec![];
+
+    // clients with echo-message get their own messages back once they
+    // have been accepted, so they know how the server relayed them
+    let echo =
+        conn_context.capabilities.contains(&Capability::EchoMessage) && can_receive(conn_context);
+
+    for (i, target) in targets.iter().enumerate() {
+        if i >= max_targets {
+            errors.push(Reply::ErrTooManyTargets {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+                target: target.to_string(),
+            });
             continue;
         }
 
-        let connected_member = match connections.get(member) {
-            Some(conn) => conn,
+        if !target.starts_with('#') {
+            match util::find_connection_by_nick(connections, target) {
+                Some(other_user) => {
+                    if can_receive(other_user) {
+                        map.entry(other_user.connection_id)
+                            .or_default()
+                            .push(build_reply(target));
+                    }
+
+                    // only a PRIVMSG gets the automatic away reply
+                    if let (RelayKind::PrivMsg, Some(away)) = (kind, &other_user.away) {
+                        errors.push(Reply::Away {
+                            server_host: server_host.to_string(),
+                            nick: nick.to_string(),
+                            target: other_user.nick.clone().unwrap_or_default(),
+                            message: away.clone(),
+                        });
+                    }
+
+                    if echo && other_user.connection_id != conn_context.connection_id {
+                        map.entry(conn_context.connection_id)
+                            .or_default()
+                            .push(build_reply(target));
+                    }
+                }
+                None => errors.push(Reply::ErrNoSuchNick {
+                    server_host: server_host.to_string(),
+                    nick: nick.to_string(),
+                    target: target.to_string(),
+                }),
+            }
+
+            continue;
+        }
+
+        let channel_ctx = match channels.get(&util::casefold(target)) {
+            Some(c) => c,
             None => {
-                println!("Unable to find member {} in connections map", member);
+                errors.push(Reply::ErrNoSuchChannel {
+                    server_host: server_host.to_string(),
+                    nick: nick.to_string(),
+                    channel: target.to_string(),
+                });
                 continue;
             }
         };
 
-        map.insert(
-            connected_member.connection_id,
-            vec![Reply::PrivMsg {
-                nick: Some(nick.to_string()),
-                user: conn_context.user.clone(),
-                client_host: conn_context.client_host,
-                channel: channel.to_string(),
-                message: message.to_string(),
-            }],
-        );
+        // +n keeps out anyone not in the channel, +m anyone without a voice
+        // and +b anyone banned, unless they have been given a voice since
+        let modes = &channel_ctx.modes;
+        let is_banned = modes.is_banned(conn_context.client.as_deref().unwrap_or_default());
+
+        let can_send = match channel_ctx.status_of(&conn_context.connection_id) {
+            Some(s) => s.operator || s.voice || (!modes.moderated && !is_banned),
+            None => !modes.no_external && !modes.moderated && !is_banned,
+        };
+
+        if !can_send {
+            errors.push(Reply::ErrCannotSendToChan {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+                channel: target.to_string(),
+            });
+            continue;
+        }
+
+        for member in channel_ctx.members.keys() {
+            if member == &conn_context.connection_id {
+                continue;
+            }
+
+            let connected_member = match connections.get(member) {
+                Some(conn) => conn,
+                None => {
+                    println!("Unable to find member {} in connections map", member);
+                    continue;
+                }
+            };
+
+            if !can_receive(connected_member) {
+                continue;
+            }
+
+            map.entry(connected_member.connection_id)
+                .or_default()
+                .push(build_reply(target));
+        }
+
+        if echo {
+            map.entry(conn_context.connection_id)
+                .or_default()
+                .push(build_reply(target));
+        }
+    }
+
+    if !is_notice && !errors.is_empty() {
+        map.entry(conn_context.connection_id)
+            .or_default()
+            .extend(errors);
     }
 
     Some(map)
 }
+
+#[test]
+fn handle_privmsg_nick_targets_are_resolved() {
+    let sender_id = Uuid::new_v4();
+    let receiver_id = Uuid::new_v4();
+    let server_host = "FOOBAR";
+
+    let sender = ConnectionContext {
+        connection_id: sender_id,
+        nick: Some("JIM".to_string()),
+        client: Some("JIM!~jim@localhost".to_string()),
+        ..Default::default()
+    };
+
+    let receiver = ConnectionContext {
+        connection_id: receiver_id,
+        nick: Some("BOB".to_string()),
+        ..Default::default()
+    };
+
+    let mut connections = HashMap::new();
+    connections.insert(receiver_id, receiver);
+
+    let replies = handle_privmsg(
+        server_host,
+        "JIM",
+        &Tags::new(),
+        &Some(vec!["bob".to_string(), "NOBODY".to_string()]),
+        &Some("hi there".to_string()),
+        &sender,
+        &HashMap::new(),
+        &connections,
+        4,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&receiver_id).map(|r| r.as_slice()) {
+        Some([Reply::Tagged { tags, reply }]) => {
+            assert!(tags.contains_key("time"));
+            assert!(tags.contains_key("msgid"));
+            assert_eq!(
+                &Reply::PrivMsg {
+                    client: "JIM!~jim@localhost".to_string(),
+                    target: "bob".to_string(),
+                    message: "hi there".to_string(),
+                },
+                reply.as_ref()
+            );
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    assert_eq!(
+        Some(&vec![Reply::ErrNoSuchNick {
+            server_host: server_host.to_string(),
+            nick: "JIM".to_string(),
+            target: "NOBODY".to_string(),
+        }]),
+        replies.get(&sender_id)
+    );
+}
+
+#[test]
+fn handle_privmsg_channel_modes_are_enforced() {
+    use crate::context::MemberStatus;
+
+    let sender_id = Uuid::new_v4();
+    let member_id = Uuid::new_v4();
+
+    let sender = ConnectionContext {
+        connection_id: sender_id,
+        nick: Some("JIM".to_string()),
+        client: Some("JIM!~jim@localhost".to_string()),
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.no_external = true;
+    chan_ctx.members.insert(member_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let send = |channels: &HashMap<String, ChannelContext>| {
+        handle_privmsg(
+            "localhost",
+            "JIM",
+            &Tags::new(),
+            &Some(vec!["#foo".to_string()]),
+            &Some("hi there".to_string()),
+            &sender,
+            channels,
+            &HashMap::new(),
+            4,
+        )
+        .expect("Expected replies")
+    };
+
+    let cannot_send = vec![Reply::ErrCannotSendToChan {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+    }];
+
+    assert_eq!(Some(&cannot_send), send(&channels).get(&sender_id));
+
+    let chan_ctx = channels.get_mut("#foo").unwrap();
+    chan_ctx.members.insert(sender_id, MemberStatus::default());
+    chan_ctx.modes.moderated = true;
+
+    assert_eq!(Some(&cannot_send), send(&channels).get(&sender_id));
+
+    channels.get_mut("#foo").unwrap().members.insert(
+        sender_id,
+        MemberStatus {
+            operator: false,
+            voice: true,
+        },
+    );
+
+    assert_eq!(None, send(&channels).get(&sender_id));
+}
+
+#[test]
+fn handle_privmsg_too_many_targets_are_rejected() {
+    let sender = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        nick: Some("JIM".to_string()),
+        ..Default::default()
+    };
+
+    let replies = handle_privmsg(
+        "localhost",
+        "JIM",
+        &Tags::new(),
+        &Some(vec!["JIM".to_string(), "BOB".to_string()]),
+        &Some("hi there".to_string()),
+        &sender,
+        &HashMap::new(),
+        &HashMap::new(),
+        1,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![
+            Reply::ErrNoSuchNick {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                target: "JIM".to_string(),
+            },
+            Reply::ErrTooManyTargets {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                target: "BOB".to_string(),
+            }
+        ]),
+        replies.get(&sender.connection_id)
+    );
+}
+
+#[test]
+fn handle_tagmsg_is_only_relayed_to_message_tags_clients() {
+    let sender_id = Uuid::new_v4();
+    let tags_id = Uuid::new_v4();
+    let plain_id = Uuid::new_v4();
+
+    let sender = ConnectionContext {
+        connection_id: sender_id,
+        nick: Some("JIM".to_string()),
+        client: Some("JIM!~jim@localhost".to_string()),
+        ..Default::default()
+    };
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        tags_id,
+        ConnectionContext {
+            connection_id: tags_id,
+            nick: Some("BOB".to_string()),
+            capabilities: vec![Capability::MessageTags].into_iter().collect(),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        plain_id,
+        ConnectionContext {
+            connection_id: plain_id,
+            nick: Some("ALICE".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let replies = handle_tagmsg(
+        "localhost",
+        "JIM",
+        &Tags::new(),
+        &Some(vec!["BOB".to_string(), "ALICE".to_string()]),
+        &sender,
+        &HashMap::new(),
+        &connections,
+        4,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&tags_id).map(|r| r.as_slice()) {
+        Some([Reply::Tagged { reply, .. }]) => assert_eq!(
+            &Reply::TagMsg {
+                client: "JIM!~jim@localhost".to_string(),
+                target: "BOB".to_string(),
+            },
+            reply.as_ref()
+        ),
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    assert_eq!(None, replies.get(&plain_id));
+    assert_eq!(None, replies.get(&sender_id));
+}
@@ -2,15 +2,44 @@ use std::collections::HashMap;
 use uuid::Uuid;
 
 use crate::{
-    context::{ChannelContext, ConnectionContext},
+    context::{ChannelContext, ConnectionContext, WhowasHistory},
     replies::Reply,
+    tags::server_tags,
 };
 
 pub fn handle_quit(
     message: &Option<String>,
     channels: &mut HashMap<String, ChannelContext>,
     connections: &HashMap<Uuid, ConnectionContext>,
+    whowas: &mut WhowasHistory,
     connection_id: Uuid,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let message = match message {
+        Some(m) => m.to_string(),
+        None => "DEFAULT QUIT MESSAGE TODO".to_string(),
+    };
+
+    let reason = format!("Quit: {}", message);
+
+    disconnect(
+        connection_id,
+        &message,
+        &reason,
+        channels,
+        connections,
+        whowas,
+    )
+}
+
+// Takes a connection out of its channels, telling everyone there it quit with
+// the given message, then closes its link with the reason given
+pub fn disconnect(
+    connection_id: Uuid,
+    message: &str,
+    reason: &str,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    whowas: &mut WhowasHistory,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
     let conn_context = match connections.get(&connection_id) {
         Some(c) => c,
@@ -19,42 +48,58 @@ pub fn handle_quit(
         }
     };
 
+    whowas.record(conn_context);
+
+    let mut map = leave_channels(conn_context, message, channels, connections);
+
+    // the QUIT is never tagged as the client sender needs to recognise it to shut down
+    map.insert(
+        connection_id,
+        vec![
+            Reply::Error {
+                message: format!("Closing Link: {} ({})", conn_context.host(), reason),
+            },
+            Reply::Quit {
+                connection_id,
+                client: conn_context.client.clone().unwrap_or_default(),
+                message: message.to_string(),
+            },
+        ],
+    );
+
+    Some(map)
+}
+
+// Takes a connection out of every channel it is in, with a QUIT for everyone
+// who was in one with it. Any channel left empty is gone.
+pub fn leave_channels(
+    conn_context: &ConnectionContext,
+    message: &str,
+    channels: &mut HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+) -> HashMap<Uuid, Vec<Reply>> {
     let mut map = HashMap::new();
-    let message = match message {
-        Some(m) => m.to_string(),
-        None => "DEFAULT QUIT MESSAGE TODO".to_string(),
-    };
 
-    for channel in channels {
-        if !channel.1.members.contains(&conn_context.connection_id) {
-            continue;
-        }
+    let quit_reply = Reply::Quit {
+        connection_id: conn_context.connection_id,
+        client: conn_context.client.clone().unwrap_or_default(),
+        message: message.to_string(),
+    }
+    .with_tags(server_tags());
 
-        // if the quitting user was part of this channel, remove them
-        // from the list and then send a QUIT to everyone other user
-        // in the channel
-        if !channel.1.members.remove(&conn_context.connection_id) {
-            println!(
-                "UNABLE TO REMOVE {} FROM CHANNEL {}",
-                &conn_context.nick.as_ref().unwrap(),
-                channel.0
-            );
+    for chan_ctx in channels.values_mut() {
+        if chan_ctx
+            .members
+            .remove(&conn_context.connection_id)
+            .is_none()
+        {
             continue;
         }
 
-        for member in &channel.1.members {
+        for member in chan_ctx.members.keys() {
             match connections.get(member) {
                 Some(_c) => {
-                    map.insert(
-                        *member,
-                        vec![Reply::Quit {
-                            connection_id,
-                            client_host: conn_context.client_host,
-                            nick: conn_context.nick.clone(),
-                            user: conn_context.user.clone(),
-                            message: message.to_string(),
-                        }],
-                    );
+                    map.insert(*member, vec![quit_reply.clone()]);
                 }
                 None => {
                     println!(
@@ -66,16 +111,58 @@ pub fn handle_quit(
         }
     }
 
-    map.insert(
-        connection_id,
-        vec![Reply::Quit {
+    channels.retain(|_, c| !c.members.is_empty());
+
+    map
+}
+
+#[test]
+fn leave_channels_removes_member_and_empty_channels() {
+    use crate::context::MemberStatus;
+
+    let leaving_id = Uuid::new_v4();
+    let other_id = Uuid::new_v4();
+
+    let mut connections = HashMap::new();
+    for (connection_id, nick) in [(leaving_id, "JIM"), (other_id, "BOB")] {
+        connections.insert(
             connection_id,
-            client_host: conn_context.client_host,
-            nick: conn_context.nick.clone(),
-            user: conn_context.user.clone(),
-            message,
-        }],
+            ConnectionContext {
+                connection_id,
+                nick: Some(nick.to_string()),
+                client: Some(format!("{}!~{}@localhost", nick, nick.to_lowercase())),
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut shared = ChannelContext::new("#foo");
+    shared.members.insert(leaving_id, MemberStatus::default());
+    shared.members.insert(other_id, MemberStatus::default());
+
+    let mut alone = ChannelContext::new("#bar");
+    alone.members.insert(leaving_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), shared);
+    channels.insert("#bar".to_string(), alone);
+
+    let replies = leave_channels(
+        &connections[&leaving_id],
+        "Connection closed",
+        &mut channels,
+        &connections,
     );
 
-    Some(map)
+    assert_eq!(vec!["#foo"], channels.keys().collect::<Vec<&String>>());
+    assert!(!channels["#foo"].members.contains_key(&leaving_id));
+
+    match replies.get(&other_id).map(|r| r.as_slice()) {
+        Some([Reply::Tagged { reply, .. }]) => assert_eq!(
+            ":JIM!~jim@localhost QUIT :Connection closed",
+            reply.to_string()
+        ),
+        r => panic!("Unexpected replies {:?}", r),
+    }
+    assert!(!replies.contains_key(&leaving_id));
 }
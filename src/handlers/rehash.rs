@@ -0,0 +1,215 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{
+    context::{ConnectionContext, RegistrationState, ServerContext},
+    isupport::isupport_tokens,
+    oper::{check_privilege, Privilege},
+    replies::Reply,
+    result::Result,
+    settings::{Settings, SETTINGS_FILE},
+};
+
+pub fn handle_rehash(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    server_context: &mut ServerContext,
+    context_sender: &watch::Sender<ServerContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    if let Some(reply) = check_privilege(server_host, nick, conn_context, Privilege::Rehash) {
+        return Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]));
+    }
+
+    println!("{} is rehashing the server", nick);
+
+    let mut map = rehash(
+        server_context,
+        context_sender,
+        connections,
+        Settings::load(),
+    );
+
+    map.entry(conn_context.connection_id).or_default().insert(
+        0,
+        Reply::Rehashing {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            config_file: SETTINGS_FILE.to_string(),
+        },
+    );
+
+    Some(map)
+}
+
+// Swaps in freshly loaded settings as long as they are valid, passing them
+// on to every task holding a copy, and tells the operators how it went.
+// Everyone is sent ISUPPORT again as the limits in it may have changed.
+pub fn rehash(
+    server_context: &mut ServerContext,
+    context_sender: &watch::Sender<ServerContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    settings: Result<Settings>,
+) -> HashMap<Uuid, Vec<Reply>> {
+    let (reloaded, notices) = match settings {
+        Ok(settings) => {
+            let not_reloaded = server_context.reload(&settings);
+
+            if context_sender.send(server_context.clone()).is_err() {
+                println!("No tasks left to pass the reloaded settings to");
+            }
+
+            let notices = std::iter::once("Settings reloaded".to_string())
+                .chain(
+                    not_reloaded
+                        .iter()
+                        .map(|s| format!("Not reloaded, {} needs a restart to change", s)),
+                )
+                .collect::<Vec<String>>();
+
+            (true, notices)
+        }
+        Err(e) => (
+            false,
+            vec![format!(
+                "Rehash failed, keeping the current settings. {}",
+                e
+            )],
+        ),
+    };
+
+    for notice in &notices {
+        println!("{}", notice);
+    }
+
+    let server_host = &server_context.server_host;
+    let tokens = isupport_tokens(server_context);
+
+    let mut map = HashMap::new();
+
+    for conn_context in connections
+        .values()
+        .filter(|c| c.registration == RegistrationState::Registered)
+    {
+        let nick = conn_context.nick.clone().unwrap_or_default();
+        let mut replies = vec![];
+
+        if conn_context.operator {
+            replies.extend(notices.iter().map(|n| Reply::ServerNotice {
+                server_host: server_host.to_owned(),
+                nick: nick.clone(),
+                message: n.to_owned(),
+            }));
+        }
+
+        if reloaded {
+            replies.extend(Reply::support(server_host, &nick, tokens.clone()));
+        }
+
+        if !replies.is_empty() {
+            map.insert(conn_context.connection_id, replies);
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{error::Error::InvalidSettings, settings::test_settings};
+
+    fn registered(operator: bool) -> ConnectionContext {
+        ConnectionContext {
+            connection_id: Uuid::new_v4(),
+            nick: Some("JIM".to_string()),
+            registration: RegistrationState::Registered,
+            operator,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rehash_applies_settings_and_reports_what_needs_a_restart() {
+        let (oper, user) = (registered(true), registered(false));
+        let (oper_id, user_id) = (oper.connection_id, user.connection_id);
+
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper);
+        connections.insert(user_id, user);
+
+        let mut server_context = ServerContext::new(&test_settings());
+        let (context_sender, context_receiver) = watch::channel(server_context.clone());
+
+        let mut new_settings = test_settings();
+        new_settings.port = 7000;
+        new_settings.ping_frequency_secs = 30;
+        new_settings.motd_lines = vec!["New MOTD".to_string()];
+
+        let replies = rehash(
+            &mut server_context,
+            &context_sender,
+            &connections,
+            Ok(new_settings),
+        );
+
+        assert_eq!(vec!["New MOTD".to_string()], server_context.motd_lines);
+        assert_eq!(6667, server_context.port);
+        assert_eq!(30, context_receiver.borrow().ping_frequency.as_secs());
+
+        let notices = replies[&oper_id]
+            .iter()
+            .filter_map(|r| match r {
+                Reply::ServerNotice { message, .. } => Some(message.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<&str>>();
+
+        assert_eq!(
+            vec![
+                "Settings reloaded",
+                "Not reloaded, port (still 6667) needs a restart to change"
+            ],
+            notices
+        );
+        assert!(replies[&user_id]
+            .iter()
+            .all(|r| matches!(r, Reply::Support { .. })));
+    }
+
+    #[test]
+    fn rehash_keeps_current_settings_when_invalid() {
+        let oper = registered(true);
+        let oper_id = oper.connection_id;
+
+        let mut connections = HashMap::new();
+        connections.insert(oper_id, oper);
+
+        let mut server_context = ServerContext::new(&test_settings());
+        let (context_sender, _context_receiver) = watch::channel(server_context.clone());
+
+        let replies = rehash(
+            &mut server_context,
+            &context_sender,
+            &connections,
+            Err(InvalidSettings("nick_len must be more than 0".to_string())),
+        );
+
+        assert_eq!(30, server_context.nick_len);
+        assert_eq!(
+            vec![Reply::ServerNotice {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                message: "Rehash failed, keeping the current settings. Invalid settings, nick_len must be more than 0".to_string(),
+            }],
+            replies[&oper_id]
+        );
+    }
+}
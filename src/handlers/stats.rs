@@ -0,0 +1,163 @@
+use std::{collections::HashMap, iter::FromIterator, sync::Mutex};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    bans::{Ban, BanKind, BanList},
+    context::ConnectionContext,
+    replies::Reply,
+};
+
+// temporary bans say how long they have left, alongside the reason
+fn ban_reason(ban: &Ban) -> String {
+    match ban.expires_at {
+        Some(expires_at) => format!(
+            "Temporary {} min. - {}",
+            (expires_at - Utc::now()).num_minutes() + 1,
+            ban.reason
+        ),
+        None => ban.reason.clone(),
+    }
+}
+
+pub fn handle_stats(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    bans: &Mutex<BanList>,
+    query: &Option<String>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let error = |reply: Reply| {
+        Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![reply],
+        )]))
+    };
+
+    let query = match query.as_ref().and_then(|q| q.chars().next()) {
+        Some(q) => q,
+        None => {
+            return error(Reply::ErrNeedMoreParams {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                command: "STATS".to_string(),
+            })
+        }
+    };
+
+    // the ban lists are only for operators' eyes
+    let kind = match query {
+        'k' | 'K' => Some(BanKind::KLine),
+        'd' | 'D' => Some(BanKind::DLine),
+        _ => None,
+    };
+
+    if kind.is_some() && !conn_context.operator {
+        return error(Reply::ErrNoPrivileges {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+        });
+    }
+
+    let mut replies = vec![];
+
+    if let Some(kind) = kind {
+        let bans = bans.lock().unwrap();
+
+        for ban in bans.list(kind) {
+            replies.push(match kind {
+                BanKind::KLine => {
+                    let (user, host) = ban.mask.split_once('@').unwrap_or(("*", &ban.mask));
+
+                    Reply::StatsKLine {
+                        server_host: server_host.to_owned(),
+                        nick: nick.to_owned(),
+                        host: host.to_string(),
+                        user: user.to_string(),
+                        reason: ban_reason(ban),
+                    }
+                }
+                BanKind::DLine => Reply::StatsDLine {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    ip: ban.mask.clone(),
+                    reason: ban_reason(ban),
+                },
+            });
+        }
+    }
+
+    replies.push(Reply::EndOfStats {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        query: query.to_string(),
+    });
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, replies);
+
+    Some(map)
+}
+
+#[test]
+fn handle_stats_lists_bans_to_operators() {
+    let mut conn_context = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        ..Default::default()
+    };
+
+    let bans = Mutex::new(BanList::default());
+    bans.lock()
+        .unwrap()
+        .add(BanKind::KLine, "~bob@example.com", "spamming", "JIM", None);
+    bans.lock()
+        .unwrap()
+        .add(BanKind::DLine, "10.0.0.0/8", "flooding", "JIM", Some(30));
+
+    let stats = |conn_context: &ConnectionContext, query: &str| {
+        handle_stats(
+            "localhost",
+            "JIM",
+            conn_context,
+            &bans,
+            &Some(query.to_string()),
+        )
+        .expect("Expected replies")
+        .remove(&conn_context.connection_id)
+        .expect("Expected replies")
+    };
+
+    assert!(matches!(
+        stats(&conn_context, "k").as_slice(),
+        [Reply::ErrNoPrivileges { .. }]
+    ));
+
+    conn_context.operator = true;
+
+    assert_eq!(
+        vec![
+            Reply::StatsKLine {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                host: "example.com".to_string(),
+                user: "~bob".to_string(),
+                reason: "spamming".to_string(),
+            },
+            Reply::EndOfStats {
+                server_host: "localhost".to_string(),
+                nick: "JIM".to_string(),
+                query: "k".to_string(),
+            }
+        ],
+        stats(&conn_context, "k")
+    );
+
+    match stats(&conn_context, "d").as_slice() {
+        [Reply::StatsDLine { ip, reason, .. }, Reply::EndOfStats { .. }] => {
+            assert_eq!("10.0.0.0/8", ip);
+            assert_eq!("Temporary 30 min. - flooding", reason);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
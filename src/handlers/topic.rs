@@ -0,0 +1,239 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ChannelTopic, ConnectionContext},
+    replies::Reply,
+    util,
+};
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_topic(
+    server_host: &str,
+    nick: &str,
+    client: &str,
+    conn_context: &ConnectionContext,
+    channels: &mut HashMap<String, ChannelContext>,
+    channel: &Option<String>,
+    topic: &Option<String>,
+    topic_len: usize,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let channel = match channel {
+        Some(c) => c,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNeedMoreParams {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    command: "TOPIC".to_string(),
+                }],
+            )]));
+        }
+    };
+
+    let chan_ctx = match channels.get_mut(&util::casefold(channel)) {
+        Some(c) => c,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNoSuchChannel {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                }],
+            )]));
+        }
+    };
+
+    let topic = match topic {
+        Some(t) => t,
+        None => {
+            let replies = match &chan_ctx.topic {
+                Some(t) => vec![
+                    Reply::Topic {
+                        server_host: server_host.to_owned(),
+                        nick: nick.to_owned(),
+                        channel: channel.to_owned(),
+                        topic: t.text.clone(),
+                    },
+                    Reply::TopicWhoTime {
+                        server_host: server_host.to_owned(),
+                        channel: channel.to_owned(),
+                        nick: nick.to_owned(),
+                        set_by: t.set_by.clone(),
+                        set_at: t.set_at,
+                    },
+                ],
+                None => vec![Reply::NoTopic {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                }],
+            };
+
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                replies,
+            )]));
+        }
+    };
+
+    let status = match chan_ctx.status_of(&conn_context.connection_id) {
+        Some(s) => s,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNotOnChannel {
+                    server_host: server_host.to_owned(),
+                    nick: nick.to_owned(),
+                    channel: channel.to_owned(),
+                }],
+            )]));
+        }
+    };
+
+    if chan_ctx.modes.topic_lock && !status.operator {
+        return Some(HashMap::<_, _>::from_iter([(
+            conn_context.connection_id,
+            vec![Reply::ErrChanOPrivsNeeded {
+                server_host: server_host.to_owned(),
+                nick: nick.to_owned(),
+                channel: channel.to_owned(),
+            }],
+        )]));
+    }
+
+    let topic = &util::truncate(topic, topic_len);
+
+    // an empty topic clears whatever was set before
+    chan_ctx.topic = match topic.is_empty() {
+        true => None,
+        false => Some(ChannelTopic {
+            text: topic.to_owned(),
+            set_by: client.to_owned(),
+            set_at: Utc::now(),
+        }),
+    };
+
+    let mut map = HashMap::new();
+
+    for member in chan_ctx.members.keys() {
+        map.insert(
+            *member,
+            vec![Reply::TopicChange {
+                client: client.to_owned(),
+                channel: channel.to_owned(),
+                topic: topic.to_owned(),
+            }],
+        );
+    }
+
+    Some(map)
+}
+
+#[test]
+fn handle_topic_set_then_query_returns_topic() {
+    let connection_id = Uuid::new_v4();
+    let server_host = "FOOBAR";
+    let client = "JIM!~jim@localhost";
+
+    let conn_ctx = ConnectionContext {
+        connection_id,
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx
+        .members
+        .insert(connection_id, crate::context::MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let channel = Some("#foo".to_string());
+
+    let replies = handle_topic(
+        server_host,
+        "JIM",
+        client,
+        &conn_ctx,
+        &mut channels,
+        &channel,
+        &Some("hello world".to_string()),
+        390,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::TopicChange {
+            client: client.to_owned(),
+            channel: "#foo".to_owned(),
+            topic: "hello world".to_owned(),
+        }]),
+        replies.get(&connection_id)
+    );
+
+    let replies = handle_topic(
+        server_host,
+        "JIM",
+        client,
+        &conn_ctx,
+        &mut channels,
+        &channel,
+        &None,
+        390,
+    )
+    .expect("Expected replies");
+
+    match replies.get(&connection_id).map(|r| r.as_slice()) {
+        Some([Reply::Topic { topic, .. }, Reply::TopicWhoTime { set_by, .. }]) => {
+            assert_eq!("hello world", topic);
+            assert_eq!(client, set_by);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
+
+#[test]
+fn handle_topic_topic_lock_needs_operator() {
+    let connection_id = Uuid::new_v4();
+
+    let conn_ctx = ConnectionContext {
+        connection_id,
+        ..Default::default()
+    };
+
+    let mut chan_ctx = ChannelContext::new("#foo");
+    chan_ctx.modes.topic_lock = true;
+    chan_ctx
+        .members
+        .insert(connection_id, crate::context::MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#foo".to_string(), chan_ctx);
+
+    let replies = handle_topic(
+        "FOOBAR",
+        "JIM",
+        "JIM!~jim@localhost",
+        &conn_ctx,
+        &mut channels,
+        &Some("#foo".to_string()),
+        &Some("hello world".to_string()),
+        390,
+    )
+    .expect("Expected replies");
+
+    assert_eq!(
+        Some(&vec![Reply::ErrChanOPrivsNeeded {
+            server_host: "FOOBAR".to_string(),
+            nick: "JIM".to_string(),
+            channel: "#foo".to_string(),
+        }]),
+        replies.get(&connection_id)
+    );
+    assert!(channels["#foo"].topic.is_none());
+}
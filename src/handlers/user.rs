@@ -2,9 +2,14 @@ use std::collections::HashMap;
 
 use uuid::Uuid;
 
-use crate::{context::ConnectionContext, replies::Reply};
+use crate::{
+    context::{ConnectionContext, RegistrationState, ServerContext},
+    handlers::welcome::advance_registration,
+    replies::Reply,
+};
 
 pub fn handle_user(
+    server_context: &ServerContext,
     server_host: &str,
     user: &Option<String>,
     realname: &Option<String>,
@@ -13,6 +18,19 @@ pub fn handle_user(
     let empty_str = &String::from("");
     let nick = conn_context.nick.as_ref().unwrap_or(empty_str);
 
+    if conn_context.registration == RegistrationState::Registered {
+        let mut map = HashMap::new();
+        map.insert(
+            conn_context.connection_id,
+            vec![Reply::ErrAlreadyRegistered {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+            }],
+        );
+
+        return Some(map);
+    }
+
     let user = match user {
         None => {
             let mut map = HashMap::new();
@@ -46,12 +64,13 @@ pub fn handle_user(
 
             return Some(map);
         }
-        Some(realname) => realname.trim_start_matches(':'),
+        Some(realname) => realname,
     };
 
     conn_context.user = Some(user.to_string());
     // TODO add mode?
     conn_context.real_name = Some(realname.to_string());
+    let next = conn_context.registration.with_user();
 
-    None
+    advance_registration(server_context, conn_context, next)
 }
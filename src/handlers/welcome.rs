@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    context::{ConnectionContext, RegistrationState, ServerContext},
+    isupport::isupport_tokens,
+    modes::{CHANNEL_MODES, CHANNEL_MODES_WITH_ARGUMENT, USER_MODES},
+    replies::Reply,
+};
+
+// Moves the connection on to the next registration state, sending the welcome
+// burst if that completes registration. While the client is still negotiating
+// capabilities registration is held open and it is completed by CAP END instead.
+// The LUSERS and MOTD that follow the burst are added by the message handler,
+// as only it can see the rest of the server
+pub fn advance_registration(
+    server_context: &ServerContext,
+    conn_context: &mut ConnectionContext,
+    next: RegistrationState,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    if next != RegistrationState::Registered {
+        conn_context.registration = next;
+        return None;
+    }
+
+    if conn_context.cap_negotiating {
+        return None;
+    }
+
+    let (nick, user) = match (&conn_context.nick, &conn_context.user) {
+        (Some(nick), Some(user)) => (nick.clone(), user.clone()),
+        _ => return None,
+    };
+
+    conn_context.registration = RegistrationState::Registered;
+    conn_context.signon_time = Utc::now();
+    conn_context.last_active = conn_context.signon_time;
+    conn_context.client = Some(format!("{}!~{}@{}", nick, user, conn_context.host()));
+
+    let mut map = HashMap::new();
+    map.insert(
+        conn_context.connection_id,
+        welcome_burst(server_context, &nick),
+    );
+
+    Some(map)
+}
+
+pub fn welcome_burst(server_context: &ServerContext, nick: &str) -> Vec<Reply> {
+    let server_host = &server_context.server_host;
+    let mut replies = vec![
+        Reply::Welcome {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+        },
+        Reply::YourHost {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            version: server_context.version.clone(),
+        },
+        Reply::Created {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            created_at: server_context.start_time,
+        },
+        Reply::MyInfo {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            version: server_context.version.clone(),
+            user_modes: USER_MODES.to_string(),
+            channel_modes: CHANNEL_MODES.to_string(),
+            channel_modes_with_argument: CHANNEL_MODES_WITH_ARGUMENT.to_string(),
+        },
+    ];
+
+    replies.extend(Reply::support(
+        server_host,
+        nick,
+        isupport_tokens(server_context),
+    ));
+
+    replies
+}
+
+pub fn motd_replies(server_context: &ServerContext, nick: &str) -> Vec<Reply> {
+    let server_host = &server_context.server_host;
+    let mut replies = vec![Reply::MotdStart {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+    }];
+
+    for line in &server_context.motd_lines {
+        replies.push(Reply::Motd {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            line: line.to_string(),
+        });
+    }
+
+    replies.push(Reply::EndOfMotd {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+    });
+
+    replies
+}
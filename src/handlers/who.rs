@@ -1,27 +1,51 @@
 use crate::{
-    context::{ChannelContext, ConnectionContext},
+    context::{ChannelContext, ConnectionContext, MemberStatus},
     replies::Reply,
     util,
 };
-use std::{
-    collections::{HashMap, HashSet},
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-};
+use chrono::Utc;
+use std::collections::HashMap;
 
 use uuid::Uuid;
 
+// The fields a WHOX query can ask for, in the order they are always sent back
+// https://ircv3.net/specs/extensions/whox
+const WHOX_FIELDS: &str = "tcuihsnfdlaor";
+
+#[derive(Debug, PartialEq)]
+struct WhoxQuery {
+    fields: Vec<char>,
+    token: Option<String>,
+}
+
+impl WhoxQuery {
+    fn parse(s: &str) -> Self {
+        let (fields, token) = match s.split_once(',') {
+            Some((f, t)) => (f, Some(t.to_string())),
+            None => (s, None),
+        };
+
+        WhoxQuery {
+            fields: WHOX_FIELDS
+                .chars()
+                .filter(|c| fields.contains(*c))
+                .collect(),
+            token,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_who(
     mask: &Option<String>,
+    only_operators: bool,
+    whox: &Option<String>,
     server_host: &str,
     nick: &str,
     channels: &HashMap<String, ChannelContext>,
     connections: &HashMap<Uuid, ConnectionContext>,
     conn_context: &ConnectionContext,
 ) -> Option<HashMap<Uuid, Vec<Reply>>> {
-    /*
-    The <mask> passed to WHO is matched against users' host, server, real
-    name and nickname if the channel <mask> cannot be found.
-    */
     let mask = match mask {
         Some(m) => m,
         None => {
@@ -38,45 +62,43 @@ pub fn handle_who(
         }
     };
 
-    let mut is_mask_channel = false;
+    // users matched by mask rather than channel have no channel status to show
+    let mut members = HashMap::new();
 
-    // if there is a mask, first check that it matches a channel
     // The <mask> passed to WHO is matched against users' host, server, real
     // name and nickname if the channel <mask> cannot be found.
-
-    let chan_ctx = channels.get(mask);
-
-    let mut members = HashSet::new();
-
-    let users = match chan_ctx {
+    let (channel, users) = match channels.get(&util::casefold(mask)) {
         Some(c) => {
-            is_mask_channel = true;
-            &c.members
+            // the members of a secret or private channel are only visible
+            // to each other
+            let hidden = (c.modes.secret || c.modes.private)
+                && !c.members.contains_key(&conn_context.connection_id);
+
+            match hidden {
+                true => (mask.as_str(), &members),
+                false => (mask.as_str(), &c.members),
+            }
         }
         None => {
-            let empty_str = "".to_string();
-            let empty_ip = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 1234));
             for (k, v) in connections.iter() {
-                let hostmask = format!(
-                    "{}!{}@{}",
-                    v.nick.as_ref().unwrap_or(&empty_str).clone(),
-                    v.user.as_ref().unwrap_or(&empty_str).clone(),
-                    v.client_host.as_ref().unwrap_or(&empty_ip).clone() // TODO
-                );
-
-                if util::match_mask(&hostmask, mask) {
-                    members.insert(*k);
+                if matches_user(v, server_host, mask) {
+                    members.insert(*k, MemberStatus::default());
                 }
             }
 
-            &members
+            ("*", &members)
         }
     };
 
+    let whox = whox.as_deref().map(WhoxQuery::parse);
+
     let mut map = HashMap::new();
     let mut replies: Vec<Reply> = vec![];
 
-    for user in users {
+    let mut users = users.iter().collect::<Vec<(&Uuid, &MemberStatus)>>();
+    users.sort_unstable_by_key(|(u, _)| connections.get(u).map(|c| c.nick.clone()));
+
+    for (user, status) in users {
         let other_user = match connections.get(user) {
             Some(c) => c,
             None => {
@@ -85,29 +107,33 @@ pub fn handle_who(
             }
         };
 
-        let empty_str = "".to_string();
+        if only_operators && !other_user.operator {
+            continue;
+        }
 
-        // TODO
-        let empty_ip = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 1234));
+        let flags = format!(
+            "{}{}{}",
+            if other_user.away.is_some() { "G" } else { "H" },
+            if other_user.operator { "*" } else { "" },
+            status.prefix()
+        );
 
-        // TODOTODOTODO
-        replies.push(Reply::Who {
-            server_host: server_host.to_string(),
-            nick: nick.to_string(),
-            channel: match is_mask_channel {
-                true => mask.to_string(),
-                false => "*".to_string(),
+        let reply = match &whox {
+            Some(query) => whox_reply(server_host, nick, channel, other_user, &flags, query),
+            None => Reply::Who {
+                server_host: server_host.to_string(),
+                nick: nick.to_string(),
+                channel: channel.to_string(),
+                other_user: other_user.user.clone().unwrap_or_default(),
+                other_host: other_user.host(),
+                other_server: server_host.to_string(), // multi-server not supported
+                other_nick: other_user.nick.clone().unwrap_or_default(),
+                flags,
+                other_realname: other_user.real_name.clone().unwrap_or_default(),
             },
-            other_user: other_user.user.as_ref().unwrap_or(&empty_str).clone(),
-            other_host: other_user
-                .client_host
-                .as_ref()
-                .unwrap_or(&empty_ip)
-                .to_string(),
-            other_server: server_host.to_string(), // multi-server not supported
-            other_nick: other_user.nick.as_ref().unwrap_or(&empty_str).clone(),
-            other_realname: other_user.real_name.as_ref().unwrap_or(&empty_str).clone(),
-        })
+        };
+
+        replies.push(reply);
     }
 
     replies.push(Reply::EndOfWho {
@@ -120,3 +146,190 @@ pub fn handle_who(
 
     Some(map)
 }
+
+fn matches_user(conn_context: &ConnectionContext, server_host: &str, mask: &str) -> bool {
+    let nick = conn_context.nick.clone().unwrap_or_default();
+    let user = conn_context.user.clone().unwrap_or_default();
+    let host = conn_context.host();
+    let real_name = conn_context.real_name.clone().unwrap_or_default();
+
+    let hostmask = format!("{}!{}@{}", nick, user, host);
+
+    [&nick, &host, &real_name, server_host, &hostmask]
+        .iter()
+        .any(|s| util::match_mask(s, mask))
+}
+
+fn whox_reply(
+    server_host: &str,
+    nick: &str,
+    channel: &str,
+    other_user: &ConnectionContext,
+    flags: &str,
+    query: &WhoxQuery,
+) -> Reply {
+    let mut fields = vec![];
+    let mut real_name = None;
+
+    for field in &query.fields {
+        let value = match field {
+            't' => query.token.clone().unwrap_or_default(),
+            'c' => channel.to_string(),
+            'u' => other_user.user.clone().unwrap_or_default(),
+            'i' => match other_user.client_host {
+                Some(addr) => addr.ip().to_string(),
+                None => "255.255.255.255".to_string(),
+            },
+            'h' => other_user.host(),
+            's' => server_host.to_string(),
+            'n' => other_user.nick.clone().unwrap_or_default(),
+            'f' => flags.to_string(),
+            // every user is on this server
+            'd' => "0".to_string(),
+            'l' => (Utc::now() - other_user.last_active)
+                .num_seconds()
+                .to_string(),
+            // TODO accounts, 0 means not logged in
+            'a' => "0".to_string(),
+            'o' => "n/a".to_string(),
+            'r' => {
+                real_name = Some(other_user.real_name.clone().unwrap_or_default());
+                continue;
+            }
+            _ => continue,
+        };
+
+        // an empty field would shift every parameter after it along, so
+        // the token falls back to 0 and anything else to *
+        let value = match value.is_empty() {
+            true if *field == 't' => "0".to_string(),
+            true => "*".to_string(),
+            false => value,
+        };
+
+        fields.push(value);
+    }
+
+    Reply::WhoSpc {
+        server_host: server_host.to_string(),
+        nick: nick.to_string(),
+        fields,
+        real_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("cnf", &['c', 'n', 'f'], None ; "fields_only")]
+    #[test_case("fnc,42", &['c', 'n', 'f'], Some("42") ; "fields_reordered_with_token")]
+    #[test_case("rtx,1", &['t', 'r'], Some("1") ; "unknown_fields_ignored")]
+    fn whox_query_parses_correctly(raw: &str, fields: &[char], token: Option<&str>) {
+        assert_eq!(
+            WhoxQuery {
+                fields: fields.to_vec(),
+                token: token.map(|t| t.to_string()),
+            },
+            WhoxQuery::parse(raw)
+        );
+    }
+
+    #[test]
+    fn handle_who_whox_and_operator_filter() {
+        let asker_id = Uuid::new_v4();
+        let oper_id = Uuid::new_v4();
+
+        let mut connections = HashMap::new();
+        connections.insert(
+            asker_id,
+            ConnectionContext {
+                connection_id: asker_id,
+                nick: Some("JIM".to_string()),
+                ..Default::default()
+            },
+        );
+        connections.insert(
+            oper_id,
+            ConnectionContext {
+                connection_id: oper_id,
+                nick: Some("BOB".to_string()),
+                user: Some("bob".to_string()),
+                real_name: Some("Bob Smith".to_string()),
+                operator: true,
+                ..Default::default()
+            },
+        );
+
+        let mut chan_ctx = ChannelContext::new("#foo");
+        chan_ctx.members.insert(
+            oper_id,
+            MemberStatus {
+                operator: true,
+                voice: false,
+            },
+        );
+        chan_ctx.members.insert(asker_id, MemberStatus::default());
+
+        let mut channels = HashMap::new();
+        channels.insert("#foo".to_string(), chan_ctx);
+
+        let who = |mask: &str, only_operators: bool, whox: Option<&str>| {
+            handle_who(
+                &Some(mask.to_string()),
+                only_operators,
+                &whox.map(|w| w.to_string()),
+                "localhost",
+                "JIM",
+                &channels,
+                &connections,
+                &connections[&asker_id],
+            )
+            .expect("Expected replies")
+            .remove(&asker_id)
+            .expect("Expected replies")
+        };
+
+        assert_eq!(3, who("#foo", false, None).len());
+
+        assert_eq!(
+            vec![
+                Reply::WhoSpc {
+                    server_host: "localhost".to_string(),
+                    nick: "JIM".to_string(),
+                    fields: vec![
+                        "42".to_string(),
+                        "#foo".to_string(),
+                        "BOB".to_string(),
+                        "H*@".to_string(),
+                    ],
+                    real_name: Some("Bob Smith".to_string()),
+                },
+                Reply::EndOfWho {
+                    server_host: "localhost".to_string(),
+                    nick: "JIM".to_string(),
+                    mask: "#foo".to_string(),
+                },
+            ],
+            who("#foo", true, Some("tcnfr,42"))
+        );
+
+        // no token was sent and JIM never gave a username
+        match who("JIM", false, Some("tun")).as_slice() {
+            [Reply::WhoSpc { fields, .. }, Reply::EndOfWho { .. }] => {
+                assert_eq!(&["0", "*", "JIM"], fields.as_slice());
+            }
+            r => panic!("Unexpected replies {:?}", r),
+        }
+
+        match who("bob smith", false, None).as_slice() {
+            [Reply::Who { channel, flags, .. }, Reply::EndOfWho { .. }] => {
+                assert_eq!("*", channel);
+                assert_eq!("H*", flags);
+            }
+            r => panic!("Unexpected replies {:?}", r),
+        }
+    }
+}
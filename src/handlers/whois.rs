@@ -0,0 +1,205 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    context::{ChannelContext, ConnectionContext},
+    replies::Reply,
+    util,
+};
+
+pub fn handle_whois(
+    server_host: &str,
+    version: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    channels: &HashMap<String, ChannelContext>,
+    connections: &HashMap<Uuid, ConnectionContext>,
+    target: &Option<String>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let target = match target {
+        Some(t) => t,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNoNickGiven {
+                    server_host: server_host.to_owned(),
+                }],
+            )]))
+        }
+    };
+
+    let end_of_whois = Reply::EndOfWhois {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        target: target.to_owned(),
+    };
+
+    let other_user = match util::find_connection_by_nick(connections, target) {
+        Some(c) => c,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![
+                    Reply::ErrNoSuchNick {
+                        server_host: server_host.to_owned(),
+                        nick: nick.to_owned(),
+                        target: target.to_owned(),
+                    },
+                    end_of_whois,
+                ],
+            )]))
+        }
+    };
+
+    let target = other_user.nick.clone().unwrap_or_default();
+
+    let mut replies = vec![
+        Reply::WhoisUser {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.clone(),
+            user: other_user.user.clone().unwrap_or_default(),
+            host: other_user.host(),
+            real_name: other_user.real_name.clone().unwrap_or_default(),
+        },
+        Reply::WhoisServer {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.clone(),
+            server: server_host.to_owned(), // multi-server not supported
+            server_info: version.to_owned(),
+        },
+    ];
+
+    if other_user.operator {
+        replies.push(Reply::WhoisOperator {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.clone(),
+        });
+    }
+
+    if let Some(away) = &other_user.away {
+        replies.push(Reply::Away {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.clone(),
+            message: away.clone(),
+        });
+    }
+
+    replies.push(Reply::WhoisIdle {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        target: target.clone(),
+        idle_secs: (Utc::now() - other_user.last_active).num_seconds(),
+        signon_time: other_user.signon_time,
+    });
+
+    // secret and private channels are only shown to those in them too
+    let mut shared_channels = channels
+        .iter()
+        .filter(|(_, c)| {
+            let hidden = c.modes.secret || c.modes.private;
+            !hidden || c.members.contains_key(&conn_context.connection_id)
+        })
+        .filter_map(|(name, c)| {
+            c.status_of(&other_user.connection_id)
+                .map(|s| (name, format!("{}{}", s.prefix(), c.name)))
+        })
+        .collect::<Vec<(&String, String)>>();
+    shared_channels.sort_unstable();
+
+    if !shared_channels.is_empty() {
+        replies.push(Reply::WhoisChannels {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.clone(),
+            channels: shared_channels.into_iter().map(|(_, c)| c).collect(),
+        });
+    }
+
+    replies.push(Reply::EndOfWhois {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        target,
+    });
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, replies);
+
+    Some(map)
+}
+
+#[test]
+fn handle_whois_hides_secret_channels() {
+    use crate::context::MemberStatus;
+
+    let asker_id = Uuid::new_v4();
+    let target_id = Uuid::new_v4();
+
+    let mut connections = HashMap::new();
+    connections.insert(
+        asker_id,
+        ConnectionContext {
+            connection_id: asker_id,
+            nick: Some("JIM".to_string()),
+            ..Default::default()
+        },
+    );
+    connections.insert(
+        target_id,
+        ConnectionContext {
+            connection_id: target_id,
+            nick: Some("BOB".to_string()),
+            user: Some("bob".to_string()),
+            real_name: Some("Bob Smith".to_string()),
+            operator: true,
+            ..Default::default()
+        },
+    );
+
+    let mut open = ChannelContext::new("#open");
+    open.members.insert(
+        target_id,
+        MemberStatus {
+            operator: true,
+            voice: false,
+        },
+    );
+
+    let mut secret = ChannelContext::new("#secret");
+    secret.modes.secret = true;
+    secret.members.insert(target_id, MemberStatus::default());
+
+    let mut channels = HashMap::new();
+    channels.insert("#open".to_string(), open);
+    channels.insert("#secret".to_string(), secret);
+
+    let replies = handle_whois(
+        "localhost",
+        "0.0.1",
+        "JIM",
+        &connections[&asker_id],
+        &channels,
+        &connections,
+        &Some("bob".to_string()),
+    )
+    .expect("Expected replies")
+    .remove(&asker_id)
+    .expect("Expected replies");
+
+    match replies.as_slice() {
+        [Reply::WhoisUser {
+            target, real_name, ..
+        }, Reply::WhoisServer { .. }, Reply::WhoisOperator { .. }, Reply::WhoisIdle { .. }, Reply::WhoisChannels { channels, .. }, Reply::EndOfWhois { .. }] =>
+        {
+            assert_eq!("BOB", target);
+            assert_eq!("Bob Smith", real_name);
+            assert_eq!(&vec!["@#open".to_string()], channels);
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, iter::FromIterator};
+
+use uuid::Uuid;
+
+use crate::{
+    context::{ConnectionContext, WhowasHistory},
+    replies::Reply,
+};
+
+pub fn handle_whowas(
+    server_host: &str,
+    nick: &str,
+    conn_context: &ConnectionContext,
+    whowas: &WhowasHistory,
+    target: &Option<String>,
+    count: &Option<usize>,
+) -> Option<HashMap<Uuid, Vec<Reply>>> {
+    let target = match target {
+        Some(t) => t,
+        None => {
+            return Some(HashMap::<_, _>::from_iter([(
+                conn_context.connection_id,
+                vec![Reply::ErrNoNickGiven {
+                    server_host: server_host.to_owned(),
+                }],
+            )]))
+        }
+    };
+
+    // a count that isn't positive means everything
+    let count = match count {
+        Some(c) if *c > 0 => *c,
+        _ => usize::MAX,
+    };
+
+    let mut replies = whowas
+        .find(target)
+        .take(count)
+        .map(|e| Reply::WhowasUser {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: e.nick.clone(),
+            user: e.user.clone(),
+            host: e.host.clone(),
+            real_name: e.real_name.clone(),
+        })
+        .collect::<Vec<Reply>>();
+
+    if replies.is_empty() {
+        replies.push(Reply::ErrWasNoSuchNick {
+            server_host: server_host.to_owned(),
+            nick: nick.to_owned(),
+            target: target.to_owned(),
+        });
+    }
+
+    replies.push(Reply::EndOfWhowas {
+        server_host: server_host.to_owned(),
+        nick: nick.to_owned(),
+        target: target.to_owned(),
+    });
+
+    let mut map = HashMap::new();
+    map.insert(conn_context.connection_id, replies);
+
+    Some(map)
+}
+
+#[test]
+fn handle_whowas_limits_to_count() {
+    let conn_context = ConnectionContext {
+        connection_id: Uuid::new_v4(),
+        ..Default::default()
+    };
+
+    let mut whowas = WhowasHistory::default();
+
+    for user in &["first", "second"] {
+        whowas.record(&ConnectionContext {
+            nick: Some("BOB".to_string()),
+            user: Some(user.to_string()),
+            ..Default::default()
+        });
+    }
+
+    let whowas = |target: &str, count: Option<usize>| {
+        handle_whowas(
+            "localhost",
+            "JIM",
+            &conn_context,
+            &whowas,
+            &Some(target.to_string()),
+            &count,
+        )
+        .expect("Expected replies")
+        .remove(&conn_context.connection_id)
+        .expect("Expected replies")
+    };
+
+    match whowas("bob", Some(1)).as_slice() {
+        [Reply::WhowasUser { user, .. }, Reply::EndOfWhowas { .. }] => assert_eq!("second", user),
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    assert_eq!(3, whowas("bob", None).len());
+
+    match whowas("TOM", None).as_slice() {
+        [Reply::ErrWasNoSuchNick { .. }, Reply::EndOfWhowas { .. }] => {}
+        r => panic!("Unexpected replies {:?}", r),
+    }
+}
@@ -0,0 +1,60 @@
+use std::{net::IpAddr, time::Duration};
+
+// how long a new connection waits on its lookup before its address is used
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// hostnames longer than this don't fit in a hostmask (HOSTLEN)
+const MAX_HOSTNAME_LEN: usize = 63;
+
+// The name an address reverse resolves to, but only if that name resolves
+// back to the same address, as a PTR record can claim to be anything.
+// The lookups block, so they are done off the runtime.
+pub async fn lookup_hostname(ip: IpAddr) -> Option<String> {
+    let lookup = tokio::task::spawn_blocking(move || {
+        let hostname = dns_lookup::lookup_addr(&ip).ok()?;
+        let confirmed = dns_lookup::lookup_host(&hostname).ok()?.contains(&ip);
+
+        match confirmed && is_valid_hostname(&hostname) {
+            true => Some(hostname),
+            false => None,
+        }
+    });
+
+    match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+        Ok(Ok(hostname)) => hostname,
+        _ => None,
+    }
+}
+
+// the name ends up in hostmasks and replies, so anything that could break
+// one of those is left for the address instead
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= MAX_HOSTNAME_LEN
+        && !hostname.starts_with(['-', '.'])
+        && hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("irc.example.com", true ; "plain")]
+    #[test_case("host-1.example.com", true ; "with_dash")]
+    #[test_case("", false ; "empty")]
+    #[test_case("-bad.example.com", false ; "leading_dash")]
+    #[test_case("bad host.example.com", false ; "space")]
+    #[test_case("bad:host", false ; "colon")]
+    fn is_valid_hostname_correctly(hostname: &str, expected: bool) {
+        assert_eq!(expected, is_valid_hostname(hostname));
+    }
+
+    #[test]
+    fn is_valid_hostname_rejects_long_names() {
+        assert!(!is_valid_hostname(&"a".repeat(MAX_HOSTNAME_LEN + 1)));
+    }
+}
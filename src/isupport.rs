@@ -0,0 +1,62 @@
+use crate::{
+    codec::FallbackEncoding,
+    context::ServerContext,
+    handlers::list::ELIST,
+    modes::{CHANMODES, MAX_MODES, PREFIX},
+};
+
+// The tokens advertised in RPL_ISUPPORT, so clients know what this server
+// implements and the limits it has been configured with
+// https://modern.ircdocs.horse/#rplisupport-005
+pub fn isupport_tokens(server_context: &ServerContext) -> Vec<String> {
+    let max_targets = server_context.max_targets;
+
+    let mut tokens = vec![
+        format!("AWAYLEN={}", server_context.away_len),
+        // nicks are only ever compared ignoring ASCII case
+        "CASEMAPPING=ascii".to_string(),
+        format!("CHANMODES={}", CHANMODES),
+        format!("CHANNELLEN={}", server_context.channel_len),
+        "CHANTYPES=#".to_string(),
+        format!("ELIST={}", ELIST),
+        format!("KICKLEN={}", server_context.kick_len),
+        format!("MAXLIST=beI:{}", server_context.max_list_entries),
+        format!("MAXTARGETS={}", max_targets),
+        format!("MODES={}", MAX_MODES),
+        format!("NETWORK={}", server_context.network_name),
+        format!("NICKLEN={}", server_context.nick_len),
+        format!("PREFIX={}", PREFIX),
+        format!(
+            "TARGMAX=JOIN:,KICK:,LIST:,NAMES:,NOTICE:{0},PART:,PRIVMSG:{0},TAGMSG:{0},WHOIS:1",
+            max_targets
+        ),
+        format!("TOPICLEN={}", server_context.topic_len),
+    ];
+
+    if server_context.fallback_encoding == FallbackEncoding::Strict {
+        tokens.push("UTF8ONLY".to_string());
+    }
+
+    tokens.push("WHOX".to_string());
+
+    tokens
+}
+
+#[test]
+fn isupport_tokens_are_built_from_context() {
+    let mut context = ServerContext::new(&crate::settings::test_settings());
+    context.fallback_encoding = FallbackEncoding::Strict;
+
+    let tokens = isupport_tokens(&context);
+
+    for expected in &[
+        "NETWORK=LocalNet",
+        "NICKLEN=30",
+        "CHANNELLEN=50",
+        "PREFIX=(ov)@+",
+        "TARGMAX=JOIN:,KICK:,LIST:,NAMES:,NOTICE:4,PART:,PRIVMSG:4,TAGMSG:4,WHOIS:1",
+        "UTF8ONLY",
+    ] {
+        assert!(tokens.contains(&expected.to_string()), "{}", expected);
+    }
+}
@@ -1,39 +1,66 @@
+mod bans;
+mod capabilities;
 mod channels;
 mod client_listener;
 mod client_sender;
+mod codec;
 mod context;
 mod error;
 mod handlers;
+mod hostname;
+mod isupport;
 mod message_handler;
 mod message_parsing;
+mod modes;
+mod oper;
 mod replies;
 mod result;
 mod server;
 mod settings;
+mod tags;
 mod util;
 
 use settings::Settings;
 use std::io;
 use tokio::{
-    signal,
+    signal::{
+        self,
+        unix::{signal as unix_signal, SignalKind},
+    },
     sync::mpsc::{self},
 };
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let settings = Settings::new().unwrap();
+    let settings = Settings::load().unwrap();
     let (server_shutdown_sender, shutdown_receiver) = mpsc::channel::<()>(1);
+    let (reload_sender, reload_receiver) = mpsc::channel::<()>(1);
 
     let server_task = tokio::spawn(async move {
-        if let Err(e) = server::run(&settings, shutdown_receiver).await {
+        if let Err(e) = server::run(&settings, shutdown_receiver, reload_receiver).await {
             println!("Error received from server {:?}", e);
         };
     });
 
-    match signal::ctrl_c().await {
-        Ok(()) => {}
-        Err(e) => {
-            println!("Unable to listen to shutdown signal {:?}", e);
+    let mut hangup = unix_signal(SignalKind::hangup())?;
+
+    // SIGHUP reloads the settings, anything else carries on to shutting down
+    loop {
+        tokio::select! {
+            res = signal::ctrl_c() => {
+                if let Err(e) = res {
+                    println!("Unable to listen to shutdown signal {:?}", e);
+                }
+
+                break;
+            }
+            _ = hangup.recv() => {
+                println!("Received SIGHUP, reloading settings");
+
+                if reload_sender.send(()).await.is_err() {
+                    println!("Unable to propagate reload signal to the rest of the program");
+                }
+            }
         }
     }
 
@@ -1,23 +1,60 @@
-use std::collections::HashMap;
-use tokio::sync::mpsc::Receiver;
+use chrono::Utc;
+use std::{
+    collections::{HashMap, HashSet},
+    iter::FromIterator,
+};
+use tokio::sync::{
+    mpsc::{self, Receiver},
+    watch,
+};
 use uuid::Uuid;
 
 use crate::{
+    bans::BanKind,
+    capabilities::Capability,
     channels::ReceiverWrapper,
-    context::{ChannelContext, ConnectionContext, ServerContext},
+    context::{
+        ChannelContext, ConnectionContext, RegistrationState, ServerContext, ServerStats,
+        WhowasHistory,
+    },
     handlers::{
-        join::handle_join, mode::handle_mode, nick::handle_nick, part::handle_part,
-        ping::handle_ping, privmsg::handle_privmsg, quit::handle_quit, user::handle_user,
+        away::handle_away,
+        cap::handle_cap,
+        invite::handle_invite,
+        join::handle_join,
+        kick::handle_kick,
+        kill::handle_kill,
+        kline::{disconnect_banned, handle_kline, handle_unkline},
+        list::handle_list,
+        lusers::{handle_lusers, lusers_replies},
+        mode::handle_mode,
+        names::handle_names,
+        nick::handle_nick,
+        oper::{handle_oper, handle_oper_verified},
+        part::handle_part,
+        ping::handle_ping,
+        privmsg::{handle_notice, handle_privmsg, handle_tagmsg},
+        quit::{handle_quit, leave_channels},
+        rehash::{handle_rehash, rehash},
+        stats::handle_stats,
+        topic::handle_topic,
+        user::handle_user,
+        welcome::motd_replies,
+        whois::handle_whois,
+        whowas::handle_whowas,
     },
     message_parsing::{Command, Message, ReplySender},
     replies::Reply,
+    settings::Settings,
 };
 
 use crate::handlers::who::*;
 use crate::result::Result;
 
+// The server context is owned here and any reload of it is sent on to the
+// other tasks through the context_sender
 pub async fn run<T>(
-    server_context: &ServerContext,
+    context_sender: &watch::Sender<ServerContext>,
     receiver_channel: &mut T,
     mut shutdown_receiver: Receiver<()>,
 ) -> Result<()>
@@ -26,9 +63,15 @@ where
 {
     let mut connections = HashMap::new();
     let mut sender_channels = HashMap::new();
-    let server_host = server_context.server_host.clone();
+    let mut context = context_sender.borrow().clone();
+    let server_host = context.server_host.clone();
     let empty_str = &String::from("");
     let mut channels: HashMap<String, ChannelContext> = HashMap::new();
+    let mut whowas = WhowasHistory::default();
+    let mut stats = ServerStats::default();
+
+    // OPER passwords are checked on a blocking thread and come back this way
+    let (verified_sender, mut verified_receiver) = mpsc::channel(100);
 
     loop {
         let received = tokio::select! {
@@ -38,6 +81,7 @@ where
                     return Ok(());
                 }
             },
+            Some(verified) = verified_receiver.recv() => verified,
             _ = shutdown_receiver.recv() => {
                 return Ok(());
             }
@@ -47,7 +91,12 @@ where
         // and thus the only one where there is no connection context available.
         // We can handle it here instead of in the match below so that the rest of the
         // commands can just deal with a ConnectionContext instead of an Option<ConnectionContext>
-        if let Command::Connected { sender, client_ip } = &received.command {
+        if let Command::Connected {
+            sender,
+            client_ip,
+            hostname,
+        } = &received.command
+        {
             let ctx = ConnectionContext {
                 connection_id: received.connection_id,
                 nick: None,
@@ -55,12 +104,40 @@ where
                 user: None,
                 real_name: None,
                 client_host: *client_ip,
+                hostname: hostname.clone(),
+                registration: RegistrationState::Unregistered,
+                capabilities: HashSet::new(),
+                cap_negotiating: false,
+                signon_time: Utc::now(),
+                last_active: Utc::now(),
+                operator: false,
+                privileges: HashSet::new(),
+                away: None,
+                oper_pending: false,
             };
             connections.insert(received.connection_id, ctx);
             sender_channels.insert(received.connection_id, sender.clone());
+
+            stats.connections_received += 1;
+            stats.max_connections = stats.max_connections.max(connections.len() as u32);
+            continue;
+        }
+
+        // a reload from SIGHUP doesn't come from any connection either
+        if let Command::Reload = &received.command {
+            let replies = rehash(&mut context, context_sender, &connections, Settings::load());
+
+            send_replies(replies, &sender_channels, &connections).await;
             continue;
         }
 
+        // only talking to others counts as activity for the idle time
+        if let Command::PrivMsg { .. } | Command::Notice { .. } = &received.command {
+            if let Some(c) = connections.get_mut(&received.connection_id) {
+                c.last_active = Utc::now();
+            }
+        }
+
         let conn_context = match connections.get(&received.connection_id) {
             Some(c) => c,
             None => {
@@ -74,11 +151,71 @@ where
             }
         };
 
+        // only the commands needed to complete registration (or to leave) are
+        // available until the connection has sent both NICK and USER
+        if conn_context.registration != RegistrationState::Registered {
+            match &received.command {
+                Command::Nick { .. }
+                | Command::User { .. }
+                | Command::Cap { .. }
+                | Command::Ping { .. }
+                | Command::Pong
+                | Command::Quit { .. }
+                | Command::Disconnected
+                | Command::InputTooLong
+                | Command::Unhandled => {}
+                _ => {
+                    let replies = HashMap::from_iter([(
+                        received.connection_id,
+                        vec![Reply::ErrNotRegistered {
+                            server_host: server_host.clone(),
+                            nick: conn_context.nick.clone().unwrap_or_else(|| "*".to_string()),
+                        }],
+                    )]);
+
+                    send_replies(replies, &sender_channels, &connections).await;
+                    continue;
+                }
+            }
+        }
+
+        // this is the one command that changes the server context, so it's
+        // dealt with before the context is borrowed for the rest
+        if let Command::Rehash = &received.command {
+            let replies = handle_rehash(
+                &server_host,
+                conn_context.nick.as_ref().unwrap_or(empty_str),
+                conn_context,
+                &mut context,
+                context_sender,
+                &connections,
+            );
+
+            if let Some(replies) = replies {
+                send_replies(replies, &sender_channels, &connections).await;
+            }
+
+            continue;
+        }
+
+        let server_context = &context;
+
+        let was_registered = conn_context.registration == RegistrationState::Registered;
+
         let ctx_client = conn_context.client.as_ref().unwrap_or(empty_str);
         let ctx_nick = conn_context.nick.as_ref().unwrap_or(empty_str);
 
-        let replies = match &received.command {
+        let mut replies = match &received.command {
             Command::Disconnected => {
+                // a connection that went without a QUIT still has to leave
+                // its channels, or it stays in them as a ghost
+                let replies = leave_channels(
+                    conn_context,
+                    "Connection closed",
+                    &mut channels,
+                    &connections,
+                );
+
                 if connections.remove(&received.connection_id).is_none() {
                     println!(
                         "Disconnected connection {} already removed",
@@ -93,7 +230,7 @@ where
                     );
                 }
 
-                None
+                Some(replies)
             }
             Command::User {
                 user,
@@ -107,9 +244,12 @@ where
                     }
                 };
 
-                handle_user(&server_host, user, realname, conn_context)
+                handle_user(server_context, &server_host, user, realname, conn_context)
             }
-            Command::Nick { nick, .. } => {
+            Command::Cap {
+                subcommand,
+                arguments,
+            } => {
                 let conn_context = match connections.get_mut(&received.connection_id) {
                     Some(c) => c,
                     None => {
@@ -117,16 +257,27 @@ where
                     }
                 };
 
-                handle_nick(
+                handle_cap(
                     server_context,
                     &server_host,
-                    nick,
-                    &server_context.version,
-                    &server_context.start_time,
+                    subcommand,
+                    arguments,
                     conn_context,
                 )
             }
-            Command::Join { channels_to_join } => handle_join(
+            Command::Nick { nick, .. } => handle_nick(
+                server_context,
+                &server_host,
+                nick,
+                received.connection_id,
+                &mut connections,
+                &channels,
+                &mut whowas,
+            ),
+            Command::Join {
+                channels_to_join,
+                keys,
+            } => handle_join(
                 &server_host,
                 ctx_nick,
                 ctx_client,
@@ -134,6 +285,9 @@ where
                 &mut channels,
                 &connections,
                 channels_to_join,
+                keys,
+                server_context.invite_timeout,
+                server_context.channel_len,
             ),
             Command::Part { channels_to_leave } => handle_part(
                 &server_host,
@@ -143,35 +297,291 @@ where
                 &mut channels,
                 channels_to_leave,
             ),
-            Command::Mode { channel } => handle_mode(&server_host, ctx_nick, channel, conn_context),
-            Command::Who { mask, .. } => handle_who(
-                mask,
+            Command::Invite { nick, channel } => handle_invite(
                 &server_host,
                 ctx_nick,
+                ctx_client,
+                conn_context,
+                &mut channels,
+                &connections,
+                nick,
+                channel,
+                server_context.invite_timeout,
+            ),
+            Command::Names {
+                channels: channels_to_list,
+            } => handle_names(
+                &server_host,
+                ctx_nick,
+                conn_context,
                 &channels,
                 &connections,
+                channels_to_list,
+            ),
+            Command::List { filters } => {
+                handle_list(&server_host, ctx_nick, conn_context, &channels, filters)
+            }
+            Command::Kick {
+                channels: channels_to_kick_from,
+                users,
+                reason,
+            } => handle_kick(
+                &server_host,
+                ctx_nick,
+                ctx_client,
                 conn_context,
+                &mut channels,
+                &connections,
+                channels_to_kick_from,
+                users,
+                reason,
+                server_context.kick_len,
             ),
-            Command::PrivMsg { channel, message } => handle_privmsg(
+            Command::Topic { channel, topic } => handle_topic(
                 &server_host,
                 ctx_nick,
+                ctx_client,
+                conn_context,
+                &mut channels,
                 channel,
-                message,
+                topic,
+                server_context.topic_len,
+            ),
+            Command::Mode {
+                channel,
+                mode_string,
+                mode_arguments,
+            } => handle_mode(
+                &server_host,
+                ctx_nick,
+                ctx_client,
+                conn_context,
+                &mut channels,
+                &connections,
+                channel,
+                mode_string,
+                mode_arguments,
+                server_context.max_list_entries,
+            ),
+            Command::Who {
+                mask,
+                only_operators,
+                whox,
+            } => handle_who(
+                mask,
+                *only_operators,
+                whox,
+                &server_host,
+                ctx_nick,
+                &channels,
+                &connections,
                 conn_context,
+            ),
+            Command::Whois { nick } => handle_whois(
+                &server_host,
+                &server_context.version,
+                ctx_nick,
+                conn_context,
+                &channels,
+                &connections,
+                nick,
+            ),
+            Command::Away { message } => handle_away(
+                &server_host,
+                received.connection_id,
+                &mut connections,
                 &channels,
+                message,
+                server_context.away_len,
+            ),
+            Command::Oper { name, password } => handle_oper(
+                &server_host,
+                received.connection_id,
+                &mut connections,
+                &server_context.opers,
+                name,
+                password,
+                &verified_sender,
+            ),
+            Command::OperVerified { name, verified } => handle_oper_verified(
+                &server_host,
+                received.connection_id,
+                &mut connections,
+                &server_context.opers,
+                name,
+                *verified,
+            ),
+            Command::Kill { nick, reason } => handle_kill(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &mut channels,
                 &connections,
+                &mut whowas,
+                nick,
+                reason,
             ),
-            Command::Quit { message } => {
-                handle_quit(message, &mut channels, &connections, received.connection_id)
+            Command::Kline {
+                duration,
+                mask,
+                reason,
+            } => handle_kline(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &mut channels,
+                &connections,
+                &mut whowas,
+                &server_context.bans,
+                BanKind::KLine,
+                duration,
+                mask,
+                reason,
+            ),
+            Command::Dline {
+                duration,
+                mask,
+                reason,
+            } => handle_kline(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &mut channels,
+                &connections,
+                &mut whowas,
+                &server_context.bans,
+                BanKind::DLine,
+                duration,
+                mask,
+                reason,
+            ),
+            Command::Unkline { mask } => handle_unkline(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &server_context.bans,
+                BanKind::KLine,
+                mask,
+            ),
+            Command::Undline { mask } => handle_unkline(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &server_context.bans,
+                BanKind::DLine,
+                mask,
+            ),
+            Command::Stats { query } => handle_stats(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &server_context.bans,
+                query,
+            ),
+            Command::Whowas { nick, count } => {
+                handle_whowas(&server_host, ctx_nick, conn_context, &whowas, nick, count)
             }
-            Command::Connected { .. } => None,
-            Command::Unhandled { .. } => None,
+            Command::PrivMsg { targets, message } => handle_privmsg(
+                &server_host,
+                ctx_nick,
+                &received.tags,
+                targets,
+                message,
+                conn_context,
+                &channels,
+                &connections,
+                server_context.max_targets,
+            ),
+            Command::Notice { targets, message } => handle_notice(
+                &server_host,
+                ctx_nick,
+                &received.tags,
+                targets,
+                message,
+                conn_context,
+                &channels,
+                &connections,
+                server_context.max_targets,
+            ),
+            Command::TagMsg { targets } => handle_tagmsg(
+                &server_host,
+                ctx_nick,
+                &received.tags,
+                targets,
+                conn_context,
+                &channels,
+                &connections,
+                server_context.max_targets,
+            ),
+            Command::Quit { message } => handle_quit(
+                message,
+                &mut channels,
+                &connections,
+                &mut whowas,
+                received.connection_id,
+            ),
+            Command::InputTooLong => Some(HashMap::from_iter([(
+                received.connection_id,
+                vec![Reply::ErrInputTooLong {
+                    server_host: server_host.clone(),
+                    nick: conn_context.nick.clone().unwrap_or_else(|| "*".to_string()),
+                }],
+            )])),
+            Command::Connected { .. } | Command::Reload | Command::Rehash => None,
+            Command::Unhandled => None,
             Command::Ping { token } => handle_ping(&server_host, ctx_nick, token, conn_context),
-            Command::Pong {} => None,
+            Command::Pong => None,
+            Command::Lusers => handle_lusers(
+                &server_host,
+                ctx_nick,
+                conn_context,
+                &connections,
+                &channels,
+                &stats,
+            ),
         };
 
+        // the welcome burst is followed by LUSERS and the MOTD once registration completes
+        let newly_registered = connections
+            .get(&received.connection_id)
+            .filter(|c| !was_registered && c.registration == RegistrationState::Registered);
+
+        // a K-line can only be checked now the user is known, and anyone banned
+        // gets nothing from registration besides being told so
+        let ban =
+            newly_registered.and_then(|c| server_context.bans.lock().unwrap().find(c).cloned());
+
+        if let Some(ban) = ban {
+            replies = disconnect_banned(
+                &server_host,
+                received.connection_id,
+                &ban,
+                &mut channels,
+                &connections,
+                &mut whowas,
+            );
+        } else if let Some(conn_context) = newly_registered {
+            let users = connections
+                .values()
+                .filter(|c| c.registration == RegistrationState::Registered)
+                .count();
+            stats.max_users = stats.max_users.max(users as u32);
+
+            let nick = conn_context.nick.clone().unwrap_or_default();
+
+            replies
+                .get_or_insert_with(HashMap::new)
+                .entry(received.connection_id)
+                .or_default()
+                .extend(
+                    lusers_replies(&server_host, &nick, &connections, &channels, &stats)
+                        .into_iter()
+                        .chain(motd_replies(server_context, &nick)),
+                );
+        }
+
         if let Some(replies) = replies {
-            send_replies(replies, &sender_channels).await
+            send_replies(replies, &sender_channels, &connections).await
         }
     }
 }
@@ -179,6 +589,7 @@ where
 async fn send_replies(
     replies_per_user: HashMap<Uuid, Vec<Reply>>,
     sender_channels: &HashMap<Uuid, ReplySender>,
+    connections: &HashMap<Uuid, ConnectionContext>,
 ) {
     for (connection_id, replies) in replies_per_user {
         let sender = match sender_channels.get(&connection_id) {
@@ -189,7 +600,23 @@ async fn send_replies(
             }
         };
 
+        // server-time can be negotiated on its own, any other tags
+        // are only understood by clients that negotiated message-tags
+        let has_capability = |capability| {
+            connections
+                .get(&connection_id)
+                .map(|c: &ConnectionContext| c.capabilities.contains(&capability))
+                .unwrap_or(false)
+        };
+        let server_time = has_capability(Capability::ServerTime);
+        let message_tags = has_capability(Capability::MessageTags);
+
         for reply in replies {
+            let reply = reply.retain_tags(|key| match key {
+                "time" => server_time || message_tags,
+                _ => message_tags,
+            });
+
             if let Err(e) = sender.send(reply).await {
                 println!("Error sending replies {:?}", e);
                 return;
@@ -200,16 +627,21 @@ async fn send_replies(
 
 #[cfg(test)]
 mod tests {
-    use chrono::Utc;
     use uuid::Uuid;
 
     use super::*;
     use crate::channels::FakeChannelReceiver;
     use crate::message_parsing::ReplySender;
+    use crate::settings::test_settings;
+    use crate::tags::Tags;
     use std::collections::VecDeque;
     use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
     use tokio::sync::mpsc::{self};
 
+    fn test_server_context() -> ServerContext {
+        ServerContext::new(&test_settings())
+    }
+
     #[tokio::test]
     pub async fn server_nickcommandsent_replystormissent() {
         // Arrange
@@ -218,6 +650,7 @@ mod tests {
 
         let mut messages = VecDeque::new();
         messages.push_back(Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Connected {
                 sender: ReplySender(sender),
@@ -225,11 +658,13 @@ mod tests {
                     Ipv4Addr::new(127, 0, 0, 1),
                     1234,
                 ))),
+                hostname: None,
             },
             connection_id,
         });
 
         messages.push_back(Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Nick {
                 nick: Some("JOE".to_string()),
@@ -237,33 +672,181 @@ mod tests {
             connection_id,
         });
 
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::User {
+                user: Some("joe".to_string()),
+                mode: Some("0".to_string()),
+                realname: Some("Joe".to_string()),
+            },
+            connection_id,
+        });
+
         let mut receiver = FakeChannelReceiver {
-            faked_messages: Box::new(messages),
+            faked_messages: messages,
             receive_count: 0,
         };
 
-        let context = ServerContext {
-            start_time: Utc::now(),
-            server_host: "localhost".to_string(),
-            version: "0.0.1".to_string(),
-            ping_frequency: std::time::Duration::from_secs(60),
-            motd_lines: vec![],
+        let (context_sender, _context_receiver) = watch::channel(test_server_context());
+
+        // Act
+        let (_shutdown_sender, shutdown_receiver) = mpsc::channel(1);
+        run(&context_sender, &mut receiver, shutdown_receiver)
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(&4, &receiver.receive_count);
+
+        let mut received = vec![];
+        while let Ok(m) = test_receiver.try_recv() {
+            received.push(m);
+        }
+
+        assert_eq!(16, received.len());
+    }
+
+    #[tokio::test]
+    pub async fn server_nickalreadyinuse_errorissent() {
+        // Arrange
+        let (sender, _test_receiver) = mpsc::channel(1000);
+        let (other_sender, mut other_test_receiver) = mpsc::channel(1000);
+        let connection_id = Uuid::new_v4();
+        let other_connection_id = Uuid::new_v4();
+
+        let mut messages = VecDeque::new();
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Connected {
+                sender: ReplySender(sender),
+                client_ip: None,
+                hostname: None,
+            },
+            connection_id,
+        });
+
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Connected {
+                sender: ReplySender(other_sender),
+                client_ip: None,
+                hostname: None,
+            },
+            connection_id: other_connection_id,
+        });
+
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Nick {
+                nick: Some("JOE".to_string()),
+            },
+            connection_id,
+        });
+
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Nick {
+                nick: Some("joe".to_string()),
+            },
+            connection_id: other_connection_id,
+        });
+
+        let mut receiver = FakeChannelReceiver {
+            faked_messages: messages,
+            receive_count: 0,
         };
 
+        let (context_sender, _context_receiver) = watch::channel(test_server_context());
+
         // Act
         let (_shutdown_sender, shutdown_receiver) = mpsc::channel(1);
-        run(&context, &mut receiver, shutdown_receiver)
+        run(&context_sender, &mut receiver, shutdown_receiver)
             .await
             .unwrap();
 
         // Assert
-        assert_eq!(&3, &receiver.receive_count);
+        let mut received = vec![];
+        while let Ok(m) = other_test_receiver.try_recv() {
+            received.push(m);
+        }
+
+        assert_eq!(
+            vec![Reply::ErrNicknameInUse {
+                server_host: "localhost".to_string(),
+                nick: "*".to_string(),
+                attempted_nick: "joe".to_string(),
+            }],
+            received
+        );
+    }
+
+    #[tokio::test]
+    pub async fn server_commandbeforeregistration_errorissent() {
+        // Arrange
+        let (sender, mut test_receiver) = mpsc::channel(1000);
+        let connection_id = Uuid::new_v4();
+
+        let mut messages = VecDeque::new();
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Connected {
+                sender: ReplySender(sender),
+                client_ip: None,
+                hostname: None,
+            },
+            connection_id,
+        });
 
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Nick {
+                nick: Some("JOE".to_string()),
+            },
+            connection_id,
+        });
+
+        messages.push_back(Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Join {
+                channels_to_join: Some(vec!["#foo".to_string()]),
+                keys: None,
+            },
+            connection_id,
+        });
+
+        let mut receiver = FakeChannelReceiver {
+            faked_messages: messages,
+            receive_count: 0,
+        };
+
+        let (context_sender, _context_receiver) = watch::channel(test_server_context());
+
+        // Act
+        let (_shutdown_sender, shutdown_receiver) = mpsc::channel(1);
+        run(&context_sender, &mut receiver, shutdown_receiver)
+            .await
+            .unwrap();
+
+        // Assert
         let mut received = vec![];
         while let Ok(m) = test_receiver.try_recv() {
             received.push(m);
         }
 
-        assert_eq!(15, received.len());
+        assert_eq!(
+            vec![Reply::ErrNotRegistered {
+                server_host: "localhost".to_string(),
+                nick: "JOE".to_string(),
+            }],
+            received
+        );
     }
 }
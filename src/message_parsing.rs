@@ -4,10 +4,12 @@ use tokio::sync::mpsc::Sender;
 use crate::error::Error::*;
 use crate::replies::Reply;
 use crate::result::Result;
+use crate::tags::{parse_tags, Tags};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Message {
+    pub tags: Tags,
     pub source: Option<String>,
     pub command: Command,
     pub connection_id: Uuid,
@@ -28,9 +30,21 @@ impl PartialEq for ReplySender {
 pub enum Command {
     Unhandled,
     Disconnected,
+    // raised by the listener rather than sent by the client, the line
+    // itself was thrown away
+    InputTooLong,
+    // raised by the server when it is told to reload its settings, by SIGHUP
+    Reload,
+    // raised once an OPER password has been checked, which is done away from
+    // the message handler as it is deliberately slow
+    OperVerified {
+        name: String,
+        verified: bool,
+    },
     Connected {
         sender: ReplySender,
         client_ip: Option<SocketAddr>,
+        hostname: Option<String>,
     },
     Nick {
         nick: Option<String>,
@@ -40,134 +54,323 @@ pub enum Command {
     },
     Join {
         channels_to_join: Option<Vec<String>>,
+        keys: Option<Vec<String>>,
     },
     Mode {
         channel: Option<String>,
+        mode_string: Option<String>,
+        mode_arguments: Vec<String>,
     },
     Who {
         mask: Option<String>,
         only_operators: bool,
+        // the WHOX fields and token, everything after the %
+        whox: Option<String>,
+    },
+    Whois {
+        nick: Option<String>,
+    },
+    Away {
+        message: Option<String>,
+    },
+    Oper {
+        name: Option<String>,
+        password: Option<String>,
+    },
+    Whowas {
+        nick: Option<String>,
+        count: Option<usize>,
+    },
+    Kill {
+        nick: Option<String>,
+        reason: Option<String>,
+    },
+    // durations are in minutes, none means the ban is permanent
+    Kline {
+        duration: Option<u64>,
+        mask: Option<String>,
+        reason: Option<String>,
+    },
+    Dline {
+        duration: Option<u64>,
+        mask: Option<String>,
+        reason: Option<String>,
+    },
+    Unkline {
+        mask: Option<String>,
     },
+    Undline {
+        mask: Option<String>,
+    },
+    Stats {
+        query: Option<String>,
+    },
+    Rehash,
     PrivMsg {
-        channel: Option<String>,
+        targets: Option<Vec<String>>,
         message: Option<String>,
     },
+    Notice {
+        targets: Option<Vec<String>>,
+        message: Option<String>,
+    },
+    TagMsg {
+        targets: Option<Vec<String>>,
+    },
     User {
         user: Option<String>,
         mode: Option<String>,
         realname: Option<String>,
     },
     Pong,
+    Lusers,
     Quit {
         message: Option<String>,
     },
     Part {
         channels_to_leave: Option<Vec<String>>,
     },
+    Invite {
+        nick: Option<String>,
+        channel: Option<String>,
+    },
+    Names {
+        channels: Option<Vec<String>>,
+    },
+    List {
+        filters: Option<Vec<String>>,
+    },
+    Kick {
+        channels: Option<Vec<String>>,
+        users: Option<Vec<String>>,
+        reason: Option<String>,
+    },
+    Topic {
+        channel: Option<String>,
+        topic: Option<String>,
+    },
+    Cap {
+        subcommand: Option<String>,
+        arguments: Option<String>,
+    },
 }
 
-// TODO this doesnt handle NICK params
-impl Message {
-    pub fn from_str(s: &str, connection_id: Uuid) -> Result<Self> {
-        let has_source = s.starts_with(':');
-        let mut words = s.split_whitespace();
+// The most parameters a single message can carry (RFC 2812 2.3.1), once
+// there are 14 middle parameters the rest of the line is the trailing one
+const MAX_PARAMS: usize = 15;
+
+// A message broken down per the RFC 1459/2812 grammar, before any
+// command specific meaning is given to its parameters
+#[derive(Debug, PartialEq)]
+struct RawMessage {
+    tags: Tags,
+    source: Option<String>,
+    command: String,
+    middle: Vec<String>,
+    trailing: Option<String>,
+}
 
-        let source = if has_source {
-            words.next().map(|s| s.trim_start_matches(':').to_owned())
-        } else {
-            None
+impl RawMessage {
+    fn parse(s: &str) -> Result<Self> {
+        // tags, if present, always come first and are followed by a space
+        let (tags, rest) = match s.strip_prefix('@') {
+            Some(rest) => {
+                let (raw_tags, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                (parse_tags(raw_tags), rest)
+            }
+            None => (Tags::new(), s),
         };
 
-        let mut raw_command = match words.next() {
-            Some(s) => Ok(s),
-            None => Err(MessageParsingErrorMissingCommand),
-        }?;
+        let rest = rest.trim_start_matches(' ');
+
+        let (source, rest) = match rest.strip_prefix(':') {
+            Some(rest) => {
+                let (source, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                (Some(source.to_string()), rest.trim_start_matches(' '))
+            }
+            None => (None, rest),
+        };
 
-        let uppercased = raw_command.to_uppercase();
-        raw_command = uppercased.as_ref();
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
 
-        let command = match raw_command {
-            "PRIVMSG" => {
-                let channel = words
-                    .next()
-                    .map(|s| s.to_owned())
-                    .filter(|s| s.starts_with('#'));
+        if command.is_empty() {
+            return Err(MessageParsingErrorMissingCommand);
+        }
 
-                let message = words
-                    .map(|w| format!("{} ", w))
-                    .collect::<String>()
-                    .trim_start_matches(':')
-                    .trim_end()
-                    .to_string();
+        let mut middle = vec![];
+        let mut trailing = None;
 
-                let message = Some(message).filter(|m| !String::is_empty(m));
+        loop {
+            rest = rest.trim_start_matches(' ');
 
-                Command::PrivMsg { channel, message }
+            if rest.is_empty() {
+                break;
             }
-            "NICK" => {
-                let nick = words.next().map(|s| s.to_owned());
 
-                Command::Nick { nick }
-            }
-            "PING" => {
-                let token = words.next().map(|s| s.trim_start_matches(':').to_owned());
-                Command::Ping { token }
-            }
-            "JOIN" => {
-                let channels_to_join: Option<Vec<String>> = words
-                    .next()
-                    .map(|s| s.to_owned().split(',').map(|s| s.to_string()).collect());
-                Command::Join { channels_to_join }
-            }
-            "PART" => {
-                let channels_to_leave: Option<Vec<String>> = words
-                    .next()
-                    .map(|s| s.to_owned().split(',').map(|s| s.to_string()).collect());
-                Command::Part { channels_to_leave }
+            // the trailing parameter is taken verbatim, spaces and all
+            if middle.len() == MAX_PARAMS - 1 || rest.starts_with(':') {
+                trailing = Some(rest.strip_prefix(':').unwrap_or(rest).to_string());
+                break;
             }
-            "MODE" => {
-                let channel = words.next().map(|s| s.to_owned());
-                Command::Mode { channel }
+
+            let (param, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+            middle.push(param.to_string());
+            rest = remaining;
+        }
+
+        Ok(RawMessage {
+            tags,
+            source,
+            command: command.to_uppercase(),
+            middle,
+            trailing,
+        })
+    }
+
+    // Commands don't care whether a parameter was a middle or the trailing
+    // one, only where it sits in the list
+    fn params(&self) -> Vec<String> {
+        let mut params = self.middle.clone();
+
+        if let Some(trailing) = &self.trailing {
+            params.push(trailing.clone());
+        }
+
+        params
+    }
+}
+
+fn split_list(s: String) -> Vec<String> {
+    s.split(',').map(|s| s.to_string()).collect()
+}
+
+impl Message {
+    pub fn from_str(s: &str, connection_id: Uuid) -> Result<Self> {
+        let raw = RawMessage::parse(s)?;
+        let params = raw.params();
+        let param = |i: usize| params.get(i).cloned();
+
+        let command = match raw.command.as_ref() {
+            "PRIVMSG" | "NOTICE" => {
+                let targets = param(0).map(split_list);
+                let message = param(1).filter(|m| !m.is_empty());
+
+                match raw.command.as_ref() {
+                    "NOTICE" => Command::Notice { targets, message },
+                    _ => Command::PrivMsg { targets, message },
+                }
             }
+            "TAGMSG" => Command::TagMsg {
+                targets: param(0).map(split_list),
+            },
+            "NICK" => Command::Nick { nick: param(0) },
+            "PING" => Command::Ping { token: param(0) },
+            "JOIN" => Command::Join {
+                channels_to_join: param(0).map(split_list),
+                keys: param(1).map(split_list),
+            },
+            "PART" => Command::Part {
+                channels_to_leave: param(0).map(split_list),
+            },
+            "INVITE" => Command::Invite {
+                nick: param(0),
+                channel: param(1),
+            },
+            "NAMES" => Command::Names {
+                channels: param(0).map(split_list),
+            },
+            "LIST" => Command::List {
+                filters: param(0).map(split_list),
+            },
+            "KICK" => Command::Kick {
+                channels: param(0).map(split_list),
+                users: param(1).map(split_list),
+                reason: param(2),
+            },
+            "MODE" => Command::Mode {
+                channel: param(0),
+                mode_string: param(1),
+                mode_arguments: params.iter().skip(2).cloned().collect(),
+            },
             "WHO" => {
-                let mask = words.next().map(|s| s.to_owned());
-
-                let only_operators = match words.next() {
-                    Some("o") => true,
-                    Some(_) | None => false,
+                let options = param(1).unwrap_or_default();
+                let (flags, whox) = match options.split_once('%') {
+                    Some((f, w)) => (f, Some(w.to_string())),
+                    None => (options.as_str(), None),
                 };
 
                 Command::Who {
-                    mask,
-                    only_operators,
+                    mask: param(0),
+                    only_operators: flags.contains('o'),
+                    whox,
                 }
             }
-            "USER" => {
-                let user = words.next().map(|s| s.to_owned());
-                let mode = words.next().map(|s| s.to_owned());
-
-                // skip the "unused" argument
-                words.next();
-
-                let realname = words.next().map(|s| s.to_owned());
+            // the optional first parameter names a server, which there is only one of
+            "WHOIS" => Command::Whois {
+                nick: params.last().cloned(),
+            },
+            "AWAY" => Command::Away { message: param(0) },
+            "OPER" => Command::Oper {
+                name: param(0),
+                password: param(1),
+            },
+            "WHOWAS" => Command::Whowas {
+                nick: param(0),
+                count: param(1).and_then(|c| c.parse().ok()),
+            },
+            "KILL" => Command::Kill {
+                nick: param(0),
+                reason: param(1),
+            },
+            "KLINE" | "DLINE" => {
+                // the duration is optional, so a number first is taken to be one
+                let duration = param(0).and_then(|d| d.parse().ok());
+                let skip = if duration.is_some() { 1 } else { 0 };
+                let (mask, reason) = (param(skip), param(skip + 1));
 
-                Command::User {
-                    user,
-                    mode,
-                    realname,
+                match raw.command.as_ref() {
+                    "KLINE" => Command::Kline {
+                        duration,
+                        mask,
+                        reason,
+                    },
+                    _ => Command::Dline {
+                        duration,
+                        mask,
+                        reason,
+                    },
                 }
             }
+            "UNKLINE" => Command::Unkline { mask: param(0) },
+            "UNDLINE" => Command::Undline { mask: param(0) },
+            "STATS" => Command::Stats { query: param(0) },
+            "REHASH" => Command::Rehash,
+            // the third parameter is unused
+            "USER" => Command::User {
+                user: param(0),
+                mode: param(1),
+                realname: param(3),
+            },
             "PONG" => Command::Pong,
-            "QUIT" => {
-                let message = words.next().map(|s| s.to_string());
-
-                Command::Quit { message }
-            }
+            // the mask and target parameters only mean something with multiple servers
+            "LUSERS" => Command::Lusers,
+            "QUIT" => Command::Quit { message: param(0) },
+            // an absent topic is a query, whereas an empty one (just ":")
+            // clears the topic so these need to be kept distinct
+            "TOPIC" => Command::Topic {
+                channel: param(0),
+                topic: param(1),
+            },
+            "CAP" => Command::Cap {
+                subcommand: param(0).map(|s| s.to_uppercase()),
+                arguments: param(1).filter(|a| !a.is_empty()),
+            },
             _ => Command::Unhandled,
         };
 
         let message = Message {
-            source,
+            tags: raw.tags,
+            source: raw.source,
             command,
             connection_id,
         };
@@ -191,10 +394,11 @@ mod tests {
 
     #[test]
     fn message_parsing_nick_command_has_prefix_success() {
-        let expected_nick = format!("Joe");
+        let expected_nick = "Joe".to_string();
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
-            source: Some(format!("FOO")),
+            tags: Tags::new(),
+            source: Some("FOO".to_string()),
             command: Command::Nick {
                 nick: Some(expected_nick.clone()),
             },
@@ -216,9 +420,10 @@ mod tests {
 
     #[test]
     fn message_parsing_nick_command_no_prefix_success() {
-        let expected_nick = format!("Joe");
+        let expected_nick = "Joe".to_string();
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Nick {
                 nick: Some(expected_nick.clone()),
@@ -234,9 +439,10 @@ mod tests {
 
     #[test]
     fn message_parsing_handles_lowercase_commands() {
-        let expected_nick = format!("Joe");
+        let expected_nick = "Joe".to_string();
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Nick {
                 nick: Some(expected_nick.clone()),
@@ -255,9 +461,11 @@ mod tests {
         let expected_channel = "foobar".to_string();
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Join {
                 channels_to_join: Some(vec![expected_channel.clone()]),
+                keys: None,
             },
             connection_id,
         };
@@ -267,6 +475,20 @@ mod tests {
         assert_eq!(expected_message.command, message.command);
     }
 
+    #[test]
+    fn message_parsing_join_with_keys_success() {
+        let message = Message::from_str("JOIN #foo,#bar secret", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+
+        assert_eq!(
+            Command::Join {
+                channels_to_join: Some(vec!["#foo".to_string(), "#bar".to_string()]),
+                keys: Some(vec!["secret".to_string()]),
+            },
+            message.command
+        );
+    }
+
     #[test]
     fn message_parsing_join_multiple_channels_success() {
         let expected_channel_1 = "foobar".to_string();
@@ -275,9 +497,11 @@ mod tests {
 
         let expected_channels = vec![expected_channel_1.clone(), expected_channel_2.clone()];
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Join {
                 channels_to_join: Some(expected_channels.clone()),
+                keys: None,
             },
             connection_id,
         };
@@ -292,14 +516,16 @@ mod tests {
     fn message_parsing_who_with_no_mask_success() {
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Who {
                 mask: None,
                 only_operators: false,
+                whox: None,
             },
             connection_id,
         };
-        let raw_str = &format!("WHO");
+        let raw_str = "WHO";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         assert_eq!(expected_message.command, message.command);
@@ -309,14 +535,16 @@ mod tests {
     fn message_parsing_who_only_operators_defaults_to_false() {
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Who {
                 mask: Some("#heythere".to_string()),
                 only_operators: false,
+                whox: None,
             },
             connection_id,
         };
-        let raw_str = &format!("WHO #heythere");
+        let raw_str = "WHO #heythere";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         assert_eq!(expected_message.command, message.command);
@@ -326,31 +554,122 @@ mod tests {
     fn message_parsing_who_only_operators_requested_success() {
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Who {
                 mask: Some("#heythere".to_string()),
                 only_operators: true,
+                whox: None,
             },
             connection_id,
         };
-        let raw_str = &format!("WHO #heythere o");
+        let raw_str = "WHO #heythere o";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         assert_eq!(expected_message.command, message.command);
     }
 
+    #[test_case("WHOIS BOB", Some("BOB") ; "nick_only")]
+    #[test_case("WHOIS localhost BOB", Some("BOB") ; "with_server")]
+    #[test_case("WHOIS", None ; "no_nick")]
+    fn message_parsing_whois_nick_is_last_param(raw_str: &str, nick: Option<&str>) {
+        let message =
+            Message::from_str(raw_str, Uuid::new_v4()).expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Whois {
+                nick: nick.map(|n| n.to_string())
+            },
+            message.command
+        );
+    }
+
+    #[test_case("KLINE *@example.com :spamming", None, "*@example.com", Some("spamming") ; "permanent")]
+    #[test_case("KLINE 60 *@example.com :spamming", Some(60), "*@example.com", Some("spamming") ; "with_duration")]
+    #[test_case("KLINE 60 *@example.com", Some(60), "*@example.com", None ; "without_reason")]
+    fn message_parsing_kline_success(
+        raw_str: &str,
+        duration: Option<u64>,
+        mask: &str,
+        reason: Option<&str>,
+    ) {
+        let message_parsed =
+            Message::from_str(raw_str, Uuid::new_v4()).expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Kline {
+                duration,
+                mask: Some(mask.to_string()),
+                reason: reason.map(|r| r.to_string()),
+            },
+            message_parsed.command
+        );
+    }
+
+    #[test]
+    fn message_parsing_kill_keeps_whole_reason() {
+        let message_parsed = Message::from_str("KILL JIM :spamming the channel", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Kill {
+                nick: Some("JIM".to_string()),
+                reason: Some("spamming the channel".to_string()),
+            },
+            message_parsed.command
+        );
+    }
+
+    #[test_case("AWAY :gone fishing", Some("gone fishing") ; "with_message")]
+    #[test_case("AWAY", None ; "without_message")]
+    fn message_parsing_away_success(raw_str: &str, message: Option<&str>) {
+        let message_parsed =
+            Message::from_str(raw_str, Uuid::new_v4()).expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Away {
+                message: message.map(|m| m.to_string())
+            },
+            message_parsed.command
+        );
+    }
+
+    #[test]
+    fn message_parsing_whowas_with_count_success() {
+        let message = Message::from_str("WHOWAS BOB 2", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Whowas {
+                nick: Some("BOB".to_string()),
+                count: Some(2),
+            },
+            message.command
+        );
+    }
+
+    #[test]
+    fn message_parsing_who_whox_fields_and_token_success() {
+        let message = Message::from_str("WHO #heythere o%cnf,42", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+        assert_eq!(
+            Command::Who {
+                mask: Some("#heythere".to_string()),
+                only_operators: true,
+                whox: Some("cnf,42".to_string()),
+            },
+            message.command
+        );
+    }
+
     #[test]
     fn message_parsing_privmsg_multi_word_message_is_parsed() {
         let connection_id = Uuid::new_v4();
         let expected_message = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::PrivMsg {
-                channel: Some("#blah".to_string()),
+                targets: Some(vec!["#blah".to_string()]),
                 message: Some("HI. THERE? HELLO!".to_string()),
             },
             connection_id,
         };
-        let raw_str = &format!("PRIVMSG #blah :HI. THERE? HELLO!");
+        let raw_str = "PRIVMSG #blah :HI. THERE? HELLO!";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         assert_eq!(expected_message.command, message.command);
@@ -359,13 +678,14 @@ mod tests {
     #[test]
     fn message_parsing_privmsg_channel_missing_returns_none() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PRIVMSG");
+        let raw_str = "PRIVMSG";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::PrivMsg {
-                channel: None,
+                targets: None,
                 message: None,
             },
             connection_id,
@@ -374,15 +694,16 @@ mod tests {
     }
 
     #[test]
-    fn message_parsing_privmsg_channel_missing_hash_errors() {
+    fn message_parsing_privmsg_only_trailing_is_the_target() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PRIVMSG :foo");
+        let raw_str = "PRIVMSG :foo";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::PrivMsg {
-                channel: None,
+                targets: Some(vec!["foo".to_string()]),
                 message: None,
             },
             connection_id,
@@ -397,9 +718,10 @@ mod tests {
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::PrivMsg {
-                channel: Some("#hey".to_string()),
+                targets: Some(vec!["#hey".to_string()]),
                 message: None,
             },
             connection_id,
@@ -408,13 +730,52 @@ mod tests {
         assert_eq!(expected, message);
     }
 
+    #[test]
+    fn message_parsing_privmsg_multiple_targets_parses_correctly() {
+        let connection_id = Uuid::new_v4();
+        let raw_str = "PRIVMSG #foo,BOB :hi there";
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::PrivMsg {
+                targets: Some(vec!["#foo".to_string(), "BOB".to_string()]),
+                message: Some("hi there".to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test]
+    fn message_parsing_notice_parses_correctly() {
+        let connection_id = Uuid::new_v4();
+        let raw_str = "NOTICE BOB :hi there";
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Notice {
+                targets: Some(vec!["BOB".to_string()]),
+                message: Some("hi there".to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
     #[test]
     fn message_parsing_part_missing_channels() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PART");
+        let raw_str = "PART";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Part {
                 channels_to_leave: None,
@@ -427,10 +788,11 @@ mod tests {
     #[test]
     fn message_parsing_part_single_channel_parses_correctly() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PART #foo");
+        let raw_str = "PART #foo";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Part {
                 channels_to_leave: Some(vec!["#foo".to_string()]),
@@ -444,10 +806,11 @@ mod tests {
     #[test]
     fn message_parsing_part_multiple_channels_parses_correctly() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PART #foo,#bar,#baz");
+        let raw_str = "PART #foo,#bar,#baz";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Part {
                 channels_to_leave: Some(vec![
@@ -465,10 +828,11 @@ mod tests {
     #[test]
     fn message_parsing_ping_token_provided_parses_correctly() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PING :foobar");
+        let raw_str = "PING :foobar";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Ping {
                 token: Some("foobar".to_string()),
@@ -482,10 +846,11 @@ mod tests {
     #[test]
     fn message_parsing_ping_empty_token_colon_parses_correctly() {
         let connection_id = Uuid::new_v4();
-        let raw_str = &format!("PING :");
+        let raw_str = "PING :";
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Ping {
                 token: Some("".to_string()),
@@ -503,6 +868,7 @@ mod tests {
         let message =
             Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
         let expected = Message {
+            tags: Tags::new(),
             source: None,
             command: Command::Ping { token: None },
             connection_id,
@@ -510,4 +876,200 @@ mod tests {
 
         assert_eq!(expected, message);
     }
+
+    #[test_case("TOPIC #foo", None ; "query")]
+    #[test_case("TOPIC #foo :", Some("") ; "clear")]
+    #[test_case("TOPIC #foo :new topic here", Some("new topic here") ; "set")]
+    fn message_parsing_topic_parses_correctly(raw_str: &str, topic: Option<&str>) {
+        let connection_id = Uuid::new_v4();
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Topic {
+                channel: Some("#foo".to_string()),
+                topic: topic.map(|t| t.to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test]
+    fn message_parsing_invite_parses_correctly() {
+        let message = Message::from_str("INVITE BOB #foo", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+
+        assert_eq!(
+            Command::Invite {
+                nick: Some("BOB".to_string()),
+                channel: Some("#foo".to_string()),
+            },
+            message.command
+        );
+    }
+
+    #[test]
+    fn message_parsing_kick_parses_correctly() {
+        let message = Message::from_str("KICK #foo,#bar BOB,TOM :go away", Uuid::new_v4())
+            .expect("Failed to parse valid message");
+
+        assert_eq!(
+            Command::Kick {
+                channels: Some(vec!["#foo".to_string(), "#bar".to_string()]),
+                users: Some(vec!["BOB".to_string(), "TOM".to_string()]),
+                reason: Some("go away".to_string()),
+            },
+            message.command
+        );
+    }
+
+    #[test_case("MODE #foo", None, &[] ; "query")]
+    #[test_case("MODE #foo +o-v BOB :TOM", Some("+o-v"), &["BOB", "TOM"] ; "with_arguments")]
+    fn message_parsing_mode_parses_correctly(
+        raw_str: &str,
+        mode_string: Option<&str>,
+        mode_arguments: &[&str],
+    ) {
+        let connection_id = Uuid::new_v4();
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Mode {
+                channel: Some("#foo".to_string()),
+                mode_string: mode_string.map(|m| m.to_string()),
+                mode_arguments: mode_arguments.iter().map(|a| a.to_string()).collect(),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test_case("CAP LS 302", Some("LS"), Some("302") ; "ls_with_version")]
+    #[test_case("CAP req :echo-message server-time", Some("REQ"), Some("echo-message server-time") ; "req_multiple")]
+    #[test_case("CAP END", Some("END"), None ; "end")]
+    #[test_case("CAP", None, None ; "missing_subcommand")]
+    fn message_parsing_cap_parses_correctly(
+        raw_str: &str,
+        subcommand: Option<&str>,
+        arguments: Option<&str>,
+    ) {
+        let connection_id = Uuid::new_v4();
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Cap {
+                subcommand: subcommand.map(|s| s.to_string()),
+                arguments: arguments.map(|a| a.to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test]
+    fn message_parsing_tags_are_parsed() {
+        let connection_id = Uuid::new_v4();
+        let raw_str = "@+typing=active;+example.com/foo=a\\sb :JIM TAGMSG #foo";
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+
+        let mut tags = Tags::new();
+        tags.insert("+typing".to_string(), "active".to_string());
+        tags.insert("+example.com/foo".to_string(), "a b".to_string());
+
+        let expected = Message {
+            tags,
+            source: Some("JIM".to_string()),
+            command: Command::TagMsg {
+                targets: Some(vec!["#foo".to_string()]),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test]
+    fn message_parsing_tags_without_command_errors() {
+        Message::from_str("@+typing=active", Uuid::new_v4()).expect_err("Expected error!");
+    }
+
+    #[test]
+    fn message_parsing_quit_keeps_whole_message() {
+        let connection_id = Uuid::new_v4();
+        let raw_str = "QUIT :gone  for lunch ";
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::Quit {
+                message: Some("gone  for lunch ".to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test]
+    fn message_parsing_user_realname_with_spaces() {
+        let connection_id = Uuid::new_v4();
+        let raw_str = "USER joe 0 * :Joe Bloggs";
+        let message =
+            Message::from_str(raw_str, connection_id).expect("Failed to parse valid message");
+        let expected = Message {
+            tags: Tags::new(),
+            source: None,
+            command: Command::User {
+                user: Some("joe".to_string()),
+                mode: Some("0".to_string()),
+                realname: Some("Joe Bloggs".to_string()),
+            },
+            connection_id,
+        };
+
+        assert_eq!(expected, message);
+    }
+
+    #[test_case("CMD", &[], None ; "no_params")]
+    #[test_case("CMD  a   b", &["a", "b"], None ; "repeated_spaces_between_middles")]
+    #[test_case("CMD a :", &["a"], Some("") ; "empty_trailing")]
+    #[test_case("CMD a ::b c", &["a"], Some(":b c") ; "trailing_starting_with_colon")]
+    #[test_case("CMD a b:c", &["a", "b:c"], None ; "colon_inside_middle")]
+    fn raw_message_parses_params(raw_str: &str, middle: &[&str], trailing: Option<&str>) {
+        let raw = RawMessage::parse(raw_str).expect("Failed to parse valid message");
+
+        assert_eq!("CMD", raw.command);
+        assert_eq!(middle, raw.middle.as_slice());
+        assert_eq!(trailing.map(|t| t.to_string()), raw.trailing);
+    }
+
+    #[test]
+    fn raw_message_rest_of_line_after_fourteen_middles_is_trailing() {
+        let raw = RawMessage::parse("CMD 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16")
+            .expect("Failed to parse valid message");
+
+        assert_eq!(14, raw.middle.len());
+        assert_eq!(Some("15 16".to_string()), raw.trailing);
+        assert_eq!(MAX_PARAMS, raw.params().len());
+    }
+
+    #[test]
+    fn raw_message_source_is_parsed() {
+        let raw = RawMessage::parse(":nick!user@host PRIVMSG #foo :hi")
+            .expect("Failed to parse valid message");
+
+        assert_eq!(Some("nick!user@host".to_string()), raw.source);
+        assert_eq!("PRIVMSG", raw.command);
+    }
 }
@@ -0,0 +1,186 @@
+// Channel modes are grouped by when they take an argument, which is what
+// CHANMODES tells clients so they can parse mode strings they don't know
+//   A - list modes, take one to add or remove an entry, or none to see the list
+//   B - always take one, even when being unset
+//   C - only take one when being set
+//   D - never take one
+// PREFIX modes (o and v) always take a nick, like type B
+pub const CHANMODES: &str = "beI,k,l,imnpst";
+
+// the prefix modes and the symbols shown for them, highest first
+pub const PREFIX: &str = "(ov)@+";
+
+// every mode a user or channel can have, and which channel modes can take an
+// argument, as listed in RPL_MYINFO
+pub const USER_MODES: &str = "o";
+pub const CHANNEL_MODES: &str = "Ibeiklmnopstv";
+pub const CHANNEL_MODES_WITH_ARGUMENT: &str = "Ibeklov";
+
+// how many modes that take an argument can be changed by a single MODE
+pub const MAX_MODES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeClass {
+    List,
+    AlwaysArgument,
+    SetArgument,
+    NoArgument,
+    Prefix,
+}
+
+pub fn mode_class(mode: char) -> Option<ModeClass> {
+    match mode {
+        'b' | 'e' | 'I' => Some(ModeClass::List),
+        'o' | 'v' => Some(ModeClass::Prefix),
+        'k' => Some(ModeClass::AlwaysArgument),
+        'l' => Some(ModeClass::SetArgument),
+        'i' | 'm' | 'n' | 'p' | 's' | 't' => Some(ModeClass::NoArgument),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub argument: Option<String>,
+}
+
+// Splits a mode string into the individual changes, taking arguments in
+// order for the modes that need them. A mode missing its argument is
+// dropped, and unknown modes are handed back so they can be reported
+pub fn parse_mode_changes(mode_string: &str, arguments: &[String]) -> (Vec<ModeChange>, Vec<char>) {
+    let mut changes = vec![];
+    let mut unknown = vec![];
+    let mut arguments = arguments.iter();
+    let mut adding = true;
+
+    for mode in mode_string.chars() {
+        let class = match mode {
+            '+' => {
+                adding = true;
+                continue;
+            }
+            '-' => {
+                adding = false;
+                continue;
+            }
+            _ => match mode_class(mode) {
+                Some(c) => c,
+                None => {
+                    unknown.push(mode);
+                    continue;
+                }
+            },
+        };
+
+        let takes_argument = match class {
+            ModeClass::List | ModeClass::AlwaysArgument | ModeClass::Prefix => true,
+            ModeClass::SetArgument => adding,
+            ModeClass::NoArgument => false,
+        };
+
+        let argument = match takes_argument {
+            true => match arguments.next() {
+                Some(a) => Some(a.clone()),
+                // a list mode without an argument is asking to see the list
+                None if class == ModeClass::List => None,
+                None => continue,
+            },
+            false => None,
+        };
+
+        changes.push(ModeChange {
+            adding,
+            mode,
+            argument,
+        });
+    }
+
+    (changes, unknown)
+}
+
+// Builds the mode string and arguments for a set of changes that have been
+// applied, only switching between + and - where needed
+pub fn format_mode_changes(changes: &[ModeChange]) -> (String, Vec<String>) {
+    let mut mode_string = String::new();
+    let mut arguments = vec![];
+    let mut adding = None;
+
+    for change in changes {
+        if adding != Some(change.adding) {
+            mode_string.push(if change.adding { '+' } else { '-' });
+            adding = Some(change.adding);
+        }
+
+        mode_string.push(change.mode);
+
+        if let Some(argument) = &change.argument {
+            arguments.push(argument.clone());
+        }
+    }
+
+    (mode_string, arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    fn change(adding: bool, mode: char, argument: Option<&str>) -> ModeChange {
+        ModeChange {
+            adding,
+            mode,
+            argument: argument.map(|a| a.to_string()),
+        }
+    }
+
+    #[test]
+    fn parse_mode_changes_takes_arguments_by_class() {
+        let arguments = ["secret".to_string(), "10".to_string(), "BOB".to_string()];
+
+        let (changes, unknown) = parse_mode_changes("+kl-lo+nXb", &arguments);
+
+        assert_eq!(
+            vec![
+                change(true, 'k', Some("secret")),
+                change(true, 'l', Some("10")),
+                change(false, 'l', None),
+                change(false, 'o', Some("BOB")),
+                change(true, 'n', None),
+                change(true, 'b', None),
+            ],
+            changes
+        );
+        assert_eq!(vec!['X'], unknown);
+    }
+
+    #[test_case("+k", &[] ; "key")]
+    #[test_case("+l", &[] ; "limit")]
+    #[test_case("-v", &[] ; "voice")]
+    fn parse_mode_changes_missing_argument_is_dropped(mode_string: &str, arguments: &[String]) {
+        let (changes, _) = parse_mode_changes(mode_string, arguments);
+
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn format_mode_changes_groups_directions() {
+        let changes = vec![
+            change(true, 'o', Some("BOB")),
+            change(true, 't', None),
+            change(false, 'l', None),
+            change(true, 'k', Some("secret")),
+        ];
+
+        assert_eq!(
+            (
+                "+ot-l+k".to_string(),
+                vec!["BOB".to_string(), "secret".to_string()]
+            ),
+            format_mode_changes(&changes)
+        );
+    }
+}
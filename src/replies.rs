@@ -1,9 +1,20 @@
 use chrono::{DateTime, Utc};
-use std::{fmt::Display, net::SocketAddr};
+use std::fmt::Display;
 use uuid::Uuid;
 
-#[derive(PartialEq, Debug)]
+use crate::{
+    codec::MAX_LINE_LEN,
+    tags::{serialize_tags, Tags},
+};
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Reply {
+    // Wraps any other reply with IRCv3 message tags, these are stripped
+    // before sending to clients that haven't negotiated message-tags
+    Tagged {
+        tags: Tags,
+        reply: Box<Reply>,
+    },
     Welcome {
         server_host: String,
         nick: String,
@@ -24,13 +35,14 @@ pub enum Reply {
         version: String,
         user_modes: String,
         channel_modes: String,
+        channel_modes_with_argument: String,
     },
     Support {
         server_host: String,
         nick: String,
-        channel_len: u32,
+        tokens: Vec<String>,
     },
-    StatsDLine {
+    StatsConn {
         server_host: String,
         nick: String,
         connections: u32,
@@ -82,13 +94,88 @@ pub enum Reply {
         nick: String,
         mask: String,
     },
+    WhoSpc {
+        server_host: String,
+        nick: String,
+        fields: Vec<String>,
+        real_name: Option<String>,
+    },
+    Away {
+        server_host: String,
+        nick: String,
+        target: String,
+        message: String,
+    },
+    UnAway {
+        server_host: String,
+        nick: String,
+    },
+    NowAway {
+        server_host: String,
+        nick: String,
+    },
+    AwayNotify {
+        client: String,
+        message: Option<String>,
+    },
+    WhoisUser {
+        server_host: String,
+        nick: String,
+        target: String,
+        user: String,
+        host: String,
+        real_name: String,
+    },
+    WhoisServer {
+        server_host: String,
+        nick: String,
+        target: String,
+        server: String,
+        server_info: String,
+    },
+    WhoisOperator {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
+    WhoisIdle {
+        server_host: String,
+        nick: String,
+        target: String,
+        idle_secs: i64,
+        signon_time: DateTime<Utc>,
+    },
+    WhoisChannels {
+        server_host: String,
+        nick: String,
+        target: String,
+        channels: Vec<String>,
+    },
+    EndOfWhois {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
+    WhowasUser {
+        server_host: String,
+        nick: String,
+        target: String,
+        user: String,
+        host: String,
+        real_name: String,
+    },
+    EndOfWhowas {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
     // TODO mode should not be plain strings
     ChannelModeIs {
         server_host: String,
         nick: String,
         channel: String,
         mode_string: String,
-        mode_arguments: String,
+        mode_arguments: Vec<String>,
     },
     CreationTime {
         server_host: String,
@@ -96,6 +183,71 @@ pub enum Reply {
         channel: String,
         created_at: DateTime<Utc>,
     },
+    InviteList {
+        server_host: String,
+        nick: String,
+        channel: String,
+        mask: String,
+        set_by: String,
+        set_at: DateTime<Utc>,
+    },
+    EndOfInviteList {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ExceptList {
+        server_host: String,
+        nick: String,
+        channel: String,
+        mask: String,
+        set_by: String,
+        set_at: DateTime<Utc>,
+    },
+    EndOfExceptList {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    BanList {
+        server_host: String,
+        nick: String,
+        channel: String,
+        mask: String,
+        set_by: String,
+        set_at: DateTime<Utc>,
+    },
+    EndOfBanList {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    Inviting {
+        server_host: String,
+        nick: String,
+        target: String,
+        channel: String,
+    },
+    ListStart {
+        server_host: String,
+        nick: String,
+    },
+    List {
+        server_host: String,
+        nick: String,
+        channel: String,
+        visible_users: usize,
+        topic: String,
+    },
+    ListEnd {
+        server_host: String,
+        nick: String,
+    },
+    NoTopic {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
     Topic {
         server_host: String,
         nick: String,
@@ -106,6 +258,7 @@ pub enum Reply {
         server_host: String,
         channel: String,
         nick: String,
+        set_by: String,
         set_at: DateTime<Utc>,
     },
     Who {
@@ -116,11 +269,13 @@ pub enum Reply {
         other_host: String,
         other_server: String,
         other_nick: String,
+        flags: String,
         other_realname: String,
     },
     Nam {
         server_host: String,
         nick: String,
+        symbol: char,
         channel: String,
         channel_users: Vec<String>,
     },
@@ -145,6 +300,12 @@ pub enum Reply {
     Ping {
         server_host: String,
     },
+    Cap {
+        server_host: String,
+        nick: String,
+        subcommand: String,
+        capabilities: String,
+    },
     Pong {
         server_host: String,
         token: String,
@@ -157,50 +318,385 @@ pub enum Reply {
         client: String,
         channel: String,
     },
-    PrivMsg {
-        client_host: Option<SocketAddr>,
-        nick: Option<String>,
-        user: Option<String>,
+    Invite {
+        client: String,
+        target: String,
         channel: String,
+    },
+    Kick {
+        client: String,
+        channel: String,
+        target: String,
+        reason: String,
+    },
+    Mode {
+        client: String,
+        target: String,
+        mode_string: String,
+        mode_arguments: Vec<String>,
+    },
+    TopicChange {
+        client: String,
+        channel: String,
+        topic: String,
+    },
+    Nick {
+        client: String,
+        nick: String,
+    },
+    PrivMsg {
+        client: String,
+        target: String,
         message: String,
     },
+    Notice {
+        client: String,
+        target: String,
+        message: String,
+    },
+    TagMsg {
+        client: String,
+        target: String,
+    },
     Quit {
         connection_id: Uuid,
-        client_host: Option<SocketAddr>,
-        nick: Option<String>,
-        user: Option<String>,
+        client: String,
         message: String,
     },
+    ErrNotRegistered {
+        server_host: String,
+        nick: String,
+    },
     ErrNeedMoreParams {
         server_host: String,
         nick: String,
         command: String,
     },
+    ErrAlreadyRegistered {
+        server_host: String,
+        nick: String,
+    },
+    ErrNoSuchNick {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
+    ErrNoRecipient {
+        server_host: String,
+        nick: String,
+        command: String,
+    },
+    ErrNoTextToSend {
+        server_host: String,
+        nick: String,
+    },
+    ErrInputTooLong {
+        server_host: String,
+        nick: String,
+    },
+    ErrInvalidCapCmd {
+        server_host: String,
+        nick: String,
+        subcommand: String,
+    },
+    ErrWasNoSuchNick {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
     ErrNoNickGiven {
         server_host: String,
     },
+    ErrErroneusNickname {
+        server_host: String,
+        nick: String,
+        attempted_nick: String,
+    },
+    ErrNicknameInUse {
+        server_host: String,
+        nick: String,
+        attempted_nick: String,
+    },
     ErrNoSuchChannel {
         server_host: String,
+        nick: String,
         channel: String,
     },
     ErrNotOnChannel {
         server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrCannotSendToChan {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrChannelIsFull {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrInviteOnlyChan {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    YoureOper {
+        server_host: String,
+        nick: String,
+    },
+    Rehashing {
+        server_host: String,
+        nick: String,
+        config_file: String,
+    },
+    ErrNoPrivileges {
+        server_host: String,
+        nick: String,
+    },
+    ErrPasswdMismatch {
+        server_host: String,
+        nick: String,
+    },
+    ErrNoOperHost {
+        server_host: String,
+        nick: String,
+    },
+    ErrNoPrivs {
+        server_host: String,
+        nick: String,
+        privilege: String,
+    },
+    ErrYoureBannedCreep {
+        server_host: String,
+        nick: String,
+        reason: String,
+    },
+    StatsKLine {
+        server_host: String,
+        nick: String,
+        host: String,
+        user: String,
+        reason: String,
+    },
+    StatsDLine {
+        server_host: String,
+        nick: String,
+        ip: String,
+        reason: String,
+    },
+    EndOfStats {
+        server_host: String,
+        nick: String,
+        query: String,
+    },
+    ServerNotice {
+        server_host: String,
+        nick: String,
+        message: String,
+    },
+    // the last thing sent before the server closes a connection
+    Error {
+        message: String,
+    },
+    ErrTooManyTargets {
+        server_host: String,
+        nick: String,
+        target: String,
+    },
+    ErrBadChanMask {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrBadChannelKey {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrBannedFromChan {
+        server_host: String,
+        nick: String,
+        channel: String,
+    },
+    ErrBanListFull {
+        server_host: String,
+        nick: String,
+        channel: String,
+        mode: char,
+    },
+    ErrUserOnChannel {
+        server_host: String,
+        nick: String,
+        target: String,
+        channel: String,
+    },
+    ErrUserNotInChannel {
+        server_host: String,
+        nick: String,
+        target: String,
+        channel: String,
+    },
+    ErrUnknownMode {
+        server_host: String,
+        nick: String,
+        mode: char,
+    },
+    ErrChanOPrivsNeeded {
+        server_host: String,
+        nick: String,
         channel: String,
     },
+    UModeIs {
+        server_host: String,
+        nick: String,
+        mode_string: String,
+    },
+    ErrUModeUnknownFlag {
+        server_host: String,
+        nick: String,
+    },
+    ErrUsersDontMatch {
+        server_host: String,
+        nick: String,
+    },
 }
 
-impl Display for Reply {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Reply::Welcome { server_host, nick } => {
-                write!(
-                    f,
-                    ":{} 001 {} :Welcome to the server {}",
-                    server_host, nick, nick
-                )
-            }
-            // TODO should this show client host too?
-            Reply::YourHost {
+impl Reply {
+    // a large channel won't fit on one RPL_NAMREPLY, so the users are
+    // spread over as many as it takes to keep each under the line limit
+    pub fn names(
+        server_host: &str,
+        nick: &str,
+        symbol: char,
+        channel: &str,
+        channel_users: Vec<String>,
+    ) -> Vec<Reply> {
+        // ":host 353 nick = #channel :" and the CRLF
+        let overhead = server_host.len() + nick.len() + channel.len() + 13;
+
+        let build = |channel_users| Reply::Nam {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+            symbol,
+            channel: channel.to_string(),
+            channel_users,
+        };
+
+        let mut replies = vec![];
+        let mut line = vec![];
+        let mut line_len = overhead;
+
+        for user in channel_users {
+            if !line.is_empty() && line_len + user.len() + 1 > MAX_LINE_LEN {
+                replies.push(build(std::mem::take(&mut line)));
+                line_len = overhead;
+            }
+
+            line_len += user.len() + 1;
+            line.push(user);
+        }
+
+        if !line.is_empty() {
+            replies.push(build(line));
+        }
+
+        replies
+    }
+
+    // ISUPPORT tokens are spread over as many RPL_ISUPPORT as it takes to
+    // keep each under both the line limit and the limit on parameters
+    pub fn support(server_host: &str, nick: &str, tokens: Vec<String>) -> Vec<Reply> {
+        // ":host 005 nick :are supported by this server" and the CRLF
+        let overhead = server_host.len() + nick.len() + 38;
+        // the nick and the trailing text take up two of the fifteen
+        let max_tokens = 13;
+
+        let build = |tokens| Reply::Support {
+            server_host: server_host.to_string(),
+            nick: nick.to_string(),
+            tokens,
+        };
+
+        let mut replies = vec![];
+        let mut line = vec![];
+        let mut line_len = overhead;
+
+        for token in tokens {
+            let full = line_len + token.len() + 1 > MAX_LINE_LEN || line.len() == max_tokens;
+
+            if !line.is_empty() && full {
+                replies.push(build(std::mem::take(&mut line)));
+                line_len = overhead;
+            }
+
+            line_len += token.len() + 1;
+            line.push(token);
+        }
+
+        if !line.is_empty() {
+            replies.push(build(line));
+        }
+
+        replies
+    }
+
+    pub fn with_tags(self, tags: Tags) -> Reply {
+        if tags.is_empty() {
+            return self;
+        }
+
+        match self {
+            Reply::Tagged {
+                tags: mut existing,
+                reply,
+            } => {
+                existing.extend(tags);
+                Reply::Tagged {
+                    tags: existing,
+                    reply,
+                }
+            }
+            reply => Reply::Tagged {
+                tags,
+                reply: Box::new(reply),
+            },
+        }
+    }
+
+    // Drops any tags the recipient can't receive, unwrapping the
+    // reply entirely if none are left
+    pub fn retain_tags<F>(self, mut keep: F) -> Reply
+    where
+        F: FnMut(&str) -> bool,
+    {
+        match self {
+            Reply::Tagged { mut tags, reply } => {
+                tags.retain(|key, _| keep(key));
+                reply.with_tags(tags)
+            }
+            reply => reply,
+        }
+    }
+}
+
+impl Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reply::Tagged { tags, reply } => match tags.is_empty() {
+                true => write!(f, "{}", reply),
+                false => write!(f, "@{} {}", serialize_tags(tags), reply),
+            },
+            Reply::Welcome { server_host, nick } => {
+                write!(
+                    f,
+                    ":{} 001 {} :Welcome to the server {}",
+                    server_host, nick, nick
+                )
+            }
+            // TODO should this show client host too?
+            Reply::YourHost {
                 server_host,
                 nick,
                 version,
@@ -224,23 +720,32 @@ impl Display for Reply {
                 version,
                 user_modes,
                 channel_modes,
+                channel_modes_with_argument,
             } => {
                 write!(
                     f,
-                    ":{} 004 {} {} {} {} {}",
-                    server_host, nick, server_host, version, user_modes, channel_modes
+                    ":{} 004 {} {} {} {} {} {}",
+                    server_host,
+                    nick,
+                    server_host,
+                    version,
+                    user_modes,
+                    channel_modes,
+                    channel_modes_with_argument
                 )
             }
             Reply::Support {
                 server_host,
                 nick,
-                channel_len,
+                tokens,
             } => write!(
                 f,
-                ":{} 005 {} CHANNELLEN={} :are supported by this server",
-                server_host, nick, channel_len
+                ":{} 005 {} {} :are supported by this server",
+                server_host,
+                nick,
+                tokens.join(" ")
             ),
-            Reply::StatsDLine {
+            Reply::StatsConn {
                 server_host,
                 nick,
                 connections,
@@ -342,10 +847,140 @@ impl Display for Reply {
             } => {
                 write!(
                     f,
-                    ":{} 324 {} {} {} {}",
-                    server_host, nick, channel, mode_string, mode_arguments
-                )
+                    ":{} 324 {} {} {}",
+                    server_host, nick, channel, mode_string
+                )?;
+
+                for argument in mode_arguments {
+                    write!(f, " {}", argument)?;
+                }
+
+                Ok(())
+            }
+            Reply::WhoSpc {
+                server_host,
+                nick,
+                fields,
+                real_name,
+            } => {
+                write!(f, ":{} 354 {}", server_host, nick)?;
+
+                for field in fields {
+                    write!(f, " {}", field)?;
+                }
+
+                // the real name can have spaces so is always last
+                match real_name {
+                    Some(r) => write!(f, " :{}", r),
+                    None => Ok(()),
+                }
             }
+            Reply::Away {
+                server_host,
+                nick,
+                target,
+                message,
+            } => write!(f, ":{} 301 {} {} :{}", server_host, nick, target, message),
+            Reply::UnAway { server_host, nick } => write!(
+                f,
+                ":{} 305 {} :You are no longer marked as being away",
+                server_host, nick
+            ),
+            Reply::NowAway { server_host, nick } => write!(
+                f,
+                ":{} 306 {} :You have been marked as being away",
+                server_host, nick
+            ),
+            Reply::AwayNotify { client, message } => match message {
+                Some(m) => write!(f, ":{} AWAY :{}", client, m),
+                None => write!(f, ":{} AWAY", client),
+            },
+            Reply::WhoisUser {
+                server_host,
+                nick,
+                target,
+                user,
+                host,
+                real_name,
+            } => write!(
+                f,
+                ":{} 311 {} {} {} {} * :{}",
+                server_host, nick, target, user, host, real_name
+            ),
+            Reply::WhoisServer {
+                server_host,
+                nick,
+                target,
+                server,
+                server_info,
+            } => write!(
+                f,
+                ":{} 312 {} {} {} :{}",
+                server_host, nick, target, server, server_info
+            ),
+            Reply::WhoisOperator {
+                server_host,
+                nick,
+                target,
+            } => write!(
+                f,
+                ":{} 313 {} {} :is an IRC operator",
+                server_host, nick, target
+            ),
+            Reply::WhoisIdle {
+                server_host,
+                nick,
+                target,
+                idle_secs,
+                signon_time,
+            } => write!(
+                f,
+                ":{} 317 {} {} {} {} :seconds idle, signon time",
+                server_host,
+                nick,
+                target,
+                idle_secs,
+                signon_time.timestamp()
+            ),
+            Reply::WhoisChannels {
+                server_host,
+                nick,
+                target,
+                channels,
+            } => write!(
+                f,
+                ":{} 319 {} {} :{}",
+                server_host,
+                nick,
+                target,
+                channels.join(" ")
+            ),
+            Reply::EndOfWhois {
+                server_host,
+                nick,
+                target,
+            } => write!(
+                f,
+                ":{} 318 {} {} :End of /WHOIS list",
+                server_host, nick, target
+            ),
+            Reply::WhowasUser {
+                server_host,
+                nick,
+                target,
+                user,
+                host,
+                real_name,
+            } => write!(
+                f,
+                ":{} 314 {} {} {} {} * :{}",
+                server_host, nick, target, user, host, real_name
+            ),
+            Reply::EndOfWhowas {
+                server_host,
+                nick,
+                target,
+            } => write!(f, ":{} 369 {} {} :End of WHOWAS", server_host, nick, target),
             Reply::CreationTime {
                 server_host,
                 nick,
@@ -354,7 +989,120 @@ impl Display for Reply {
             } => write!(
                 f,
                 ":{} 329 {} {} {}",
-                server_host, nick, channel, created_at
+                server_host,
+                nick,
+                channel,
+                created_at.timestamp()
+            ),
+            Reply::InviteList {
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at,
+            } => write!(
+                f,
+                ":{} 346 {} {} {} {} {}",
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at.timestamp()
+            ),
+            Reply::EndOfInviteList {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 347 {} {} :End of channel invite list",
+                server_host, nick, channel
+            ),
+            Reply::ExceptList {
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at,
+            } => write!(
+                f,
+                ":{} 348 {} {} {} {} {}",
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at.timestamp()
+            ),
+            Reply::EndOfExceptList {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 349 {} {} :End of channel exception list",
+                server_host, nick, channel
+            ),
+            Reply::BanList {
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at,
+            } => write!(
+                f,
+                ":{} 367 {} {} {} {} {}",
+                server_host,
+                nick,
+                channel,
+                mask,
+                set_by,
+                set_at.timestamp()
+            ),
+            Reply::EndOfBanList {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 368 {} {} :End of channel ban list",
+                server_host, nick, channel
+            ),
+            Reply::Inviting {
+                server_host,
+                nick,
+                target,
+                channel,
+            } => write!(f, ":{} 341 {} {} {}", server_host, nick, target, channel),
+            Reply::ListStart { server_host, nick } => {
+                write!(f, ":{} 321 {} Channel :Users  Name", server_host, nick)
+            }
+            Reply::List {
+                server_host,
+                nick,
+                channel,
+                visible_users,
+                topic,
+            } => write!(
+                f,
+                ":{} 322 {} {} {} :{}",
+                server_host, nick, channel, visible_users, topic
+            ),
+            Reply::ListEnd { server_host, nick } => {
+                write!(f, ":{} 323 {} :End of /LIST", server_host, nick)
+            }
+            Reply::NoTopic {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 331 {} {} :No topic is set",
+                server_host, nick, channel
             ),
             Reply::Topic {
                 server_host,
@@ -362,14 +1110,22 @@ impl Display for Reply {
                 channel,
                 topic,
             } => write!(f, ":{} 332 {} {} :{}", server_host, nick, channel, topic),
-            // TODO print set_at as UNIX time??
             Reply::TopicWhoTime {
                 server_host,
                 channel,
                 nick,
+                set_by,
                 set_at,
-            } => write!(f, ":{} 333 {} {} {}", server_host, nick, channel, set_at),
-            // TODO remove hard-coding
+            } => write!(
+                f,
+                ":{} 333 {} {} {} {}",
+                server_host,
+                nick,
+                channel,
+                set_by,
+                set_at.timestamp()
+            ),
+            // the hopcount is always 0, every user is on this server
             Reply::Who {
                 server_host,
                 nick,
@@ -378,11 +1134,12 @@ impl Display for Reply {
                 other_host,
                 other_server,
                 other_nick,
+                flags,
                 other_realname,
             } => {
                 write!(
                     f,
-                    ":{} 352 {} {} {} {} {} {} H@ :0 {}",
+                    ":{} 352 {} {} {} {} {} {} {} :0 {}",
                     server_host,
                     nick,
                     channel,
@@ -390,33 +1147,25 @@ impl Display for Reply {
                     other_host,
                     other_server,
                     other_nick,
+                    flags,
                     other_realname
                 )
             }
             Reply::Nam {
                 server_host,
                 nick,
+                symbol,
                 channel,
                 channel_users,
-            } => {
-                let mut printed_users = String::new();
-                let mut first = true;
-
-                for user in channel_users {
-                    if first {
-                        first = false;
-                        printed_users.push_str(user);
-                    } else {
-                        printed_users.push_str(&format!(" {}", user));
-                    }
-                }
-
-                write!(
-                    f,
-                    ":{} 353 {} = {} {}",
-                    server_host, nick, channel, printed_users
-                )
-            }
+            } => write!(
+                f,
+                ":{} 353 {} {} {} :{}",
+                server_host,
+                nick,
+                symbol,
+                channel,
+                channel_users.join(" ")
+            ),
             Reply::EndOfNames {
                 server_host,
                 nick,
@@ -442,56 +1191,73 @@ impl Display for Reply {
                 write!(f, ":{} 376 {} :End of /MOTD command.", server_host, nick)
             }
             Reply::Ping { server_host } => write!(f, ":{} PING", server_host),
+            Reply::Cap {
+                server_host,
+                nick,
+                subcommand,
+                capabilities,
+            } => write!(
+                f,
+                ":{} CAP {} {} :{}",
+                server_host, nick, subcommand, capabilities
+            ),
             Reply::Pong { server_host, token } => {
                 write!(f, ":{} PONG {} :{}", server_host, server_host, token)
             }
             Reply::Join { client, channel } => write!(f, ":{} JOIN :{}", client, channel),
             Reply::Part { client, channel } => write!(f, ":{} PART {}", client, channel),
-            Reply::PrivMsg {
-                client_host,
-                nick,
-                user,
+            Reply::Invite {
+                client,
+                target,
                 channel,
-                message,
+            } => write!(f, ":{} INVITE {} {}", client, target, channel),
+            Reply::Kick {
+                client,
+                channel,
+                target,
+                reason,
+            } => write!(f, ":{} KICK {} {} :{}", client, channel, target, reason),
+            Reply::Mode {
+                client,
+                target,
+                mode_string,
+                mode_arguments,
             } => {
-                // TODO this isnt strictly quite right
-                let mut prefix = format!("");
-                if let Some(n) = nick {
-                    prefix.push_str(&format!(":{}", &n.to_string()));
-
-                    if let Some(u) = user {
-                        prefix.push_str(&format!("!{}", u))
-                    }
-
-                    if let Some(ch) = client_host {
-                        prefix.push_str(&format!("@{}", &ch.to_string()))
-                    }
+                write!(f, ":{} MODE {} {}", client, target, mode_string)?;
+
+                for argument in mode_arguments {
+                    write!(f, " {}", argument)?;
                 }
 
-                write!(f, "{} PRIVMSG {} :{}", prefix, channel, message)
+                Ok(())
             }
+            Reply::TopicChange {
+                client,
+                channel,
+                topic,
+            } => write!(f, ":{} TOPIC {} :{}", client, channel, topic),
+            Reply::Nick { client, nick } => write!(f, ":{} NICK {}", client, nick),
+            Reply::PrivMsg {
+                client,
+                target,
+                message,
+            } => write!(f, ":{} PRIVMSG {} :{}", client, target, message),
+            Reply::Notice {
+                client,
+                target,
+                message,
+            } => write!(f, ":{} NOTICE {} :{}", client, target, message),
+            Reply::TagMsg { client, target } => write!(f, ":{} TAGMSG {}", client, target),
             Reply::Quit {
                 connection_id: _,
-                nick,
-                user,
-                client_host,
+                client,
                 message,
-            } => {
-                // TODO this isnt strictly quite right
-                let mut prefix = format!("");
-                if let Some(n) = nick {
-                    prefix.push_str(&format!(":{}", &n.to_string()));
-
-                    if let Some(u) = user {
-                        prefix.push_str(&format!("!{}", u))
-                    }
-
-                    if let Some(ch) = client_host {
-                        prefix.push_str(&format!("@{}", &ch.to_string()))
-                    }
-                }
-
-                write!(f, "{} QUIT :{}", prefix, message)
+            } => write!(f, ":{} QUIT :{}", client, message),
+            Reply::ErrNotRegistered { server_host, nick } => {
+                write!(f, ":{} 451 {} :You have not registered", server_host, nick)
+            }
+            Reply::ErrAlreadyRegistered { server_host, nick } => {
+                write!(f, ":{} 462 {} :You may not reregister", server_host, nick)
             }
             Reply::ErrNeedMoreParams {
                 server_host,
@@ -504,25 +1270,286 @@ impl Display for Reply {
                     server_host, nick, command
                 )
             }
-            Reply::ErrNoNickGiven { server_host } => {
-                write!(f, ":{} 431 :No nickname given", server_host)
-            }
-            Reply::ErrNoSuchChannel {
+            Reply::ErrNoSuchNick {
                 server_host,
-                channel,
-            } => {
-                write!(f, ":{} 403 {} :No such channel", server_host, channel)
-            }
-            Reply::ErrNotOnChannel {
+                nick,
+                target,
+            } => write!(
+                f,
+                ":{} 401 {} {} :No such nick/channel",
+                server_host, nick, target
+            ),
+            Reply::ErrNoRecipient {
                 server_host,
-                channel,
-            } => {
-                write!(
-                    f,
-                    ":{} 442 {} :You're not on that channel",
-                    server_host, channel
-                )
+                nick,
+                command,
+            } => write!(
+                f,
+                ":{} 411 {} :No recipient given ({})",
+                server_host, nick, command
+            ),
+            Reply::ErrNoTextToSend { server_host, nick } => {
+                write!(f, ":{} 412 {} :No text to send", server_host, nick)
+            }
+            Reply::ErrInputTooLong { server_host, nick } => {
+                write!(f, ":{} 417 {} :Input line was too long", server_host, nick)
+            }
+            Reply::ErrInvalidCapCmd {
+                server_host,
+                nick,
+                subcommand,
+            } => write!(
+                f,
+                ":{} 410 {} {} :Invalid CAP command",
+                server_host, nick, subcommand
+            ),
+            Reply::ErrWasNoSuchNick {
+                server_host,
+                nick,
+                target,
+            } => write!(
+                f,
+                ":{} 406 {} {} :There was no such nickname",
+                server_host, nick, target
+            ),
+            Reply::ErrNoNickGiven { server_host } => {
+                write!(f, ":{} 431 :No nickname given", server_host)
+            }
+            Reply::ErrErroneusNickname {
+                server_host,
+                nick,
+                attempted_nick,
+            } => write!(
+                f,
+                ":{} 432 {} {} :Erroneous nickname",
+                server_host, nick, attempted_nick
+            ),
+            Reply::ErrNicknameInUse {
+                server_host,
+                nick,
+                attempted_nick,
+            } => write!(
+                f,
+                ":{} 433 {} {} :Nickname is already in use",
+                server_host, nick, attempted_nick
+            ),
+            Reply::ErrNoSuchChannel {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 403 {} {} :No such channel",
+                server_host, nick, channel
+            ),
+            Reply::ErrNotOnChannel {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 442 {} {} :You're not on that channel",
+                server_host, nick, channel
+            ),
+            Reply::ErrCannotSendToChan {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 404 {} {} :Cannot send to channel",
+                server_host, nick, channel
+            ),
+            Reply::ErrChannelIsFull {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 471 {} {} :Cannot join channel (+l)",
+                server_host, nick, channel
+            ),
+            Reply::ErrInviteOnlyChan {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 473 {} {} :Cannot join channel (+i)",
+                server_host, nick, channel
+            ),
+            Reply::YoureOper { server_host, nick } => write!(
+                f,
+                ":{} 381 {} :You are now an IRC operator",
+                server_host, nick
+            ),
+            Reply::Rehashing {
+                server_host,
+                nick,
+                config_file,
+            } => write!(
+                f,
+                ":{} 382 {} {} :Rehashing",
+                server_host, nick, config_file
+            ),
+            Reply::ErrNoPrivileges { server_host, nick } => write!(
+                f,
+                ":{} 481 {} :Permission Denied- You're not an IRC operator",
+                server_host, nick
+            ),
+            Reply::ErrPasswdMismatch { server_host, nick } => {
+                write!(f, ":{} 464 {} :Password incorrect", server_host, nick)
+            }
+            Reply::ErrNoOperHost { server_host, nick } => {
+                write!(f, ":{} 491 {} :No O-lines for your host", server_host, nick)
+            }
+            Reply::ErrNoPrivs {
+                server_host,
+                nick,
+                privilege,
+            } => write!(
+                f,
+                ":{} 723 {} {} :Insufficient oper privileges.",
+                server_host, nick, privilege
+            ),
+            Reply::ErrYoureBannedCreep {
+                server_host,
+                nick,
+                reason,
+            } => write!(
+                f,
+                ":{} 465 {} :You are banned from this server- {}",
+                server_host, nick, reason
+            ),
+            Reply::StatsKLine {
+                server_host,
+                nick,
+                host,
+                user,
+                reason,
+            } => write!(
+                f,
+                ":{} 216 {} K {} * {} :{}",
+                server_host, nick, host, user, reason
+            ),
+            Reply::StatsDLine {
+                server_host,
+                nick,
+                ip,
+                reason,
+            } => write!(f, ":{} 225 {} D {} :{}", server_host, nick, ip, reason),
+            Reply::EndOfStats {
+                server_host,
+                nick,
+                query,
+            } => write!(
+                f,
+                ":{} 219 {} {} :End of /STATS report",
+                server_host, nick, query
+            ),
+            Reply::ServerNotice {
+                server_host,
+                nick,
+                message,
+            } => write!(f, ":{} NOTICE {} :{}", server_host, nick, message),
+            Reply::Error { message } => write!(f, "ERROR :{}", message),
+            Reply::ErrTooManyTargets {
+                server_host,
+                nick,
+                target,
+            } => write!(
+                f,
+                ":{} 407 {} {} :Too many recipients",
+                server_host, nick, target
+            ),
+            Reply::ErrBadChanMask {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 476 {} {} :Bad Channel Mask",
+                server_host, nick, channel
+            ),
+            Reply::ErrBadChannelKey {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 475 {} {} :Cannot join channel (+k)",
+                server_host, nick, channel
+            ),
+            Reply::ErrBannedFromChan {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 474 {} {} :Cannot join channel (+b)",
+                server_host, nick, channel
+            ),
+            Reply::ErrBanListFull {
+                server_host,
+                nick,
+                channel,
+                mode,
+            } => write!(
+                f,
+                ":{} 478 {} {} {} :Channel list is full",
+                server_host, nick, channel, mode
+            ),
+            Reply::ErrUserOnChannel {
+                server_host,
+                nick,
+                target,
+                channel,
+            } => write!(
+                f,
+                ":{} 443 {} {} {} :is already on channel",
+                server_host, nick, target, channel
+            ),
+            Reply::ErrUserNotInChannel {
+                server_host,
+                nick,
+                target,
+                channel,
+            } => write!(
+                f,
+                ":{} 441 {} {} {} :They aren't on that channel",
+                server_host, nick, target, channel
+            ),
+            Reply::ErrUnknownMode {
+                server_host,
+                nick,
+                mode,
+            } => write!(
+                f,
+                ":{} 472 {} {} :is unknown mode char to me",
+                server_host, nick, mode
+            ),
+            Reply::ErrChanOPrivsNeeded {
+                server_host,
+                nick,
+                channel,
+            } => write!(
+                f,
+                ":{} 482 {} {} :You're not channel operator",
+                server_host, nick, channel
+            ),
+            Reply::UModeIs {
+                server_host,
+                nick,
+                mode_string,
+            } => write!(f, ":{} 221 {} {}", server_host, nick, mode_string),
+            Reply::ErrUModeUnknownFlag { server_host, nick } => {
+                write!(f, ":{} 501 {} :Unknown MODE flag", server_host, nick)
             }
+            Reply::ErrUsersDontMatch { server_host, nick } => write!(
+                f,
+                ":{} 502 {} :Can't change mode for other users",
+                server_host, nick
+            ),
         }
     }
 }
@@ -556,7 +1583,7 @@ fn created_prints_correctly() {
     let reply = Reply::Created {
         server_host: "localhost".to_string(),
         nick: "JIM".to_string(),
-        created_at: now.clone(),
+        created_at: now,
     };
     let actual = reply.to_string();
     let expected = format!(":localhost 003 JIM :This server was created {}", now);
@@ -569,11 +1596,12 @@ fn myinfo_prints_correctly() {
         server_host: "localhost".to_string(),
         nick: "JIM".to_string(),
         version: "0.0.1".to_string(),
-        user_modes: "r".to_string(),
-        channel_modes: "i".to_string(),
+        user_modes: "o".to_string(),
+        channel_modes: "Ibeiklmnopstv".to_string(),
+        channel_modes_with_argument: "Ibeklov".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 004 JIM localhost 0.0.1 r i");
+    let expected = ":localhost 004 JIM localhost 0.0.1 o Ibeiklmnopstv Ibeklov".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -582,10 +1610,16 @@ fn support_prints_correctly() {
     let reply = Reply::Support {
         server_host: "localhost".to_string(),
         nick: "JIM".to_string(),
-        channel_len: 100,
+        tokens: vec![
+            "CHANNELLEN=100".to_string(),
+            "CHANMODES=beI,k,l,imnpst".to_string(),
+            "WHOX".to_string(),
+        ],
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 005 JIM CHANNELLEN=100 :are supported by this server");
+    let expected =
+        ":localhost 005 JIM CHANNELLEN=100 CHANMODES=beI,k,l,imnpst WHOX :are supported by this server"
+            .to_string();
     assert_eq!(expected, actual);
 }
 
@@ -599,7 +1633,8 @@ fn luserclient_prints_correctly() {
         servers: 1,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 251 JIM :There are 100 users and 20 invisible on 1 servers");
+    let expected =
+        ":localhost 251 JIM :There are 100 users and 20 invisible on 1 servers".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -611,7 +1646,7 @@ fn luserop_prints_correctly() {
         operators: 1337,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 252 JIM 1337 :IRC Operators online");
+    let expected = ":localhost 252 JIM 1337 :IRC Operators online".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -623,7 +1658,7 @@ fn luserunknown_prints_correctly() {
         unknown: 7,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 253 JIM 7 :unknown connection(s)");
+    let expected = ":localhost 253 JIM 7 :unknown connection(s)".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -635,7 +1670,7 @@ fn luserchannels_prints_correctly() {
         channels: 9999,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 254 JIM 9999 :channels formed");
+    let expected = ":localhost 254 JIM 9999 :channels formed".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -648,7 +1683,7 @@ fn luserme_prints_correctly() {
         servers: 1,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 255 JIM :I have 900 clients and 1 servers");
+    let expected = ":localhost 255 JIM :I have 900 clients and 1 servers".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -661,7 +1696,7 @@ fn localusers_prints_correctly() {
         max: 1000,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 265 JIM 845 1000 :Current local users 845, max 1000");
+    let expected = ":localhost 265 JIM 845 1000 :Current local users 845, max 1000".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -674,13 +1709,14 @@ fn globalusers_prints_correctly() {
         max: 23455,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 266 JIM 9823 23455 :Current global users 9823, max 23455");
+    let expected =
+        ":localhost 266 JIM 9823 23455 :Current global users 9823, max 23455".to_string();
     assert_eq!(expected, actual);
 }
 
 #[test]
-fn statsdline_prints_correctly() {
-    let reply = Reply::StatsDLine {
+fn statsconn_prints_correctly() {
+    let reply = Reply::StatsConn {
         server_host: "localhost".to_string(),
         nick: "JIM".to_string(),
         connections: 9998,
@@ -688,7 +1724,7 @@ fn statsdline_prints_correctly() {
         received: 99999,
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 250 JIM :Highest connection count: 9998 (9000 clients) (99999 connections received)");
+    let expected = ":localhost 250 JIM :Highest connection count: 9998 (9000 clients) (99999 connections received)".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -699,7 +1735,7 @@ fn motdstart_prints_correctly() {
         nick: "JIM".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 375 JIM :- localhost Message of the Day -");
+    let expected = ":localhost 375 JIM :- localhost Message of the Day -".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -710,7 +1746,7 @@ fn endofmotd_prints_correctly() {
         nick: "JIM".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 376 JIM :End of /MOTD command.");
+    let expected = ":localhost 376 JIM :End of /MOTD command.".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -722,7 +1758,7 @@ fn motd_prints_correctly() {
         line: "Foobar".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 372 JIM :- Foobar");
+    let expected = ":localhost 372 JIM :- Foobar".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -733,7 +1769,7 @@ fn pong_prints_correctly() {
         token: "LAG1238948394".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost PONG localhost :LAG1238948394");
+    let expected = ":localhost PONG localhost :LAG1238948394".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -745,7 +1781,7 @@ fn endofnames_prints_correctly() {
         channel: "#foobar".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 366 JIM #foobar :End of /NAMES list.");
+    let expected = ":localhost 366 JIM #foobar :End of /NAMES list.".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -757,7 +1793,7 @@ fn endofwho_prints_correctly() {
         mask: "#foobar".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 315 JIM #foobar :End of /WHO list.");
+    let expected = ":localhost 315 JIM #foobar :End of /WHO list.".to_string();
     assert_eq!(expected, actual);
 }
 
@@ -770,6 +1806,826 @@ fn topic_prints_correctly() {
         topic: "hELLO WORLD".to_string(),
     };
     let actual = reply.to_string();
-    let expected = format!(":localhost 332 JIM #foobar :hELLO WORLD");
+    let expected = ":localhost 332 JIM #foobar :hELLO WORLD".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn notopic_prints_correctly() {
+    let reply = Reply::NoTopic {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foobar".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 331 JIM #foobar :No topic is set".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn topicwhotime_prints_correctly() {
+    use chrono::TimeZone;
+
+    let set_at = Utc.timestamp(1634000000, 0);
+    let reply = Reply::TopicWhoTime {
+        server_host: "localhost".to_string(),
+        channel: "#foobar".to_string(),
+        nick: "JIM".to_string(),
+        set_by: "BOB!~bob@localhost".to_string(),
+        set_at,
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 333 JIM #foobar BOB!~bob@localhost 1634000000".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn topicchange_prints_correctly() {
+    let reply = Reply::TopicChange {
+        client: "JIM!~jim@localhost".to_string(),
+        channel: "#foobar".to_string(),
+        topic: "hELLO WORLD".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost TOPIC #foobar :hELLO WORLD".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn notice_prints_correctly() {
+    let reply = Reply::Notice {
+        client: "JIM!~jim@localhost".to_string(),
+        target: "BOB".to_string(),
+        message: "hi there".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost NOTICE BOB :hi there".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn privmsg_prints_correctly() {
+    let reply = Reply::PrivMsg {
+        client: "JIM!~jim@localhost".to_string(),
+        target: "#foobar".to_string(),
+        message: "hi there".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost PRIVMSG #foobar :hi there".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn quit_prints_correctly() {
+    let reply = Reply::Quit {
+        connection_id: Uuid::new_v4(),
+        client: "JIM!~jim@localhost".to_string(),
+        message: "bye".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost QUIT :bye".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnosuchnick_prints_correctly() {
+    let reply = Reply::ErrNoSuchNick {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 401 JIM BOB :No such nick/channel".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnosuchchannel_prints_correctly() {
+    let reply = Reply::ErrNoSuchChannel {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foobar".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 403 JIM #foobar :No such channel".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnotonchannel_prints_correctly() {
+    let reply = Reply::ErrNotOnChannel {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foobar".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 442 JIM #foobar :You're not on that channel".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn nick_prints_correctly() {
+    let reply = Reply::Nick {
+        client: "JIM!~jim@localhost".to_string(),
+        nick: "JIMMY".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost NICK JIMMY".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnicknameinuse_prints_correctly() {
+    let reply = Reply::ErrNicknameInUse {
+        server_host: "localhost".to_string(),
+        nick: "*".to_string(),
+        attempted_nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 433 * JIM :Nickname is already in use".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnotregistered_prints_correctly() {
+    let reply = Reply::ErrNotRegistered {
+        server_host: "localhost".to_string(),
+        nick: "*".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 451 * :You have not registered".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn cap_prints_correctly() {
+    let reply = Reply::Cap {
+        server_host: "localhost".to_string(),
+        nick: "*".to_string(),
+        subcommand: "LS".to_string(),
+        capabilities: "echo-message".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost CAP * LS :echo-message".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn tagged_prints_correctly() {
+    let mut tags = Tags::new();
+    tags.insert("+typing".to_string(), "active".to_string());
+    tags.insert("+example.com/note".to_string(), "a b;c".to_string());
+
+    let reply = Reply::TagMsg {
+        client: "JIM!~jim@localhost".to_string(),
+        target: "#foobar".to_string(),
+    }
+    .with_tags(tags);
+    let actual = reply.to_string();
+    let expected = "@+example.com/note=a\\sb\\:c;+typing=active :JIM!~jim@localhost TAGMSG #foobar"
+        .to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn retain_tags_unwraps_tagged_reply_when_empty() {
+    let mut tags = Tags::new();
+    tags.insert("+typing".to_string(), "active".to_string());
+
+    let reply = Reply::Ping {
+        server_host: "localhost".to_string(),
+    };
+
+    assert_eq!(
+        ":localhost PING",
+        reply.with_tags(tags).retain_tags(|_| false).to_string()
+    );
+}
+
+#[test]
+fn retain_tags_keeps_matching_tags() {
+    let mut tags = Tags::new();
+    tags.insert("time".to_string(), "2021-10-12T14:00:00.000Z".to_string());
+    tags.insert("msgid".to_string(), "abc".to_string());
+
+    let reply = Reply::Join {
+        client: "JIM!~jim@localhost".to_string(),
+        channel: "#foobar".to_string(),
+    };
+
+    assert_eq!(
+        "@time=2021-10-12T14:00:00.000Z :JIM!~jim@localhost JOIN :#foobar",
+        reply
+            .with_tags(tags)
+            .retain_tags(|key| key == "time")
+            .to_string()
+    );
+}
+
+#[test]
+fn errinputtoolong_prints_correctly() {
+    let reply = Reply::ErrInputTooLong {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 417 JIM :Input line was too long".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn support_splits_tokens_across_lines() {
+    let tokens = (0..20)
+        .map(|i| format!("TOKEN{}", i))
+        .collect::<Vec<String>>();
+
+    let replies = Reply::support("localhost", "JIM", tokens);
+
+    assert_eq!(2, replies.len());
+
+    match replies.as_slice() {
+        [Reply::Support { tokens: first, .. }, Reply::Support { tokens: second, .. }] => {
+            assert_eq!(13, first.len());
+            assert_eq!(7, second.len());
+        }
+        r => panic!("Unexpected replies {:?}", r),
+    }
+
+    let long_tokens = (0..10)
+        .map(|i| format!("TOKEN{}={}", i, "x".repeat(100)))
+        .collect::<Vec<String>>();
+
+    for reply in Reply::support("localhost", "JIM", long_tokens) {
+        assert!(reply.to_string().len() + 2 <= MAX_LINE_LEN);
+    }
+}
+
+#[test]
+fn mode_prints_correctly() {
+    let reply = Reply::Mode {
+        client: "JIM!~jim@localhost".to_string(),
+        target: "#foo".to_string(),
+        mode_string: "+o-v".to_string(),
+        mode_arguments: vec!["BOB".to_string(), "TOM".to_string()],
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost MODE #foo +o-v BOB TOM".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errchanoprivsneeded_prints_correctly() {
+    let reply = Reply::ErrChanOPrivsNeeded {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 482 JIM #foo :You're not channel operator".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn channelmodeis_prints_correctly() {
+    let reply = Reply::ChannelModeIs {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+        mode_string: "+ntl".to_string(),
+        mode_arguments: vec!["10".to_string()],
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 324 JIM #foo +ntl 10".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn umodeis_prints_correctly() {
+    let reply = Reply::UModeIs {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        mode_string: "+o".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 221 JIM +o".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errumodeunknownflag_prints_correctly() {
+    let reply = Reply::ErrUModeUnknownFlag {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 501 JIM :Unknown MODE flag".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errusersdontmatch_prints_correctly() {
+    let reply = Reply::ErrUsersDontMatch {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 502 JIM :Can't change mode for other users".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn creationtime_prints_correctly() {
+    use chrono::TimeZone;
+
+    let reply = Reply::CreationTime {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+        created_at: Utc.timestamp(1_600_000_000, 0),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 329 JIM #foo 1600000000".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errbadchannelkey_prints_correctly() {
+    let reply = Reply::ErrBadChannelKey {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 475 JIM #foo :Cannot join channel (+k)".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn banlist_prints_correctly() {
+    use chrono::TimeZone;
+
+    let reply = Reply::BanList {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+        mask: "bob!*@*".to_string(),
+        set_by: "JIM!~jim@localhost".to_string(),
+        set_at: Utc.timestamp(1_600_000_000, 0),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 367 JIM #foo bob!*@* JIM!~jim@localhost 1600000000".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn endofexceptlist_prints_correctly() {
+    let reply = Reply::EndOfExceptList {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 349 JIM #foo :End of channel exception list".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn kick_prints_correctly() {
+    let reply = Reply::Kick {
+        client: "JIM!~jim@localhost".to_string(),
+        channel: "#foo".to_string(),
+        target: "BOB".to_string(),
+        reason: "go away".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost KICK #foo BOB :go away".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn inviting_prints_correctly() {
+    let reply = Reply::Inviting {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        channel: "#foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 341 JIM BOB #foo".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn invite_prints_correctly() {
+    let reply = Reply::Invite {
+        client: "JIM!~jim@localhost".to_string(),
+        target: "BOB".to_string(),
+        channel: "#foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":JIM!~jim@localhost INVITE BOB #foo".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn nam_prints_correctly() {
+    let reply = Reply::Nam {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        symbol: '@',
+        channel: "#foo".to_string(),
+        channel_users: vec!["@JIM".to_string(), "BOB".to_string()],
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 353 JIM @ #foo :@JIM BOB".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn names_splits_over_line_limit() {
+    let channel_users = (0..200)
+        .map(|i| format!("user{:05}", i))
+        .collect::<Vec<String>>();
+
+    let replies = Reply::names("localhost", "JIM", '=', "#foo", channel_users.clone());
+
+    assert!(replies.len() > 1);
+
+    let mut seen = vec![];
+
+    for reply in replies {
+        assert!(reply.to_string().len() + 2 <= MAX_LINE_LEN);
+
+        match reply {
+            Reply::Nam { channel_users, .. } => seen.extend(channel_users),
+            r => panic!("Unexpected reply {:?}", r),
+        }
+    }
+
+    assert_eq!(channel_users, seen);
+}
+
+#[test]
+fn list_prints_correctly() {
+    let reply = Reply::List {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "#foo".to_string(),
+        visible_users: 3,
+        topic: "hello world".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 322 JIM #foo 3 :hello world".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whoisuser_prints_correctly() {
+    let reply = Reply::WhoisUser {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        user: "bob".to_string(),
+        host: "127.0.0.1".to_string(),
+        real_name: "Bob Smith".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 311 JIM BOB bob 127.0.0.1 * :Bob Smith".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whoisserver_prints_correctly() {
+    let reply = Reply::WhoisServer {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        server: "localhost".to_string(),
+        server_info: "0.0.1".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 312 JIM BOB localhost :0.0.1".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whoisoperator_prints_correctly() {
+    let reply = Reply::WhoisOperator {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 313 JIM BOB :is an IRC operator".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whoisidle_prints_correctly() {
+    let now = Utc::now();
+    let reply = Reply::WhoisIdle {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        idle_secs: 42,
+        signon_time: now,
+    };
+    let actual = reply.to_string();
+    let expected = format!(
+        ":localhost 317 JIM BOB 42 {} :seconds idle, signon time",
+        now.timestamp()
+    );
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whoischannels_prints_correctly() {
+    let reply = Reply::WhoisChannels {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        channels: vec!["@#foo".to_string(), "#bar".to_string()],
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 319 JIM BOB :@#foo #bar".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn endofwhois_prints_correctly() {
+    let reply = Reply::EndOfWhois {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 318 JIM BOB :End of /WHOIS list".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whowasuser_prints_correctly() {
+    let reply = Reply::WhowasUser {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        user: "bob".to_string(),
+        host: "127.0.0.1".to_string(),
+        real_name: "Bob Smith".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 314 JIM BOB bob 127.0.0.1 * :Bob Smith".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn endofwhowas_prints_correctly() {
+    let reply = Reply::EndOfWhowas {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 369 JIM BOB :End of WHOWAS".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errwasnosuchnick_prints_correctly() {
+    let reply = Reply::ErrWasNoSuchNick {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 406 JIM BOB :There was no such nickname".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn whospc_prints_correctly() {
+    let reply = Reply::WhoSpc {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        fields: vec!["42".to_string(), "BOB".to_string(), "H".to_string()],
+        real_name: Some("Bob Smith".to_string()),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 354 JIM 42 BOB H :Bob Smith".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn away_prints_correctly() {
+    let reply = Reply::Away {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+        message: "gone fishing".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 301 JIM BOB :gone fishing".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn unaway_prints_correctly() {
+    let reply = Reply::UnAway {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 305 JIM :You are no longer marked as being away".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn nowaway_prints_correctly() {
+    let reply = Reply::NowAway {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 306 JIM :You have been marked as being away".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn awaynotify_prints_correctly() {
+    let reply = Reply::AwayNotify {
+        client: "JIM!~jim@localhost".to_string(),
+        message: Some("gone fishing".to_string()),
+    };
+    assert_eq!(":JIM!~jim@localhost AWAY :gone fishing", reply.to_string());
+
+    let reply = Reply::AwayNotify {
+        client: "JIM!~jim@localhost".to_string(),
+        message: None,
+    };
+    assert_eq!(":JIM!~jim@localhost AWAY", reply.to_string());
+}
+
+#[test]
+fn errtoomanytargets_prints_correctly() {
+    let reply = Reply::ErrTooManyTargets {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        target: "BOB".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 407 JIM BOB :Too many recipients".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errbadchanmask_prints_correctly() {
+    let reply = Reply::ErrBadChanMask {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        channel: "foo".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 476 JIM foo :Bad Channel Mask".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn youreoper_prints_correctly() {
+    let reply = Reply::YoureOper {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 381 JIM :You are now an IRC operator".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnoprivileges_prints_correctly() {
+    let reply = Reply::ErrNoPrivileges {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 481 JIM :Permission Denied- You're not an IRC operator".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errpasswdmismatch_prints_correctly() {
+    let reply = Reply::ErrPasswdMismatch {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 464 JIM :Password incorrect".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnooperhost_prints_correctly() {
+    let reply = Reply::ErrNoOperHost {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 491 JIM :No O-lines for your host".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn errnoprivs_prints_correctly() {
+    let reply = Reply::ErrNoPrivs {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        privilege: "kill".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 723 JIM kill :Insufficient oper privileges.".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn erryourebannedcreep_prints_correctly() {
+    let reply = Reply::ErrYoureBannedCreep {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        reason: "spamming".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 465 JIM :You are banned from this server- spamming".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn statskline_prints_correctly() {
+    let reply = Reply::StatsKLine {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        host: "*.example.com".to_string(),
+        user: "*".to_string(),
+        reason: "spamming".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 216 JIM K *.example.com * * :spamming".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn statsdline_prints_correctly() {
+    let reply = Reply::StatsDLine {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        ip: "10.0.0.0/8".to_string(),
+        reason: "flooding".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 225 JIM D 10.0.0.0/8 :flooding".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn endofstats_prints_correctly() {
+    let reply = Reply::EndOfStats {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        query: "k".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 219 JIM k :End of /STATS report".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn servernotice_prints_correctly() {
+    let reply = Reply::ServerNotice {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        message: "Added K-Line [*@example.com]".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost NOTICE JIM :Added K-Line [*@example.com]".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn error_prints_correctly() {
+    let reply = Reply::Error {
+        message: "Closing Link: 127.0.0.1 (Killed (BOB (spamming)))".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = "ERROR :Closing Link: 127.0.0.1 (Killed (BOB (spamming)))".to_string();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn rehashing_prints_correctly() {
+    let reply = Reply::Rehashing {
+        server_host: "localhost".to_string(),
+        nick: "JIM".to_string(),
+        config_file: "Settings.toml".to_string(),
+    };
+    let actual = reply.to_string();
+    let expected = ":localhost 382 JIM Settings.toml :Rehashing".to_string();
     assert_eq!(expected, actual);
 }
@@ -1,9 +1,7 @@
-use std::time::Duration;
-
-use chrono::Utc;
 use tokio::{
+    io::AsyncWriteExt,
     net::TcpListener,
-    sync::{broadcast, mpsc, mpsc::Receiver},
+    sync::{broadcast, mpsc, mpsc::Receiver, watch},
 };
 use uuid::Uuid;
 
@@ -11,20 +9,21 @@ use crate::{
     client_listener, client_sender,
     context::ServerContext,
     error::Error::UnableToBindToPort,
+    hostname::lookup_hostname,
     message_handler,
     message_parsing::{Command, Message, ReplySender},
+    replies::Reply,
     result::Result,
     settings::Settings,
+    tags::Tags,
 };
 
-pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Result<()> {
-    let context = ServerContext {
-        start_time: Utc::now(),
-        server_host: settings.host.clone(),
-        version: "0.0.1".to_string(),
-        ping_frequency: Duration::from_secs(settings.ping_frequency_secs),
-        motd_lines: settings.motd_lines.clone(),
-    };
+pub async fn run(
+    settings: &Settings,
+    mut shutdown_receiver: Receiver<()>,
+    mut reload_receiver: Receiver<()>,
+) -> Result<()> {
+    let context = ServerContext::new(settings);
 
     println!("Starting server on {}:{}", settings.host, settings.port);
 
@@ -41,11 +40,14 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
     let (listener_shutdown_sender, _listener_shutdown_receiver) = broadcast::channel(1000);
     let (sender_shutdown_sender, _sender_shutdown_receiver) = broadcast::channel(1000);
 
-    let server_context = context.clone();
+    // the message handler owns the context from here on, every other task is
+    // given a receiver so that they see it change when the settings are reloaded
+    let bans = context.bans.clone();
+    let (context_sender, context_receiver) = watch::channel(context);
 
     let message_handler_task = tokio::spawn(async move {
         if let Err(e) = message_handler::run::<Receiver<Message>>(
-            &server_context,
+            &context_sender,
             &mut message_receiver,
             message_handler_shutdown_receiver,
         )
@@ -56,7 +58,7 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
     });
 
     loop {
-        let (stream, _) = tokio::select! {
+        let (mut stream, addr) = tokio::select! {
             res = listener.accept() => match res {
                 Ok(res) => res,
                 Err(_) => {
@@ -68,9 +70,41 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
                 println!("Server received shutdown signal");
                 break;
             }
+            _ = reload_receiver.recv() => {
+                if let Err(e) = message_sender
+                    .send(Message {
+                        tags: Tags::new(),
+                        source: None,
+                        command: Command::Reload,
+                        connection_id: Uuid::nil(),
+                    })
+                    .await
+                {
+                    println!("Error sending reload message {:?}", e);
+                }
+
+                continue;
+            }
         };
 
-        let server_context = context.clone();
+        // D-lined addresses are turned away before anything else is set up for them
+        let dline = bans.lock().unwrap().find_dline(addr.ip()).cloned();
+
+        if let Some(ban) = dline {
+            println!("Refusing connection from D-lined {}", addr.ip());
+
+            let error = Reply::Error {
+                message: format!("Closing Link: {} (D-lined: {})", addr.ip(), ban.reason),
+            };
+
+            if let Err(e) = stream.write_all(format!("{}\r\n", error).as_bytes()).await {
+                println!("Error writing reply {} {:?}", error, e);
+            }
+
+            continue;
+        }
+
+        let context_receiver = context_receiver.clone();
 
         // pass this around in messages to grab details about this connection/user
         let connection_id = Uuid::new_v4();
@@ -79,23 +113,6 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
         // given to message handler so it can send replies to this client when needed
         let message_handler_reply_sender = reply_sender.clone();
 
-        let addr = stream.peer_addr().ok();
-
-        if let Err(e) = message_sender
-            .send(Message {
-                source: None,
-                command: Command::Connected {
-                    sender: ReplySender(message_handler_reply_sender),
-                    client_ip: addr,
-                },
-                connection_id,
-            })
-            .await
-        {
-            println!("Error sending connection initialization message {:?}", e);
-            break;
-        };
-
         let (mut read_handle, mut write_handle) = stream.into_split();
         let sender_shutdown_receiver = sender_shutdown_sender.subscribe();
 
@@ -132,8 +149,29 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
             }
             */
 
+            // nothing is read from the client until its hostname is known,
+            // so the handler always hears of the connection first
+            let hostname = lookup_hostname(addr.ip()).await;
+
+            if let Err(e) = message_sender
+                .send(Message {
+                    tags: Tags::new(),
+                    source: None,
+                    command: Command::Connected {
+                        sender: ReplySender(message_handler_reply_sender),
+                        client_ip: Some(addr),
+                        hostname,
+                    },
+                    connection_id,
+                })
+                .await
+            {
+                println!("Error sending connection initialization message {:?}", e);
+                return;
+            };
+
             if let Err(e) = client_listener::run(
-                server_context,
+                context_receiver,
                 &connection_id,
                 &mut read_handle,
                 &message_sender,
@@ -149,6 +187,7 @@ pub async fn run(settings: &Settings, mut shutdown_receiver: Receiver<()>) -> Re
             // so that it can clean up and communicate this to other clients
             if let Err(e) = message_sender
                 .send(Message {
+                    tags: Tags::new(),
                     source: None,
                     command: Command::Disconnected,
                     connection_id,
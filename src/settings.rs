@@ -1,18 +1,152 @@
+use argon2::PasswordHash;
 use config::{Config, ConfigError, File};
 use serde_derive::Deserialize;
 
+use crate::{
+    codec::FallbackEncoding, error::Error::InvalidSettings, oper::OperBlock, result::Result,
+};
+
+// where the settings are read from, relative to the working directory
+pub const SETTINGS_FILE: &str = "Settings.toml";
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub host: String,
+    pub network_name: String,
     pub port: u32,
     pub ping_frequency_secs: u64,
+    pub nick_len: usize,
+    pub channel_len: usize,
+    pub topic_len: usize,
+    pub kick_len: usize,
+    pub away_len: usize,
+    pub max_targets: usize,
+    pub max_list_entries: usize,
+    pub invite_timeout_secs: u64,
+    #[serde(default)]
+    pub fallback_encoding: FallbackEncoding,
     pub motd_lines: Vec<String>,
+    pub ban_file: String,
+    #[serde(default)]
+    pub opers: Vec<OperBlock>,
 }
 
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
+    pub fn new() -> std::result::Result<Self, ConfigError> {
         let mut s = Config::new();
-        s.merge(File::with_name("Settings"))?;
+        s.merge(File::with_name(SETTINGS_FILE))?;
         s.try_into()
     }
+
+    // reads and validates the settings, so a bad edit can be caught before
+    // anything starts using it
+    pub fn load() -> Result<Self> {
+        let settings = Settings::new().map_err(|e| InvalidSettings(e.to_string()))?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    // catches what would parse fine but leave the server unusable
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |problem: &str| Err(InvalidSettings(problem.to_string()));
+
+        if self.network_name.is_empty() || self.network_name.contains(' ') {
+            return invalid("network_name must be set and can't contain spaces");
+        }
+
+        if self.ping_frequency_secs == 0 {
+            return invalid("ping_frequency_secs must be more than 0");
+        }
+
+        // the # alone isn't a valid channel name
+        if self.channel_len < 2 {
+            return invalid("channel_len must be at least 2");
+        }
+
+        for (name, value) in [
+            ("nick_len", self.nick_len),
+            ("topic_len", self.topic_len),
+            ("kick_len", self.kick_len),
+            ("away_len", self.away_len),
+            ("max_targets", self.max_targets),
+        ] {
+            if value == 0 {
+                return Err(InvalidSettings(format!("{} must be more than 0", name)));
+            }
+        }
+
+        if self.ban_file.is_empty() {
+            return invalid("ban_file must be set");
+        }
+
+        for oper in &self.opers {
+            if oper.hosts.is_empty() {
+                return Err(InvalidSettings(format!(
+                    "oper {} has no hosts it can be used from",
+                    oper.name
+                )));
+            }
+
+            if PasswordHash::new(&oper.password_hash).is_err() {
+                return Err(InvalidSettings(format!(
+                    "oper {} has a password_hash that isn't an argon2 hash",
+                    oper.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub fn test_settings() -> Settings {
+    Settings {
+        host: "localhost".to_string(),
+        network_name: "LocalNet".to_string(),
+        port: 6667,
+        ping_frequency_secs: 60,
+        nick_len: 30,
+        channel_len: 50,
+        topic_len: 390,
+        kick_len: 255,
+        away_len: 200,
+        max_targets: 4,
+        max_list_entries: 100,
+        invite_timeout_secs: 3600,
+        fallback_encoding: FallbackEncoding::default(),
+        motd_lines: vec![],
+        // somewhere of its own, so tests never read or write a real ban file
+        ban_file: std::env::temp_dir()
+            .join(format!("{}.bans", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string(),
+        opers: vec![],
+    }
+}
+
+#[test]
+fn validate_rejects_unusable_settings() {
+    use crate::oper::test_oper_block;
+
+    let mut settings = test_settings();
+    settings.opers.push(test_oper_block("hunter2"));
+    assert_eq!(Ok(()), settings.validate());
+
+    settings.network_name = "Local Net".to_string();
+    assert!(settings.validate().is_err());
+
+    let mut settings = test_settings();
+    settings.nick_len = 0;
+    assert_eq!(
+        Err(InvalidSettings("nick_len must be more than 0".to_string())),
+        settings.validate()
+    );
+
+    let mut settings = test_settings();
+    let mut oper = test_oper_block("hunter2");
+    oper.password_hash = "hunter2".to_string();
+    settings.opers.push(oper);
+    assert!(settings.validate().is_err());
 }
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use chrono::{SecondsFormat, Utc};
+use uuid::Uuid;
+
+// IRCv3 message tags, kept ordered so they always serialize the same way
+// https://ircv3.net/specs/extensions/message-tags
+pub type Tags = BTreeMap<String, String>;
+
+// Parses the raw tags section of a message, without the leading "@"
+pub fn parse_tags(raw: &str) -> Tags {
+    raw.split(';')
+        .filter(|t| !t.is_empty())
+        .map(|t| match t.split_once('=') {
+            Some((key, value)) => (key.to_string(), unescape_value(value)),
+            None => (t.to_string(), String::new()),
+        })
+        .collect()
+}
+
+// Serializes tags into the form sent on the wire, without the leading "@"
+pub fn serialize_tags(tags: &Tags) -> String {
+    tags.iter()
+        .map(|(key, value)| match value.is_empty() {
+            true => key.to_string(),
+            false => format!("{}={}", key, escape_value(value)),
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+// The tags the server stamps on relayed messages. These are generated once per
+// message and shared between all recipients, so msgid can be used to deduplicate
+pub fn server_tags() -> Tags {
+    let mut tags = Tags::new();
+    tags.insert(
+        "time".to_string(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    );
+    tags.insert("msgid".to_string(), Uuid::new_v4().to_simple().to_string());
+    tags
+}
+
+// Client-only tags are prefixed with "+" and are relayed as-is by the server
+pub fn client_only_tags(tags: &Tags) -> Tags {
+    tags.iter()
+        .filter(|(key, _)| key.starts_with('+'))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// An unknown escape just drops the backslash, and a trailing lone
+// backslash is dropped entirely
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("a\\:b", "a;b" ; "semicolon")]
+    #[test_case("a\\sb", "a b" ; "space")]
+    #[test_case("a\\\\b", "a\\b" ; "backslash")]
+    #[test_case("a\\r\\nb", "a\r\nb" ; "cr_lf")]
+    #[test_case("a\\bc", "abc" ; "unknown_escape")]
+    #[test_case("abc\\", "abc" ; "trailing_backslash")]
+    fn unescape_value_unescapes(raw: &str, expected: &str) {
+        assert_eq!(expected, unescape_value(raw));
+    }
+
+    #[test]
+    fn parse_tags_parses_keys_and_values() {
+        let tags = parse_tags("+example.com/foo=bar\\sbaz;msgid=abc;+typing=active;flag");
+
+        assert_eq!(4, tags.len());
+        assert_eq!("bar baz", tags["+example.com/foo"]);
+        assert_eq!("abc", tags["msgid"]);
+        assert_eq!("active", tags["+typing"]);
+        assert_eq!("", tags["flag"]);
+    }
+
+    #[test]
+    fn serialize_tags_roundtrips() {
+        let raw = "+typing=active;flag;time=a\\sb\\:c\\\\";
+        assert_eq!(raw, serialize_tags(&parse_tags(raw)));
+    }
+
+    #[test]
+    fn server_tags_are_unique_per_message() {
+        let first = server_tags();
+        let second = server_tags();
+
+        assert!(first["time"].ends_with('Z'));
+        assert_ne!(first["msgid"], second["msgid"]);
+    }
+
+    #[test]
+    fn client_only_tags_filters_server_tags() {
+        let tags = client_only_tags(&parse_tags("+typing=active;msgid=abc"));
+
+        assert_eq!(1, tags.len());
+        assert_eq!("active", tags["+typing"]);
+    }
+}
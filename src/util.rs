@@ -1,8 +1,15 @@
+use std::collections::HashMap;
+
 use regex::Regex;
+use uuid::Uuid;
+
+use crate::context::ConnectionContext;
 
+// nicks and hosts are full of characters that mean something in a regex,
+// so only the two wildcards are given their meaning
 pub fn match_mask(input: &str, mask: &str) -> bool {
-    let mut regex = String::from("^");
-    regex.push_str(&mask.replace("*", ".*").replace("?", "."));
+    let mut regex = String::from("(?i)^");
+    regex.push_str(&regex::escape(mask).replace(r"\*", ".*").replace(r"\?", "."));
     regex.push('$');
 
     let re = match Regex::new(&regex) {
@@ -16,22 +23,88 @@ pub fn match_mask(input: &str, mask: &str) -> bool {
     re.is_match(input)
 }
 
+// fills in whatever was left off a nick!user@host mask, so "bob" becomes
+// "bob!*@*" and "bob@host" becomes "*!bob@host"
+pub fn normalize_mask(mask: &str) -> String {
+    let (rest, host) = match mask.rsplit_once('@') {
+        Some((r, h)) => (r, Some(h)),
+        None => (mask, None),
+    };
+
+    match (rest.split_once('!'), host) {
+        (Some((nick, user)), Some(host)) => format!("{}!{}@{}", nick, user, host),
+        (Some((nick, user)), None) => format!("{}!{}@*", nick, user),
+        (None, Some(host)) => format!("*!{}@{}", rest, host),
+        (None, None) => format!("{}!*@*", rest),
+    }
+}
+
+// cuts a string down to at most max_len bytes without splitting a character
+pub fn truncate(s: &str, max_len: usize) -> String {
+    let mut end = s.len().min(max_len);
+
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    s[..end].to_string()
+}
+
+// channel names are compared with ascii casemapping, so every lookup and
+// insert goes through the folded name
+pub fn casefold(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+// nicks are compared case-insensitively, two connections that only
+// differ by case are still the same user as far as clients are concerned
+pub fn find_connection_by_nick<'a>(
+    connections: &'a HashMap<Uuid, ConnectionContext>,
+    nick: &str,
+) -> Option<&'a ConnectionContext> {
+    connections.values().find(|c| match &c.nick {
+        Some(n) => n.eq_ignore_ascii_case(nick),
+        None => false,
+    })
+}
+
 #[test]
 fn match_mask_prefix_matches_no_wildcard_no_match() {
-    assert_eq!(false, match_mask("nick!username@host", "nick"));
+    assert!(!match_mask("nick!username@host", "nick"));
 }
 
 #[test]
 fn match_mask_single_char_wildcard_multi_char_mask_no_match() {
-    assert_eq!(false, match_mask("nick!username@host", "?"));
+    assert!(!match_mask("nick!username@host", "?"));
 }
 
 #[test]
 fn match_mask_wildcard_matches() {
-    assert_eq!(true, match_mask("nick!username@host", "*"));
+    assert!(match_mask("nick!username@host", "*"));
 }
 
 #[test]
 fn match_mask_prefix_with_wildcard_matches() {
-    assert_eq!(true, match_mask("nick!username@host", "nick*"));
+    assert!(match_mask("nick!username@host", "nick*"));
+}
+
+#[test]
+fn match_mask_regex_characters_are_literal() {
+    assert!(match_mask("[bob]!~bob@host.example", "[BOB]!*@*.example"));
+    assert!(!match_mask("bob!~bob@hostxexample", "*@host.example"));
+}
+
+#[test]
+fn normalize_mask_fills_in_missing_parts() {
+    assert_eq!("bob!*@*", normalize_mask("bob"));
+    assert_eq!("*!bob@host", normalize_mask("bob@host"));
+    assert_eq!("bob!~bob@*", normalize_mask("bob!~bob"));
+    assert_eq!("bob!~bob@host", normalize_mask("bob!~bob@host"));
+}
+
+#[test]
+fn truncate_keeps_whole_characters() {
+    assert_eq!("hello", truncate("hello", 10));
+    assert_eq!("hel", truncate("hello", 3));
+    assert_eq!("h", truncate("hé", 2));
 }